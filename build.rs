@@ -0,0 +1,4 @@
+fn main() {
+    // the migrations are embedded, see `database::MIGRATIONS`
+    println!("cargo:rerun-if-changed=migrations");
+}
@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS craft_jobs;
+DROP TABLE IF EXISTS item_thresholds;
+DROP TABLE IF EXISTS item_samples;
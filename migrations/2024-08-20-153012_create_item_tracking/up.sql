@@ -0,0 +1,42 @@
+CREATE TABLE item_samples (
+    id BIGSERIAL PRIMARY KEY,
+    client_id BIGINT NOT NULL,
+    sampled_on TIMESTAMPTZ NOT NULL DEFAULT now(),
+    item_name TEXT NOT NULL,
+    label TEXT NOT NULL,
+    amount BIGINT NOT NULL,
+    craftable BOOL NOT NULL,
+    CONSTRAINT fk_client FOREIGN KEY (client_id) REFERENCES clients (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_item_samples_client_time ON item_samples (client_id, sampled_on);
+CREATE INDEX idx_item_samples_item_time ON item_samples (item_name, sampled_on);
+
+CREATE TABLE item_thresholds (
+    id BIGSERIAL PRIMARY KEY,
+    client_id BIGINT NOT NULL,
+    item_name TEXT NOT NULL,
+    min_amount BIGINT NOT NULL,
+    enabled BOOL NOT NULL DEFAULT TRUE,
+    failure_count INT NOT NULL DEFAULT 0,
+    flagged BOOL NOT NULL DEFAULT FALSE,
+    created_on TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CONSTRAINT unique_threshold UNIQUE (client_id, item_name),
+    CONSTRAINT fk_client FOREIGN KEY (client_id) REFERENCES clients (id) ON DELETE CASCADE
+);
+
+CREATE TABLE craft_jobs (
+    id BIGSERIAL PRIMARY KEY,
+    threshold_id BIGINT,
+    client_id BIGINT NOT NULL,
+    item_name TEXT NOT NULL,
+    amount BIGINT NOT NULL,
+    status TEXT NOT NULL,
+    message TEXT,
+    requested_on TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_on TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CONSTRAINT fk_threshold FOREIGN KEY (threshold_id) REFERENCES item_thresholds (id) ON DELETE SET NULL,
+    CONSTRAINT fk_client FOREIGN KEY (client_id) REFERENCES clients (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_craft_jobs_threshold_status ON craft_jobs (threshold_id, status);
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS telemetry_rollups;
+DROP TABLE IF EXISTS power_samples;
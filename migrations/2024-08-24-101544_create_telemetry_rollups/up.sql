@@ -0,0 +1,27 @@
+CREATE TABLE power_samples (
+    id BIGSERIAL PRIMARY KEY,
+    client_id BIGINT NOT NULL,
+    sampled_on TIMESTAMPTZ NOT NULL DEFAULT now(),
+    stored DOUBLE PRECISION NOT NULL,
+    capacity DOUBLE PRECISION NOT NULL,
+    draw DOUBLE PRECISION NOT NULL,
+    CONSTRAINT fk_client FOREIGN KEY (client_id) REFERENCES clients (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_power_samples_client_time ON power_samples (client_id, sampled_on);
+
+-- min/max/avg of every metric per client, bucketed at each rollup resolution (in seconds)
+CREATE TABLE telemetry_rollups (
+    client_id BIGINT NOT NULL,
+    metric TEXT NOT NULL,
+    resolution INT NOT NULL,
+    bucket TIMESTAMPTZ NOT NULL,
+    min_value DOUBLE PRECISION NOT NULL,
+    max_value DOUBLE PRECISION NOT NULL,
+    avg_value DOUBLE PRECISION NOT NULL,
+    sample_count BIGINT NOT NULL,
+    PRIMARY KEY (client_id, metric, resolution, bucket),
+    CONSTRAINT fk_client FOREIGN KEY (client_id) REFERENCES clients (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_telemetry_rollups_resolution_bucket ON telemetry_rollups (resolution, bucket);
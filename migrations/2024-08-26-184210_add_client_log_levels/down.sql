@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_client_logs_time_level;
+ALTER TABLE client_logs DROP COLUMN IF EXISTS log_level;
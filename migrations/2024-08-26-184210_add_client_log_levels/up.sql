@@ -0,0 +1,3 @@
+ALTER TABLE client_logs ADD COLUMN log_level TEXT NOT NULL DEFAULT 'Info';
+
+CREATE INDEX idx_client_logs_time_level ON client_logs (log_time, log_level);
@@ -0,0 +1,17 @@
+CREATE TABLE client_sessions (
+    id BIGSERIAL PRIMARY KEY,
+    client_id BIGINT NOT NULL,
+    connected_on TIMESTAMPTZ NOT NULL DEFAULT now(),
+    disconnected_on TIMESTAMPTZ,
+    close_reason TEXT,
+    remote_addr TEXT,
+    protocol_version TEXT,
+    bytes_in BIGINT NOT NULL DEFAULT 0,
+    bytes_out BIGINT NOT NULL DEFAULT 0,
+    messages_in BIGINT NOT NULL DEFAULT 0,
+    messages_out BIGINT NOT NULL DEFAULT 0,
+    CONSTRAINT fk_client FOREIGN KEY (client_id) REFERENCES clients (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_client_sessions_client_time ON client_sessions (client_id, connected_on);
+CREATE INDEX idx_client_sessions_open ON client_sessions (disconnected_on) WHERE disconnected_on IS NULL;
@@ -0,0 +1,12 @@
+CREATE TABLE client_metadata_history (
+    id BIGSERIAL PRIMARY KEY,
+    client_id BIGINT NOT NULL,
+    field TEXT NOT NULL,
+    old_value TEXT,
+    new_value TEXT,
+    changed_by TEXT NOT NULL,
+    changed_on TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CONSTRAINT fk_client FOREIGN KEY (client_id) REFERENCES clients (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_client_metadata_history_client ON client_metadata_history (client_id, changed_on);
@@ -0,0 +1,15 @@
+CREATE TABLE queued_commands (
+    id BIGSERIAL PRIMARY KEY,
+    client_id BIGINT NOT NULL,
+    command TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'Pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    queued_on TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_on TIMESTAMPTZ NOT NULL,
+    last_sent_on TIMESTAMPTZ,
+    acked_on TIMESTAMPTZ,
+    CONSTRAINT fk_client FOREIGN KEY (client_id) REFERENCES clients (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_queued_commands_client ON queued_commands (client_id, id);
+CREATE INDEX idx_queued_commands_open ON queued_commands (status) WHERE status IN ('Pending', 'Delivered');
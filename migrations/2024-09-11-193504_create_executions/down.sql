@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS executions;
+ALTER TABLE clients DROP COLUMN IF EXISTS allow_exec;
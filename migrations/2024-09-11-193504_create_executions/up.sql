@@ -0,0 +1,17 @@
+ALTER TABLE clients ADD COLUMN allow_exec BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE executions (
+    id BIGSERIAL PRIMARY KEY,
+    client_id BIGINT NOT NULL,
+    code TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'Running',
+    output TEXT,
+    result TEXT,
+    error TEXT,
+    duration_ms BIGINT,
+    requested_on TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    completed_on TIMESTAMPTZ,
+    CONSTRAINT fk_client FOREIGN KEY (client_id) REFERENCES clients (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_executions_client ON executions (client_id, requested_on DESC);
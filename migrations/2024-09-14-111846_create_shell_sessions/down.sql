@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS shell_events;
+DROP TABLE IF EXISTS shell_sessions;
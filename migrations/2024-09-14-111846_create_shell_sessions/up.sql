@@ -0,0 +1,22 @@
+CREATE TABLE shell_sessions (
+    id BIGSERIAL PRIMARY KEY,
+    client_id BIGINT NOT NULL,
+    started_on TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    ended_on TIMESTAMPTZ,
+    close_reason TEXT,
+    CONSTRAINT fk_client FOREIGN KEY (client_id) REFERENCES clients (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_shell_sessions_client ON shell_sessions (client_id, started_on DESC);
+
+-- every keystroke batch and output chunk of a session, in order, for replay
+CREATE TABLE shell_events (
+    id BIGSERIAL PRIMARY KEY,
+    session_id BIGINT NOT NULL,
+    offset_ms BIGINT NOT NULL,
+    direction TEXT NOT NULL,
+    data TEXT NOT NULL,
+    CONSTRAINT fk_session FOREIGN KEY (session_id) REFERENCES shell_sessions (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_shell_events_session ON shell_events (session_id, id);
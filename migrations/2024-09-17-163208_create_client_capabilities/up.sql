@@ -0,0 +1,11 @@
+-- what each client reported in the Hello of its latest connection
+CREATE TABLE client_capabilities (
+    client_id BIGINT PRIMARY KEY,
+    protocol_version INTEGER NOT NULL,
+    client_build TEXT,
+    components TEXT[] NOT NULL DEFAULT '{}',
+    free_memory BIGINT NOT NULL,
+    total_memory BIGINT NOT NULL,
+    reported_on TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT fk_client FOREIGN KEY (client_id) REFERENCES clients (id) ON DELETE CASCADE
+);
@@ -0,0 +1,5 @@
+DROP TABLE client_updates;
+ALTER TABLE tags DROP COLUMN channel;
+ALTER TABLE clients DROP COLUMN channel;
+DROP TABLE release_files;
+DROP TABLE releases;
@@ -0,0 +1,39 @@
+-- client software releases, `build` is the build of their manifest as reported in Hello
+CREATE TABLE releases (
+    id BIGSERIAL PRIMARY KEY,
+    version TEXT NOT NULL UNIQUE,
+    channel TEXT NOT NULL,
+    build TEXT NOT NULL,
+    notes TEXT,
+    rollout_percent INTEGER NOT NULL DEFAULT 100 CHECK (rollout_percent BETWEEN 0 AND 100),
+    created_on TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_releases_channel ON releases (channel, id DESC);
+CREATE INDEX idx_releases_build ON releases (build);
+
+CREATE TABLE release_files (
+    release_id BIGINT NOT NULL,
+    path TEXT NOT NULL,
+    contents BYTEA NOT NULL,
+    PRIMARY KEY (release_id, path),
+    CONSTRAINT fk_release FOREIGN KEY (release_id) REFERENCES releases (id) ON DELETE CASCADE
+);
+
+-- channel a client follows, unset to follow its tags' or stable
+ALTER TABLE clients ADD COLUMN channel TEXT;
+ALTER TABLE tags ADD COLUMN channel TEXT;
+
+-- every Update sent for a release, completed once the client reports that release's build
+CREATE TABLE client_updates (
+    id BIGSERIAL PRIMARY KEY,
+    client_id BIGINT NOT NULL,
+    release_id BIGINT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'Sent',
+    sent_on TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    completed_on TIMESTAMPTZ,
+    CONSTRAINT fk_client FOREIGN KEY (client_id) REFERENCES clients (id) ON DELETE CASCADE,
+    CONSTRAINT fk_release FOREIGN KEY (release_id) REFERENCES releases (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_client_updates_client ON client_updates (client_id, sent_on DESC);
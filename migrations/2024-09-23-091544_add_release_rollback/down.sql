@@ -0,0 +1,5 @@
+DROP INDEX idx_client_updates_status;
+ALTER TABLE client_updates DROP COLUMN failure;
+ALTER TABLE releases DROP COLUMN halt_reason;
+ALTER TABLE releases DROP COLUMN halted_on;
+ALTER TABLE releases DROP COLUMN max_failure_percent;
@@ -0,0 +1,10 @@
+-- share of a release's updates that may fail before its rollout is halted and its clients rolled back
+ALTER TABLE releases ADD COLUMN max_failure_percent INTEGER NOT NULL DEFAULT 20
+    CHECK (max_failure_percent BETWEEN 0 AND 100);
+ALTER TABLE releases ADD COLUMN halted_on TIMESTAMPTZ;
+ALTER TABLE releases ADD COLUMN halt_reason TEXT;
+
+-- why an update was counted as failed
+ALTER TABLE client_updates ADD COLUMN failure TEXT;
+
+CREATE INDEX idx_client_updates_status ON client_updates (status, release_id);
@@ -0,0 +1,2 @@
+ALTER TABLE clients DROP COLUMN disabled_modules;
+ALTER TABLE clients DROP COLUMN log_level;
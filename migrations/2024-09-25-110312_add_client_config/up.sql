@@ -0,0 +1,3 @@
+-- per client settings of the generated client configuration, see the server's client_config module
+ALTER TABLE clients ADD COLUMN log_level TEXT NOT NULL DEFAULT 'Info';
+ALTER TABLE clients ADD COLUMN disabled_modules TEXT[] NOT NULL DEFAULT '{}';
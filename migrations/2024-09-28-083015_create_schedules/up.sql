@@ -0,0 +1,31 @@
+-- commands sent on a cron schedule, `next_run_on` is when the scheduler fires them next
+CREATE TABLE schedules (
+    id BIGSERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    cron TEXT NOT NULL,
+    selector TEXT NOT NULL,
+    command TEXT NOT NULL,
+    args TEXT NOT NULL DEFAULT '',
+    queue_ttl TEXT,
+    paused BOOLEAN NOT NULL DEFAULT FALSE,
+    next_run_on TIMESTAMPTZ NOT NULL,
+    created_on TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_schedules_due ON schedules (next_run_on) WHERE NOT paused;
+
+-- every time a schedule fired and how its command was delivered
+CREATE TABLE schedule_runs (
+    id BIGSERIAL PRIMARY KEY,
+    schedule_id BIGINT NOT NULL,
+    status TEXT NOT NULL,
+    matched INTEGER NOT NULL DEFAULT 0,
+    sent INTEGER NOT NULL DEFAULT 0,
+    queued INTEGER NOT NULL DEFAULT 0,
+    failed INTEGER NOT NULL DEFAULT 0,
+    error TEXT,
+    started_on TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CONSTRAINT fk_schedule FOREIGN KEY (schedule_id) REFERENCES schedules (id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_schedule_runs_schedule ON schedule_runs (schedule_id, started_on DESC);
@@ -0,0 +1,6 @@
+-- server settings edited on the settings page, missing keys take their defaults
+CREATE TABLE settings (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL,
+    updated_on TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,9 @@
+-- accounts of the people running the server, managed with `site user`
+CREATE TABLE users (
+    id BIGSERIAL PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE,
+    -- argon2 in the PHC string format, salt and parameters included
+    password_hash TEXT NOT NULL,
+    created_on TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    password_changed_on TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
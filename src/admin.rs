@@ -0,0 +1,547 @@
+//! Administration from the command line, e.g. `site client create reactor-1`.
+//!
+//! The commands work on the database directly, so they run without the web UI and whether or not
+//! the server is running. A running server only sees their effect on connected clients when those
+//! reconnect, e.g. a client revoked here keeps its open connection until then.
+//!
+//! `export` writes the settings, tags, clients, schedules and users as JSON, `import` loads such a
+//! file into an empty database keeping the client ids, so selectors like `id:12` in schedules still
+//! pick the same clients. History such as logs, telemetry and schedule runs, queued commands and
+//! releases are not exported.
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+use anyhow::{anyhow, bail};
+use axum::http::HeaderMap;
+use clap::Subcommand;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use time::format_description::well_known::Rfc3339;
+use time::{OffsetDateTime, PrimitiveDateTime, UtcOffset};
+use crate::config::Config;
+use crate::database::models::{metadata_value, Client, ClientLog, ClientMetadata, NewClient, NewSchedule, Schedule, Setting, Tag, User};
+use crate::routes::api::create_api_key;
+use crate::selector::Selector;
+use crate::settings::Settings;
+use crate::{client_config, database, install, scheduler, users};
+
+/// How often `logs tail --follow` checks for new logs.
+const LOG_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Format of `export`, raised when it changes in a way older servers cannot import.
+const EXPORT_VERSION: u32 = 1;
+
+#[derive(Subcommand)]
+pub enum ClientCommand {
+    /// List every client, or the ones a selector such as tag:factory matches
+    List {
+        selector: Option<String>,
+    },
+    /// Create a client and print the authorization code it enrolls with
+    Create {
+        name: String,
+        #[arg(long)]
+        description: Option<String>,
+        #[arg(long)]
+        location: Option<String>,
+        /// Tag the client, may be repeated
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+    },
+    /// Revoke a client's access
+    Revoke {
+        id: i64,
+    },
+    /// Issue a new authorization code and drop the client's API key, it has to enroll again
+    Rotate {
+        id: i64,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum UserCommand {
+    /// Create an account, printing a generated password unless one is given
+    Add {
+        username: String,
+        /// Read the password from the first line of stdin
+        #[arg(long)]
+        password_stdin: bool,
+    },
+    /// Replace an account's password, printing a generated one unless one is given
+    ResetPassword {
+        username: String,
+        /// Read the password from the first line of stdin
+        #[arg(long)]
+        password_stdin: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum LogsCommand {
+    /// Print the latest client logs
+    Tail {
+        /// Only logs of the clients the selector matches when the command starts
+        selector: Option<String>,
+        /// Number of logs to print
+        #[arg(short = 'n', long, default_value_t = 20)]
+        lines: i64,
+        /// Keep printing logs as they arrive
+        #[arg(short, long)]
+        follow: bool,
+    },
+}
+
+pub fn migrate(connection: &mut PgConnection) -> Result<(), anyhow::Error> {
+    let applied = database::run_migrations(connection)?;
+    if applied.is_empty() {
+        println!("The database is up to date.");
+    }
+    for version in applied {
+        println!("Applied {}", version);
+    }
+    Ok(())
+}
+
+pub fn client(connection: &mut PgConnection, config: &Config, command: ClientCommand) -> Result<(), anyhow::Error> {
+    match command {
+        ClientCommand::List { selector } => list_clients(connection, selector.as_deref()),
+        ClientCommand::Create { name, description, location, tags } => {
+            create_client(connection, config, &name, ClientMetadata { description, location }, &tags)
+        }
+        ClientCommand::Revoke { id } => {
+            let client = get_client(connection, id)?;
+            if client.revoked {
+                bail!("Client {} is already revoked.", id);
+            }
+            client.revoke(connection)?;
+            println!("Revoked {} ({}), a connected client keeps its connection until it reconnects.", client.name, id);
+            Ok(())
+        }
+        ClientCommand::Rotate { id } => {
+            let client = get_client(connection, id)?;
+            if client.revoked {
+                bail!("Client {} is revoked.", id);
+            }
+            let auth_key = create_api_key(settings(connection, config)?.auth_code_length);
+            client.reissue_auth(connection, &auth_key)?;
+            println!("Issued {} ({}) a new authorization code, its API key no longer works: {}", client.name, id, auth_key);
+            print_install_command(config, &auth_key);
+            Ok(())
+        }
+    }
+}
+
+fn get_client(connection: &mut PgConnection, id: i64) -> Result<Client, anyhow::Error> {
+    Client::get_by_id(connection, id)?.ok_or(anyhow!("No client with id {}.", id))
+}
+
+/// The stored settings with the configuration's overrides, as the server would use them.
+fn settings(connection: &mut PgConnection, config: &Config) -> Result<Settings, anyhow::Error> {
+    Ok(Settings::load(connection)?.overridden(config))
+}
+
+fn print_install_command(config: &Config, auth_code: &str) {
+    match client_config::server_url(config.public_url.as_deref(), &HeaderMap::new()) {
+        Some(server_url) => println!("Install and enroll the computer with:\n  {}", install::command(&server_url, Some(auth_code))),
+        None => println!("Set public_url to print the install command."),
+    }
+}
+
+fn list_clients(connection: &mut PgConnection, selector: Option<&str>) -> Result<(), anyhow::Error> {
+    let mut clients = match selector {
+        Some(selector) => Selector::parse(selector).map_err(|e| anyhow!(e))?.resolve(connection)?,
+        None => Client::get_all(connection)?,
+    };
+    clients.sort_by_key(|client| *client.id());
+    let mut tags: HashMap<i64, Vec<String>> = HashMap::new();
+    for (client, tag) in Tag::get_assignments(connection)? {
+        tags.entry(client).or_default().push(tag);
+    }
+
+    println!("{:>6}  {:<24}  {:<10}  {:<20}  TAGS", "ID", "NAME", "STATUS", "LAST SEEN");
+    for client in clients {
+        let status = if client.revoked { "Revoked" } else { client.status.as_str() };
+        let accessed_on = client.accessed_on.map(|accessed_on| format_time(accessed_on.assume_utc()));
+        println!(
+            "{:>6}  {:<24}  {:<10}  {:<20}  {}",
+            client.id(),
+            client.name,
+            status,
+            accessed_on.as_deref().unwrap_or("Never"),
+            tags.get(client.id()).map(|tags| tags.join(", ")).unwrap_or_default(),
+        );
+    }
+    Ok(())
+}
+
+fn create_client(
+    connection: &mut PgConnection,
+    config: &Config,
+    name: &str,
+    metadata: ClientMetadata,
+    tags: &[String],
+) -> Result<(), anyhow::Error> {
+    let name = name.trim();
+    if name.is_empty() {
+        bail!("Name must not be empty.");
+    }
+    metadata.validate().map_err(|errors| anyhow!(errors.join(" ")))?;
+    let tags = tags.iter().map(|tag| Tag::normalize(tag)).collect::<Result<Vec<_>, _>>().map_err(|e| anyhow!(e))?;
+
+    let auth_key = create_api_key(settings(connection, config)?.auth_code_length);
+    let mut client = NewClient::new(name, &auth_key);
+    client.description = metadata.description.as_deref().and_then(metadata_value);
+    client.location = metadata.location.as_deref().and_then(metadata_value);
+    let id = connection.transaction(|connection| {
+        let id = client.create(connection)?;
+        for tag in &tags {
+            Tag::add_to_client(connection, id, tag)?;
+        }
+        Ok::<_, anyhow::Error>(id)
+    })?;
+
+    println!("Created {} ({}) with authorization code: {}", name, id, auth_key);
+    print_install_command(config, &auth_key);
+    Ok(())
+}
+
+pub fn user(connection: &mut PgConnection, command: UserCommand) -> Result<(), anyhow::Error> {
+    match command {
+        UserCommand::Add { username, password_stdin } => {
+            let username = users::normalize_username(&username).map_err(|e| anyhow!(e))?;
+            if User::get_by_username(connection, &username)?.is_some() {
+                bail!("User {} already exists.", username);
+            }
+            let (password, generated) = password(password_stdin)?;
+            User::create(connection, &username, &users::hash_password(&password)?)?;
+            match generated {
+                true => println!("Created user {} with password: {}", username, password),
+                false => println!("Created user {}.", username),
+            }
+        }
+        UserCommand::ResetPassword { username, password_stdin } => {
+            let username = users::normalize_username(&username).map_err(|e| anyhow!(e))?;
+            let user = User::get_by_username(connection, &username)?.ok_or(anyhow!("No user {}.", username))?;
+            let (password, generated) = password(password_stdin)?;
+            user.set_password_hash(connection, &users::hash_password(&password)?)?;
+            match generated {
+                true => println!("Reset the password of {} to: {}", username, password),
+                false => println!("Reset the password of {}.", username),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The password read from stdin, or a generated one, and whether it was generated.
+fn password(from_stdin: bool) -> Result<(String, bool), anyhow::Error> {
+    if !from_stdin {
+        return Ok((users::generate_password(), true));
+    }
+    let mut line = String::new();
+    std::io::stdin().lock().read_line(&mut line)?;
+    let password = line.trim_end_matches(['\r', '\n']).to_string();
+    users::check_password(&password).map_err(|e| anyhow!(e))?;
+    Ok((password, false))
+}
+
+pub fn logs(connection: &mut PgConnection, command: LogsCommand) -> Result<(), anyhow::Error> {
+    let LogsCommand::Tail { selector, lines, follow } = command;
+    let clients = selector
+        .map(|selector| -> Result<Vec<i64>, anyhow::Error> {
+            let clients = Selector::parse(&selector).map_err(|e| anyhow!(e))?.resolve(connection)?;
+            Ok(clients.iter().map(|client| *client.id()).collect())
+        })
+        .transpose()?;
+    let mut names = client_names(connection)?;
+
+    let mut logs = ClientLog::get_recent(connection, clients.as_deref(), lines)?;
+    logs.reverse();
+    let mut last = 0;
+    loop {
+        for log in logs {
+            if !names.contains_key(&log.client_id) {
+                names = client_names(connection)?;
+            }
+            let name = names.get(&log.client_id).map(String::as_str).unwrap_or("Unknown");
+            println!("{} {:<7} {} ({}): {}", format_time(log.log_time), log.log_level, name, log.client_id, log.log_message);
+            last = log.id;
+        }
+        if !follow {
+            return Ok(());
+        }
+        std::io::stdout().flush()?;
+        std::thread::sleep(LOG_POLL_INTERVAL);
+        if last == 0 {
+            // nothing was logged yet, start from the newest log once there is one
+            logs = ClientLog::get_recent(connection, clients.as_deref(), 1)?;
+        } else {
+            logs = ClientLog::get_after(connection, clients.as_deref(), last)?;
+        }
+    }
+}
+
+fn client_names(connection: &mut PgConnection) -> Result<HashMap<i64, String>, anyhow::Error> {
+    Ok(Client::get_all(connection)?.into_iter().map(|client| (*client.id(), client.name)).collect())
+}
+
+/// UTC to the second, e.g. `2024-10-01T19:04:22Z`.
+fn format_time(time: OffsetDateTime) -> String {
+    let time = time.to_offset(UtcOffset::UTC);
+    time.replace_nanosecond(0).unwrap_or(time).format(&Rfc3339).unwrap_or_else(|_| time.to_string())
+}
+
+#[derive(Serialize, Deserialize)]
+struct Export {
+    version: u32,
+    #[serde(with = "time::serde::rfc3339")]
+    exported_on: OffsetDateTime,
+    /// Stored settings by key, see `crate::settings`.
+    settings: BTreeMap<String, String>,
+    tags: Vec<ExportedTag>,
+    clients: Vec<ExportedClient>,
+    schedules: Vec<ExportedSchedule>,
+    #[serde(default)]
+    users: Vec<ExportedUser>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExportedTag {
+    name: String,
+    channel: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExportedClient {
+    id: i64,
+    name: String,
+    status: String,
+    description: Option<String>,
+    location: Option<String>,
+    revoked: bool,
+    auth_key: String,
+    api_key: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    created_on: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    accessed_on: Option<OffsetDateTime>,
+    allow_exec: bool,
+    channel: Option<String>,
+    log_level: String,
+    disabled_modules: Vec<String>,
+    tags: Vec<String>,
+}
+
+/// Imported schedules get a new id and their next run is worked out again.
+#[derive(Serialize, Deserialize)]
+struct ExportedSchedule {
+    name: String,
+    cron: String,
+    selector: String,
+    command: String,
+    args: String,
+    queue_ttl: Option<String>,
+    paused: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExportedUser {
+    username: String,
+    password_hash: String,
+    #[serde(with = "time::serde::rfc3339")]
+    created_on: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    password_changed_on: OffsetDateTime,
+}
+
+/// Write the export to `path`, or to stdout without one.
+pub fn export(connection: &mut PgConnection, path: Option<&Path>) -> Result<(), anyhow::Error> {
+    let export = connection.transaction(|connection| {
+        let mut tags: HashMap<i64, Vec<String>> = HashMap::new();
+        for (client, tag) in Tag::get_assignments(connection)? {
+            tags.entry(client).or_default().push(tag);
+        }
+        let mut clients = Client::get_all(connection)?;
+        clients.sort_by_key(|client| *client.id());
+
+        Ok::<_, anyhow::Error>(Export {
+            version: EXPORT_VERSION,
+            exported_on: OffsetDateTime::now_utc(),
+            settings: Setting::get_all(connection)?.into_iter().map(|setting| (setting.key, setting.value)).collect(),
+            tags: Tag::get_all(connection)?
+                .into_iter()
+                .map(|tag| ExportedTag { name: tag.name, channel: tag.channel })
+                .collect(),
+            clients: clients
+                .into_iter()
+                .map(|client| ExportedClient {
+                    id: *client.id(),
+                    tags: tags.remove(client.id()).unwrap_or_default(),
+                    name: client.name,
+                    status: client.status,
+                    description: client.description,
+                    location: client.location,
+                    revoked: client.revoked,
+                    auth_key: client.auth_key,
+                    api_key: client.api_key,
+                    created_on: client.created_on.assume_utc(),
+                    accessed_on: client.accessed_on.map(PrimitiveDateTime::assume_utc),
+                    allow_exec: client.allow_exec,
+                    channel: client.channel,
+                    log_level: client.log_level,
+                    disabled_modules: client.disabled_modules.into_iter().flatten().collect(),
+                })
+                .collect(),
+            schedules: Schedule::get_all(connection)?
+                .into_iter()
+                .map(|schedule| ExportedSchedule {
+                    name: schedule.name,
+                    cron: schedule.cron,
+                    selector: schedule.selector,
+                    command: schedule.command,
+                    args: schedule.args,
+                    queue_ttl: schedule.queue_ttl,
+                    paused: schedule.paused,
+                })
+                .collect(),
+            users: User::get_all(connection)?
+                .into_iter()
+                .map(|user| ExportedUser {
+                    username: user.username,
+                    password_hash: user.password_hash,
+                    created_on: user.created_on,
+                    password_changed_on: user.password_changed_on,
+                })
+                .collect(),
+        })
+    })?;
+
+    match path {
+        Some(path) => {
+            let file = File::create(path).map_err(|e| anyhow!("Failed to create {}: {}", path.display(), e))?;
+            let mut writer = BufWriter::new(file);
+            serde_json::to_writer_pretty(&mut writer, &export)?;
+            writer.flush()?;
+            println!(
+                "Exported {} settings, {} tags, {} clients, {} schedules and {} users to {}.",
+                export.settings.len(), export.tags.len(), export.clients.len(), export.schedules.len(),
+                export.users.len(), path.display(),
+            );
+        }
+        None => {
+            let mut stdout = std::io::stdout().lock();
+            serde_json::to_writer_pretty(&mut stdout, &export)?;
+            writeln!(stdout)?;
+        }
+    }
+    Ok(())
+}
+
+/// Load an export into a database without any clients, tags, schedules or users, all of it or
+/// nothing.
+pub fn import(connection: &mut PgConnection, path: &Path) -> Result<(), anyhow::Error> {
+    use crate::database::schema::{clients, schedules, tags, users};
+
+    let file = File::open(path).map_err(|e| anyhow!("Failed to open {}: {}", path.display(), e))?;
+    let export: Export = serde_json::from_reader(BufReader::new(file))
+        .map_err(|e| anyhow!("Invalid export {}: {}", path.display(), e))?;
+    if export.version > EXPORT_VERSION {
+        bail!("The export has version {}, this server reads up to version {}.", export.version, EXPORT_VERSION);
+    }
+    let settings: HashMap<String, String> = export.settings.clone().into_iter().collect();
+    Settings::default().update(&settings).map_err(|errors| anyhow!(errors.join(" ")))?;
+
+    connection.transaction(|connection| {
+        let existing: i64 = clients::table.count().get_result(connection)?;
+        let existing = existing
+            + tags::table.count().get_result::<i64>(connection)?
+            + schedules::table.count().get_result::<i64>(connection)?
+            + users::table.count().get_result::<i64>(connection)?;
+        if existing > 0 {
+            bail!("The database already has clients, tags, schedules or users, import into an empty one.");
+        }
+
+        let values: Vec<(&str, String)> = export.settings.iter().map(|(key, value)| (key.as_str(), value.clone())).collect();
+        Setting::set_all(connection, &values)?;
+
+        for tag in &export.tags {
+            let name = Tag::normalize(&tag.name).map_err(|e| anyhow!(e))?;
+            diesel::insert_into(tags::table)
+                .values((tags::name.eq(name), tags::channel.eq(&tag.channel)))
+                .execute(connection)?;
+        }
+
+        for client in &export.clients {
+            let disabled_modules: Vec<Option<String>> = client.disabled_modules.iter().cloned().map(Some).collect();
+            diesel::insert_into(clients::table)
+                .values((
+                    clients::id.eq(client.id),
+                    clients::name.eq(&client.name),
+                    clients::status.eq(&client.status),
+                    clients::description.eq(&client.description),
+                    clients::location.eq(&client.location),
+                    clients::revoked.eq(client.revoked),
+                    clients::auth_key.eq(&client.auth_key),
+                    clients::api_key.eq(&client.api_key),
+                    clients::created_on.eq(utc(client.created_on)),
+                    clients::accessed_on.eq(client.accessed_on.map(utc)),
+                    clients::allow_exec.eq(client.allow_exec),
+                    clients::channel.eq(&client.channel),
+                    clients::log_level.eq(&client.log_level),
+                    clients::disabled_modules.eq(disabled_modules),
+                ))
+                .execute(connection)?;
+            for tag in &client.tags {
+                Tag::add_to_client(connection, client.id, &Tag::normalize(tag).map_err(|e| anyhow!(e))?)?;
+            }
+        }
+        // the ids were given, so new clients have to continue after the highest one
+        diesel::sql_query(
+            "SELECT setval(pg_get_serial_sequence('clients', 'id'), (SELECT COALESCE(MAX(id), 0) + 1 FROM clients), false)",
+        ).execute(connection)?;
+
+        let now = OffsetDateTime::now_utc();
+        for schedule in &export.schedules {
+            let next_run_on = scheduler::next_run(&schedule.cron, now)
+                .map_err(|e| anyhow!("Schedule {}: {}", schedule.name, e))?;
+            let id = Schedule::create(connection, &NewSchedule {
+                name: schedule.name.clone(),
+                cron: schedule.cron.clone(),
+                selector: schedule.selector.clone(),
+                command: schedule.command.clone(),
+                args: schedule.args.clone(),
+                queue_ttl: schedule.queue_ttl.clone(),
+                next_run_on,
+            })?;
+            if schedule.paused {
+                Schedule::set_paused(connection, id, true, next_run_on)?;
+            }
+        }
+
+        for user in &export.users {
+            diesel::insert_into(users::table)
+                .values((
+                    users::username.eq(&user.username),
+                    users::password_hash.eq(&user.password_hash),
+                    users::created_on.eq(user.created_on),
+                    users::password_changed_on.eq(user.password_changed_on),
+                ))
+                .execute(connection)?;
+        }
+        Ok(())
+    })?;
+
+    println!(
+        "Imported {} settings, {} tags, {} clients, {} schedules and {} users from {}.",
+        export.settings.len(), export.tags.len(), export.clients.len(), export.schedules.len(),
+        export.users.len(), path.display(),
+    );
+    Ok(())
+}
+
+/// A UTC timestamp as stored in `timestamp` columns.
+fn utc(time: OffsetDateTime) -> PrimitiveDateTime {
+    let time = time.to_offset(UtcOffset::UTC);
+    PrimitiveDateTime::new(time.date(), time.time())
+}
@@ -0,0 +1,142 @@
+//! Static files: the web assets and the Lua client bundle.
+//!
+//! Everything under `static` is embedded in the server. When a static directory is configured,
+//! files are read from there on every request instead, so editing them needs no recompile, and
+//! files missing from it fall back to the embedded ones. The client bundle is taken from the
+//! directory's `client` folder as a whole when it has one. Its manifest is rebuilt whenever a file
+//! changes, and connected clients that ran the previous bundle are sent `Update`.
+use std::borrow::Cow;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use crate::{releases, AppState};
+use crate::manifest::{embedded_files, sha256_hex, Manifest, CLIENT_DIR};
+use crate::routes::ASSETS_DIR;
+use tracing::{error, info};
+
+/// How often the static directory is checked for changes to the client bundle.
+pub const RELOAD_INTERVAL: Duration = Duration::from_secs(2);
+
+pub struct Assets {
+    dir: Option<PathBuf>,
+    bundle: RwLock<Arc<Manifest>>,
+}
+
+impl Assets {
+    pub fn new(dir: Option<PathBuf>) -> Assets {
+        let bundle = RwLock::new(Arc::new(bundle_manifest(dir.as_deref())));
+        Assets { dir, bundle }
+    }
+
+    /// The configured static directory, `None` when only the embedded files are served.
+    pub fn dir(&self) -> Option<&Path> {
+        self.dir.as_deref()
+    }
+
+    /// Contents of a static file by its path below `/static`.
+    pub fn read(&self, path: &str) -> Option<Cow<'static, [u8]>> {
+        let relative = relative_path(path)?;
+        if let Some(dir) = &self.dir {
+            if let Ok(contents) = std::fs::read(dir.join(&relative)) {
+                return Some(Cow::Owned(contents));
+            }
+        }
+        ASSETS_DIR.get_file(&relative).map(|file| Cow::Borrowed(file.contents()))
+    }
+
+    /// Files of the client bundle as `(install path, contents)`.
+    pub fn bundle_files(&self) -> Vec<(String, Cow<'static, [u8]>)> {
+        bundle_files(self.dir.as_deref())
+    }
+
+    /// Manifest of the current client bundle.
+    pub fn manifest(&self) -> Arc<Manifest> {
+        self.bundle.read().unwrap().clone()
+    }
+
+    /// Rebuild the bundle manifest, returns the previous one if the bundle changed.
+    pub fn reload(&self) -> Option<Arc<Manifest>> {
+        let manifest = bundle_manifest(self.dir.as_deref());
+        let mut bundle = self.bundle.write().unwrap();
+        if bundle.build == manifest.build {
+            return None;
+        }
+        Some(std::mem::replace(&mut *bundle, Arc::new(manifest)))
+    }
+}
+
+fn bundle_files(dir: Option<&Path>) -> Vec<(String, Cow<'static, [u8]>)> {
+    let Some(client_dir) = dir.map(|dir| dir.join(CLIENT_DIR)).filter(|dir| dir.is_dir()) else {
+        return embedded_files()
+            .into_iter()
+            .map(|(path, contents)| (path, Cow::Borrowed(contents)))
+            .collect();
+    };
+    let mut files = Vec::new();
+    collect_files(&client_dir, &client_dir, &mut files);
+    files
+}
+
+fn bundle_manifest(dir: Option<&Path>) -> Manifest {
+    let files = bundle_files(dir);
+    Manifest::from_files(
+        env!("CARGO_PKG_VERSION"),
+        &format!("/static/{}", CLIENT_DIR),
+        files.iter().map(|(path, contents)| (path.clone(), contents.as_ref())),
+    )
+}
+
+/// Path below the static root with anything that could leave it rejected.
+fn relative_path(path: &str) -> Option<PathBuf> {
+    let path = Path::new(path.trim_start_matches('/'));
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_)))
+        .then(|| path.to_path_buf())
+}
+
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<(String, Cow<'static, [u8]>)>) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(root, &path, files);
+        } else if let (Ok(relative), Ok(contents)) = (path.strip_prefix(root), std::fs::read(&path)) {
+            let relative = relative.to_string_lossy().replace('\\', "/");
+            files.push((format!("/{}", relative), Cow::Owned(contents)));
+        }
+    }
+}
+
+/// Strong validator of a static file for `ETag` and `If-None-Match`.
+pub fn etag(contents: &[u8]) -> String {
+    format!("\"{}\"", sha256_hex(contents))
+}
+
+/// Whether an `If-None-Match` header value matches the file's tag.
+pub fn matches_etag(if_none_match: &str, tag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|candidate| candidate.trim().trim_start_matches("W/"))
+        .any(|candidate| candidate == "*" || candidate == tag)
+}
+
+/// Watch the static directory, if there is one, for changes to the client bundle.
+pub async fn reload_task(state: Arc<AppState>) {
+    if state.assets.dir().is_none() {
+        return;
+    }
+    let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+    loop {
+        interval.tick().await;
+        let reload_state = state.clone();
+        let reloaded = tokio::task::spawn_blocking(move || reload_state.assets.reload()).await;
+        let Ok(Some(previous)) = reloaded else { continue };
+
+        let current = state.assets.manifest();
+        info!("Client bundle changed, build {} is now {}", &previous.build[..12], &current.build[..12]);
+        match releases::bundle_changed(&state, &previous.build).await {
+            Ok(updated) => info!("Sent Update to {} clients on the previous bundle", updated),
+            Err(e) => error!("Failed to update clients to the new bundle: {}", e),
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! Configuration file generated for each client, so the Lua files hold no server addresses.
+//!
+//! Served at `/api/config` to clients presenting their API key, as a Lua chunk returning a table:
+//!
+//! ```text
+//! return {
+//!     server_url = "http://192.168.1.124:3000",
+//!     ws_address = "ws://192.168.1.124",
+//!     ws_port = 3000,
+//!     ws_path = "/api/ws",
+//!     reconnect_timeout = 30,
+//!     log_level = "Info",
+//!     modules = {"telemetry", "items", "screen"},
+//! }
+//! ```
+//!
+//! The app downloads it to `/etc/client.cfg` on every start and falls back to the saved copy while
+//! the server cannot be reached. Addresses come from the server's public URL, or the address the
+//! client reached the server at when none is set, while the log level and modules are per client.
+use std::time::Duration;
+use http::{HeaderMap, Uri};
+use http::header::HOST;
+use crate::database::models::Client;
+
+pub const WS_PATH: &str = "/api/ws";
+/// How long a client waits before reconnecting after its connection failed.
+pub const RECONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Optional parts of the client, each running in its own thread.
+pub const MODULES: [&str; 3] = ["telemetry", "items", "screen"];
+
+pub struct ClientConfig {
+    pub server_url: String,
+    pub ws_address: String,
+    pub ws_port: u16,
+    pub ws_path: String,
+    pub reconnect_timeout: Duration,
+    pub log_level: String,
+    pub modules: Vec<String>,
+}
+
+impl ClientConfig {
+    /// Configuration of a client reaching the server at `server_url`.
+    pub fn for_client(server_url: &str, client: &Client) -> Result<ClientConfig, anyhow::Error> {
+        let uri: Uri = server_url.parse()?;
+        let secure = uri.scheme_str() == Some("https");
+        let host = uri.host().ok_or(anyhow::anyhow!("Server URL {} has no host", server_url))?;
+        let disabled: Vec<&str> = client.disabled_modules.iter().flatten().map(String::as_str).collect();
+
+        Ok(ClientConfig {
+            server_url: server_url.trim_end_matches('/').to_string(),
+            ws_address: format!("{}://{}", if secure { "wss" } else { "ws" }, host),
+            ws_port: uri.port_u16().unwrap_or(if secure { 443 } else { 80 }),
+            ws_path: WS_PATH.to_string(),
+            reconnect_timeout: RECONNECT_TIMEOUT,
+            log_level: client.log_level.clone(),
+            modules: MODULES
+                .iter()
+                .filter(|module| !disabled.contains(module))
+                .map(|module| module.to_string())
+                .collect(),
+        })
+    }
+
+    /// The configuration as the Lua chunk clients load.
+    pub fn to_lua(&self) -> String {
+        let modules: Vec<String> = self.modules.iter().map(|module| lua_string(module)).collect();
+        format!(
+            "-- generated by the server, replaced whenever the client starts\n\
+             return {{\n    \
+                 server_url = {},\n    \
+                 ws_address = {},\n    \
+                 ws_port = {},\n    \
+                 ws_path = {},\n    \
+                 reconnect_timeout = {},\n    \
+                 log_level = {},\n    \
+                 modules = {{{}}},\n\
+             }}\n",
+            lua_string(&self.server_url),
+            lua_string(&self.ws_address),
+            self.ws_port,
+            lua_string(&self.ws_path),
+            self.reconnect_timeout.as_secs(),
+            lua_string(&self.log_level),
+            modules.join(", "),
+        )
+    }
+}
+
+/// URL clients reach the server at, the configured public URL or else the `Host` the request was
+/// sent to.
+pub fn server_url(public_url: Option<&str>, headers: &HeaderMap) -> Option<String> {
+    if let Some(public_url) = public_url {
+        return Some(public_url.trim_end_matches('/').to_string());
+    }
+    let host = headers.get(HOST)?.to_str().ok()?;
+    Some(format!("http://{}", host))
+}
+
+/// A quoted Lua string literal.
+pub fn lua_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
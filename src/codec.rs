@@ -0,0 +1,140 @@
+//! Compact binary frames for high-volume payloads such as inventory snapshots and screen buffers.
+//!
+//! A client opts in by offering one of `PROTOCOLS` as its `Sec-WebSocket-Protocol`, the server
+//! picks the first it supports and from then on accepts binary frames in this format alongside
+//! the usual text commands. Only clients send compact frames, the server always answers in text.
+//! All numbers are big endian:
+//!
+//! ```text
+//! u8  flags        bit 0 set when the rest of the frame is zlib compressed
+//! one or more messages, each a u32 length followed by that many bytes:
+//! u8  type         1..=8 client commands, 0x80 a screen frame
+//! client command:  u8 1 when a payload follows and 0 otherwise, then the payload as UTF-8
+//! screen frame:    as described in the screen module
+//! ```
+//!
+//! Compression is only accepted on connections that negotiated `oc-compact+deflate`, which
+//! clients offer when they have a data card to deflate with.
+use std::io::Read;
+use anyhow::anyhow;
+use flate2::read::ZlibDecoder;
+use crate::routes::api::ClientWSCommand;
+use crate::screen::ScreenFrame;
+
+pub const COMPACT: &str = "oc-compact";
+pub const COMPACT_DEFLATE: &str = "oc-compact+deflate";
+/// Subprotocols the server accepts, most preferred first.
+pub const PROTOCOLS: [&str; 2] = [COMPACT_DEFLATE, COMPACT];
+
+pub const COMPRESSED: u8 = 0x01;
+pub const SCREEN_FRAME: u8 = 0x80;
+/// Upper bound on a frame after decompression.
+pub const MAX_FRAME_SIZE: usize = 4 * 1024 * 1024;
+
+/// One message inside a compact frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WireMessage {
+    Client(ClientWSCommand),
+    Screen(ScreenFrame),
+}
+
+fn client_command(tag: u8, payload: Option<String>) -> Option<ClientWSCommand> {
+    match tag {
+        1 => Some(ClientWSCommand::Log(payload)),
+        2 => Some(ClientWSCommand::Response(payload)),
+        3 => Some(ClientWSCommand::Discord(payload)),
+        4 => Some(ClientWSCommand::Items(payload)),
+        5 => Some(ClientWSCommand::Power(payload)),
+        6 => Some(ClientWSCommand::Metadata(payload)),
+        7 => Some(ClientWSCommand::Ack(payload)),
+        8 => Some(ClientWSCommand::Shell(payload)),
+        _ => None,
+    }
+}
+
+fn utf8(data: &[u8]) -> Result<String, anyhow::Error> {
+    Ok(String::from_utf8(data.to_vec())?)
+}
+
+impl WireMessage {
+    fn decode(data: &[u8]) -> Result<WireMessage, anyhow::Error> {
+        let (&tag, rest) = data.split_first().ok_or(anyhow!("Empty message."))?;
+        match tag {
+            SCREEN_FRAME => Ok(WireMessage::Screen(ScreenFrame::decode(rest)?)),
+            _ => {
+                let payload = match rest.split_first() {
+                    Some((0, [])) => None,
+                    Some((1, payload)) => Some(utf8(payload)?),
+                    _ => return Err(anyhow!("Invalid payload marker for message type {}", tag)),
+                };
+                let command = client_command(tag, payload).ok_or(anyhow!("Unknown message type {}", tag))?;
+                Ok(WireMessage::Client(command))
+            }
+        }
+    }
+}
+
+/// How binary frames of a connection are read and written, decided by its subprotocol.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Codec {
+    pub compact: bool,
+    pub compression: bool,
+}
+
+impl Codec {
+    pub fn negotiate(protocol: Option<&str>) -> Codec {
+        match protocol {
+            Some(COMPACT_DEFLATE) => Codec { compact: true, compression: true },
+            Some(COMPACT) => Codec { compact: true, compression: false },
+            _ => Codec::default(),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match (self.compact, self.compression) {
+            (true, true) => COMPACT_DEFLATE,
+            (true, false) => COMPACT,
+            _ => "text",
+        }
+    }
+
+    pub fn decode(&self, frame: &[u8]) -> Result<Vec<WireMessage>, anyhow::Error> {
+        if !self.compact {
+            return Err(anyhow!("No binary codec was negotiated."));
+        }
+        let (&flags, body) = frame.split_first().ok_or(anyhow!("Empty frame."))?;
+        let inflated;
+        let mut body = if flags & COMPRESSED == 0 {
+            body
+        } else if self.compression {
+            let mut data = Vec::new();
+            ZlibDecoder::new(body).take(MAX_FRAME_SIZE as u64 + 1).read_to_end(&mut data)?;
+            if data.len() > MAX_FRAME_SIZE {
+                return Err(anyhow!("Frame is larger than {} bytes.", MAX_FRAME_SIZE));
+            }
+            inflated = data;
+            &inflated[..]
+        } else {
+            return Err(anyhow!("Compression was not negotiated."));
+        };
+
+        let mut messages = Vec::new();
+        while !body.is_empty() {
+            if body.len() < 4 {
+                return Err(anyhow!("Frame is truncated."));
+            }
+            let (length, rest) = body.split_at(4);
+            let length = u32::from_be_bytes(length.try_into()?) as usize;
+            if rest.len() < length {
+                return Err(anyhow!("Frame is truncated."));
+            }
+            let (message, rest) = rest.split_at(length);
+            messages.push(WireMessage::decode(message)?);
+            body = rest;
+        }
+        if messages.is_empty() {
+            return Err(anyhow!("Frame has no messages."));
+        }
+        Ok(messages)
+    }
+}
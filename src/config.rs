@@ -0,0 +1,292 @@
+//! Server configuration, layered from a TOML file, environment variables and command line flags.
+//!
+//! Each layer overrides the one before it: the defaults, the file, the environment and the flags.
+//! The file is `site.toml` in the working directory when there is one, or the one given with
+//! `--config`, see `site.example.toml` for its keys. The environment variables and flags are named
+//! after the same keys, e.g. `DATABASE_URL` and `--database-url` for `database_url`.
+//!
+//! The bind address and request timeout are also settings, see `crate::settings`. Configuring them
+//! here overrides the settings page.
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use clap::Args;
+use serde::Deserialize;
+use crate::parse_duration;
+
+/// Read when no file is given and it exists.
+pub const DEFAULT_CONFIG_FILE: &str = "site.toml";
+const DEFAULT_POOL_SIZE: u32 = 10;
+const MAX_POOL_SIZE: u32 = 100;
+
+/// The command line and environment layers, unparsed so every problem can be reported at once.
+#[derive(Args, Debug, Default)]
+pub struct ConfigArgs {
+    /// TOML configuration file [default: site.toml if it exists]
+    #[arg(long, env = "SITE_CONFIG", global = true)]
+    pub config: Option<PathBuf>,
+    /// Address to listen on, e.g. 0.0.0.0:3000, overrides the setting
+    #[arg(long, env = "BIND_ADDRESS", global = true)]
+    pub bind_address: Option<String>,
+    #[arg(long, env = "DATABASE_URL", global = true)]
+    pub database_url: Option<String>,
+    /// Maximum number of database connections
+    #[arg(long, env = "POOL_SIZE", global = true)]
+    pub pool_size: Option<String>,
+    /// How long a request may take, e.g. 10s, overrides the setting
+    #[arg(long, env = "REQUEST_TIMEOUT", global = true)]
+    pub request_timeout: Option<String>,
+    /// full, compact or json
+    #[arg(long, env = "LOG_FORMAT", global = true)]
+    pub log_format: Option<String>,
+    /// Serve static files and the client bundle from this directory instead of the embedded ones
+    #[arg(long, env = "STATIC_DIR", global = true)]
+    pub static_dir: Option<PathBuf>,
+    /// URL clients reach the server at, e.g. http://192.168.1.124:3000
+    #[arg(long, env = "PUBLIC_URL", global = true)]
+    pub public_url: Option<String>,
+}
+
+/// The configuration file, every key is optional.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    bind_address: Option<String>,
+    database_url: Option<String>,
+    pool_size: Option<u32>,
+    request_timeout: Option<String>,
+    log_format: Option<String>,
+    static_dir: Option<PathBuf>,
+    public_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Full,
+    Compact,
+    Json,
+}
+
+impl LogFormat {
+    fn parse(string: &str) -> Option<LogFormat> {
+        match string.to_lowercase().as_str() {
+            "full" => Some(LogFormat::Full),
+            "compact" => Some(LogFormat::Compact),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+
+    /// Install the global tracing subscriber writing in this format.
+    pub fn init(&self) {
+        let subscriber = tracing_subscriber::fmt();
+        match self {
+            LogFormat::Full => subscriber.init(),
+            LogFormat::Compact => subscriber.compact().init(),
+            LogFormat::Json => subscriber.json().init(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Overrides the bind address setting when set.
+    pub bind_address: Option<SocketAddr>,
+    pub database_url: String,
+    pub pool_size: u32,
+    /// Overrides the request timeout setting when set.
+    pub request_timeout: Option<Duration>,
+    pub log_format: LogFormat,
+    pub static_dir: Option<PathBuf>,
+    /// URL clients reach the server at, written into their configuration.
+    pub public_url: Option<String>,
+}
+
+impl Config {
+    /// Merge the layers, reporting every invalid or missing value.
+    pub fn load(args: &ConfigArgs) -> Result<Config, Vec<String>> {
+        let mut errors = Vec::new();
+        let file = match read_file(args.config.as_deref()) {
+            Ok(file) => file,
+            Err(error) => {
+                errors.push(error);
+                FileConfig::default()
+            }
+        };
+        // blank environment variables count as unset
+        let layer = |arg: &Option<String>, file: Option<String>| {
+            arg.clone().filter(|value| !value.trim().is_empty()).or(file).map(|value| value.trim().to_string())
+        };
+
+        let bind_address = layer(&args.bind_address, file.bind_address).and_then(|address| {
+            let error = format!("bind_address \"{}\" is not an address like 0.0.0.0:3000.", address);
+            check(&mut errors, address.parse().map_err(|_| error))
+        });
+        let database_url = layer(&args.database_url, file.database_url);
+        if database_url.is_none() {
+            errors.push("database_url must be set, e.g. with DATABASE_URL.".to_string());
+        }
+        let pool_size = layer(&args.pool_size, file.pool_size.map(|size| size.to_string())).and_then(|size| {
+            let error = format!("pool_size \"{}\" must be a number from 1 to {}.", size, MAX_POOL_SIZE);
+            check(&mut errors, size.parse().ok().filter(|size| (1..=MAX_POOL_SIZE).contains(size)).ok_or(error))
+        });
+        let request_timeout = layer(&args.request_timeout, file.request_timeout).and_then(|timeout| {
+            let error = format!("request_timeout \"{}\" must be a duration such as 10s.", timeout);
+            check(&mut errors, parse_duration(&timeout).ok_or(error))
+        });
+        let log_format = layer(&args.log_format, file.log_format).and_then(|format| {
+            let error = format!("log_format \"{}\" must be full, compact or json.", format);
+            check(&mut errors, LogFormat::parse(&format).ok_or(error))
+        });
+        let static_dir = args.static_dir.clone().filter(|dir| !dir.as_os_str().is_empty()).or(file.static_dir);
+        if let Some(dir) = static_dir.as_ref().filter(|dir| !dir.is_dir()) {
+            errors.push(format!("static_dir {} is not a directory.", dir.display()));
+        }
+        let public_url = layer(&args.public_url, file.public_url);
+        if let Some(url) = &public_url {
+            let valid = url.parse::<http::Uri>().is_ok_and(|uri| uri.scheme().is_some() && uri.host().is_some());
+            if !valid {
+                errors.push(format!("public_url \"{}\" is not a URL like http://192.168.1.124:3000.", url));
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(Config {
+            bind_address,
+            database_url: database_url.unwrap_or_default(),
+            pool_size: pool_size.unwrap_or(DEFAULT_POOL_SIZE),
+            request_timeout,
+            log_format: log_format.unwrap_or(LogFormat::Full),
+            static_dir,
+            public_url,
+        })
+    }
+}
+
+/// The value, or `None` with the error recorded.
+fn check<T>(errors: &mut Vec<String>, result: Result<T, String>) -> Option<T> {
+    result.map_err(|error| errors.push(error)).ok()
+}
+
+/// The given file, or the default one if it exists.
+fn read_file(path: Option<&Path>) -> Result<FileConfig, String> {
+    let path = match path {
+        Some(path) => path,
+        None if Path::new(DEFAULT_CONFIG_FILE).is_file() => Path::new(DEFAULT_CONFIG_FILE),
+        None => return Ok(FileConfig::default()),
+    };
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    toml::from_str(&contents).map_err(|e| format!("Invalid configuration file {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A configuration file unique to the test, so tests can run in parallel, removed when dropped.
+    struct TestFile(PathBuf);
+
+    impl Drop for TestFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn file(name: &str, contents: &str) -> TestFile {
+        let path = std::env::temp_dir().join(format!("site-config-{}-{}.toml", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        TestFile(path)
+    }
+
+    fn args(config: &TestFile) -> ConfigArgs {
+        ConfigArgs { config: Some(config.0.clone()), ..Default::default() }
+    }
+
+    #[test]
+    fn defaults_fill_what_is_unset() {
+        let file = file("defaults", "database_url = \"postgres://file\"\n");
+        let config = Config::load(&args(&file)).unwrap();
+        assert_eq!(config.database_url, "postgres://file");
+        assert_eq!(config.pool_size, DEFAULT_POOL_SIZE);
+        assert_eq!(config.log_format, LogFormat::Full);
+        assert_eq!(config.bind_address, None);
+        assert_eq!(config.request_timeout, None);
+        assert_eq!(config.public_url, None);
+    }
+
+    #[test]
+    fn flags_and_environment_override_the_file() {
+        let file = file(
+            "override",
+            "database_url = \"postgres://file\"\npool_size = 5\nrequest_timeout = \"10s\"\nlog_format = \"json\"\n",
+        );
+        let config = Config::load(&ConfigArgs {
+            database_url: Some("postgres://flag".to_string()),
+            pool_size: Some("20".to_string()),
+            ..args(&file)
+        })
+        .unwrap();
+        assert_eq!(config.database_url, "postgres://flag");
+        assert_eq!(config.pool_size, 20);
+        // the rest still comes from the file
+        assert_eq!(config.request_timeout, Some(Duration::from_secs(10)));
+        assert_eq!(config.log_format, LogFormat::Json);
+    }
+
+    #[test]
+    fn blank_values_count_as_unset() {
+        let file = file("blank", "database_url = \"postgres://file\"\npool_size = 5\n");
+        let config = Config::load(&ConfigArgs {
+            database_url: Some("".to_string()),
+            pool_size: Some("  ".to_string()),
+            log_format: Some(" compact ".to_string()),
+            ..args(&file)
+        })
+        .unwrap();
+        assert_eq!(config.database_url, "postgres://file");
+        assert_eq!(config.pool_size, 5);
+        assert_eq!(config.log_format, LogFormat::Compact);
+    }
+
+    #[test]
+    fn every_error_is_reported() {
+        let file = file("errors", "");
+        let errors = Config::load(&ConfigArgs {
+            bind_address: Some("localhost".to_string()),
+            pool_size: Some("0".to_string()),
+            request_timeout: Some("366d".to_string()),
+            log_format: Some("xml".to_string()),
+            public_url: Some("192.168.1.124:3000".to_string()),
+            ..args(&file)
+        })
+        .unwrap_err();
+        assert_eq!(errors.len(), 6, "{:?}", errors);
+        for key in ["bind_address", "database_url", "pool_size", "request_timeout", "log_format", "public_url"] {
+            assert!(errors.iter().any(|error| error.starts_with(key)), "{} in {:?}", key, errors);
+        }
+    }
+
+    #[test]
+    fn unknown_file_keys_are_rejected() {
+        let file = file("unknown", "database_url = \"postgres://file\"\nport = 3000\n");
+        let errors = Config::load(&args(&file)).unwrap_err();
+        // the rejected file does not set database_url either
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert!(errors[0].contains("unknown field `port`"), "{:?}", errors);
+    }
+
+    #[test]
+    fn missing_files_are_reported() {
+        let args = ConfigArgs {
+            config: Some(std::env::temp_dir().join("site-config-does-not-exist.toml")),
+            database_url: Some("postgres://flag".to_string()),
+            ..Default::default()
+        };
+        let errors = Config::load(&args).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("Failed to read"), "{:?}", errors);
+    }
+}
@@ -0,0 +1,258 @@
+//! Cron expressions for scheduled commands, see `crate::scheduler`.
+//!
+//! The usual five fields `minute hour day-of-month month day-of-week`, each `*`, a value, a range
+//! `a-b` or a list of those separated by commas, optionally with a step such as `*/5` or `8-18/2`.
+//! Months and weekdays may also be written by their first three letters, Sunday is `0` or `7`.
+//! `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly` stand for their usual expressions.
+//!
+//! As in cron, a time matches when its day matches either the day of the month or the day of the
+//! week if both are restricted. Times are UTC.
+//!
+//! `0 3 * * *` runs every night at 03:00, `*/5 * * * *` every five minutes.
+
+use std::fmt;
+use time::{Date, Duration, Month, OffsetDateTime, Time};
+
+const MONTH_NAMES: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+const WEEKDAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+/// Expressions that match nothing within this many years, like the 30th of February, never run.
+const MAX_SEARCH_YEARS: i32 = 5;
+
+/// Values a field matches as bits, bit `n` for value `n`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Field(u64);
+
+impl Field {
+    fn parse(string: &str, name: &str, min: u32, max: u32, names: &[&str]) -> Result<Field, String> {
+        let mut bits = 0;
+        for part in string.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => {
+                    let step: u32 = step
+                        .parse()
+                        .ok()
+                        .filter(|&step| step > 0)
+                        .ok_or(format!("Invalid step \"{}\" in the {} field.", step, name))?;
+                    (range, step)
+                }
+                None => (part, 1),
+            };
+            let (start, end) = if range == "*" {
+                (min, max)
+            } else if let Some((start, end)) = range.split_once('-') {
+                (value(start, name, min, max, names)?, value(end, name, min, max, names)?)
+            } else {
+                let start = value(range, name, min, max, names)?;
+                // `5/15` runs from 5 to the end of the field
+                (start, if step > 1 { max } else { start })
+            };
+            if start > end {
+                return Err(format!("Range \"{}\" of the {} field is backwards.", range, name));
+            }
+            for value in (start..=end).step_by(step as usize) {
+                bits |= 1 << value;
+            }
+        }
+        Ok(Field(bits))
+    }
+
+    fn contains(&self, value: u32) -> bool {
+        self.0 & (1 << value) != 0
+    }
+}
+
+fn value(string: &str, name: &str, min: u32, max: u32, names: &[&str]) -> Result<u32, String> {
+    let named = names
+        .iter()
+        .position(|candidate| candidate.eq_ignore_ascii_case(string))
+        .map(|index| index as u32 + min);
+    named
+        .or(string.parse().ok())
+        .filter(|value| (min..=max).contains(value))
+        .ok_or(format!("\"{}\" is not a valid {}, expected {} to {}.", string, name, min, max))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cron {
+    expression: String,
+    minutes: Field,
+    hours: Field,
+    days: Field,
+    months: Field,
+    weekdays: Field,
+    /// Whether the day fields were given as anything but `*`, see the module docs.
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl Cron {
+    pub fn parse(string: &str) -> Result<Cron, String> {
+        let expression = string.split_whitespace().collect::<Vec<_>>().join(" ");
+        let expanded = match expression.as_str() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split(' ').collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!("Expected 5 fields, minute hour day month weekday, got {}.", fields.len()));
+        };
+        let mut weekday_field = Field::parse(weekdays, "weekday", 0, 7, &WEEKDAY_NAMES)?;
+        // 7 is Sunday too
+        if weekday_field.contains(7) {
+            weekday_field.0 |= 1;
+        }
+
+        Ok(Cron {
+            minutes: Field::parse(minutes, "minute", 0, 59, &[])?,
+            hours: Field::parse(hours, "hour", 0, 23, &[])?,
+            days: Field::parse(days, "day", 1, 31, &[])?,
+            months: Field::parse(months, "month", 1, 12, &MONTH_NAMES)?,
+            weekdays: weekday_field,
+            days_restricted: days != "*",
+            weekdays_restricted: weekdays != "*",
+            expression,
+        })
+    }
+
+    fn matches_day(&self, date: Date) -> bool {
+        let day = self.days.contains(date.day() as u32);
+        let weekday = self.weekdays.contains(date.weekday().number_days_from_sunday() as u32);
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            (true, false) => day,
+            (false, true) => weekday,
+            (false, false) => true,
+        }
+    }
+
+    /// The first time after `time` the expression matches, `None` if it never does.
+    pub fn next_after(&self, time: OffsetDateTime) -> Option<OffsetDateTime> {
+        let time = time.to_offset(time::UtcOffset::UTC);
+        let limit = time.year() + MAX_SEARCH_YEARS;
+        let mut next = time.replace_time(Time::from_hms(time.hour(), time.minute(), 0).ok()?) + Duration::minutes(1);
+
+        // skip ahead a whole month, day or hour whenever one of those does not match
+        while next.year() <= limit {
+            if !self.months.contains(next.month() as u32) {
+                let (year, month) = match next.month() {
+                    Month::December => (next.year() + 1, Month::January),
+                    month => (next.year(), month.next()),
+                };
+                next = Date::from_calendar_date(year, month, 1).ok()?.midnight().assume_utc();
+            } else if !self.matches_day(next.date()) {
+                next = next.date().next_day()?.midnight().assume_utc();
+            } else if !self.hours.contains(next.hour() as u32) {
+                next = next.replace_minute(0).ok()? + Duration::hours(1);
+            } else if !self.minutes.contains(next.minute() as u32) {
+                next += Duration::minutes(1);
+            } else {
+                return Some(next);
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Display for Cron {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.expression)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(year: i32, month: Month, day: u8, hour: u8, minute: u8) -> OffsetDateTime {
+        Date::from_calendar_date(year, month, day).unwrap().with_hms(hour, minute, 0).unwrap().assume_utc()
+    }
+
+    fn minutes(string: &str) -> Vec<u32> {
+        let field = Field::parse(string, "minute", 0, 59, &[]).unwrap();
+        (0..=59).filter(|&minute| field.contains(minute)).collect()
+    }
+
+    #[test]
+    fn fields_take_values_ranges_lists_and_steps() {
+        assert_eq!(minutes("5"), [5]);
+        assert_eq!(minutes("10-13"), [10, 11, 12, 13]);
+        assert_eq!(minutes("1,3,58-59"), [1, 3, 58, 59]);
+        assert_eq!(minutes("*/15"), [0, 15, 30, 45]);
+        assert_eq!(minutes("5/15"), [5, 20, 35, 50]);
+        assert_eq!(minutes("8-18/4"), [8, 12, 16]);
+        assert_eq!(minutes("0/1"), [0]);
+        assert_eq!(minutes("*").len(), 60);
+    }
+
+    #[test]
+    fn invalid_fields_are_rejected() {
+        for string in ["60", "-1", "5-", "10-5", "*/0", "*/x", "5/", "", "1,,2", "a"] {
+            assert!(Field::parse(string, "minute", 0, 59, &[]).is_err(), "{}", string);
+        }
+        assert!(Cron::parse("0 24 * * *").is_err());
+        assert!(Cron::parse("0 0 0 * *").is_err());
+        assert!(Cron::parse("0 0 32 * *").is_err());
+        assert!(Cron::parse("0 0 * 13 *").is_err());
+        assert!(Cron::parse("0 0 * * 8").is_err());
+        assert!(Cron::parse("0 0 * *").is_err());
+        assert!(Cron::parse("0 0 * * * *").is_err());
+        assert!(Cron::parse("@reboot").is_err());
+    }
+
+    #[test]
+    fn names_and_macros_parse() {
+        let (named, numbered) = (Cron::parse("0 0 * JAN-mar mon-fri").unwrap(), Cron::parse("0 0 * 1-3 1-5").unwrap());
+        assert_eq!((named.months, named.weekdays), (numbered.months, numbered.weekdays));
+        assert_eq!(Cron::parse("@daily").unwrap().next_after(at(2024, Month::May, 3, 12, 0)), Some(at(2024, Month::May, 4, 0, 0)));
+        assert_eq!(Cron::parse("@hourly").unwrap().next_after(at(2024, Month::May, 3, 12, 0)), Some(at(2024, Month::May, 3, 13, 0)));
+        assert_eq!(Cron::parse("  0   3 * *  * ").unwrap().to_string(), "0 3 * * *");
+    }
+
+    #[test]
+    fn seven_is_sunday() {
+        let sunday = Cron::parse("0 0 * * 7").unwrap();
+        // 2024-05-03 is a Friday
+        assert_eq!(sunday.next_after(at(2024, Month::May, 3, 12, 0)), Some(at(2024, Month::May, 5, 0, 0)));
+        assert_eq!(sunday.weekdays, Cron::parse("0 0 * * 0,7").unwrap().weekdays);
+    }
+
+    #[test]
+    fn restricted_days_match_either_field() {
+        // the 10th or any Monday
+        let cron = Cron::parse("0 0 10 * mon").unwrap();
+        assert_eq!(cron.next_after(at(2024, Month::May, 3, 12, 0)), Some(at(2024, Month::May, 6, 0, 0)));
+        assert_eq!(cron.next_after(at(2024, Month::May, 6, 0, 0)), Some(at(2024, Month::May, 10, 0, 0)));
+        // only the 10th when the weekday is unrestricted
+        let cron = Cron::parse("0 0 10 * *").unwrap();
+        assert_eq!(cron.next_after(at(2024, Month::May, 3, 12, 0)), Some(at(2024, Month::May, 10, 0, 0)));
+    }
+
+    #[test]
+    fn next_is_strictly_after() {
+        let cron = Cron::parse("*/5 * * * *").unwrap();
+        assert_eq!(cron.next_after(at(2024, Month::May, 3, 12, 0)), Some(at(2024, Month::May, 3, 12, 5)));
+        let seconds_in = at(2024, Month::May, 3, 12, 4) + Duration::seconds(59);
+        assert_eq!(cron.next_after(seconds_in), Some(at(2024, Month::May, 3, 12, 5)));
+    }
+
+    #[test]
+    fn next_rolls_over_hours_days_months_and_years() {
+        let cron = Cron::parse("30 * * * *").unwrap();
+        assert_eq!(cron.next_after(at(2024, Month::May, 3, 23, 45)), Some(at(2024, Month::May, 4, 0, 30)));
+        let cron = Cron::parse("0 3 * * *").unwrap();
+        assert_eq!(cron.next_after(at(2024, Month::May, 31, 3, 0)), Some(at(2024, Month::June, 1, 3, 0)));
+        let cron = Cron::parse("@yearly").unwrap();
+        assert_eq!(cron.next_after(at(2024, Month::December, 31, 23, 59)), Some(at(2025, Month::January, 1, 0, 0)));
+        let cron = Cron::parse("0 0 29 2 *").unwrap();
+        assert_eq!(cron.next_after(at(2024, Month::March, 1, 0, 0)), Some(at(2028, Month::February, 29, 0, 0)));
+    }
+
+    #[test]
+    fn impossible_dates_never_run() {
+        assert_eq!(Cron::parse("0 0 30 feb *").unwrap().next_after(at(2024, Month::May, 3, 12, 0)), None);
+    }
+}
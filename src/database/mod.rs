@@ -1,5 +1,7 @@
-use diesel::PgConnection;
+use anyhow::anyhow;
+use diesel::{Connection, PgConnection};
 use diesel::r2d2::ConnectionManager;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 
 pub mod models;
 pub mod schema;
@@ -7,9 +9,24 @@ pub mod schema;
 pub type Pool = diesel::r2d2::Pool<ConnectionManager<PgConnection>>;
 pub type DbConn = diesel::r2d2::PooledConnection<ConnectionManager<PgConnection>>;
 
-pub fn establish_connection_pool(database_url: &str) -> Pool {
+/// The migrations in `migrations/`, built into the binary so it can set up its own database.
+pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
+
+pub fn establish_connection_pool(database_url: &str, pool_size: u32) -> Pool {
     let manager = ConnectionManager::<PgConnection>::new(database_url);
     diesel::r2d2::Pool::builder()
+        .max_size(pool_size)
         .build(manager)
         .expect("Failed to create pool.")
 }
+
+/// A single connection, for the admin commands.
+pub fn establish_connection(database_url: &str) -> Result<PgConnection, anyhow::Error> {
+    PgConnection::establish(database_url).map_err(|e| anyhow!("Failed to connect to the database: {}", e))
+}
+
+/// Apply every pending migration, returning the versions that were applied.
+pub fn run_migrations(connection: &mut PgConnection) -> Result<Vec<String>, anyhow::Error> {
+    let applied = connection.run_pending_migrations(MIGRATIONS).map_err(|e| anyhow!(e))?;
+    Ok(applied.iter().map(ToString::to_string).collect())
+}
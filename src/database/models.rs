@@ -1,12 +1,16 @@
-use std::collections::VecDeque;
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use axum::extract::ws::{Message, WebSocket};
 use diesel::prelude::*;
+use futures_util::SinkExt;
 use futures_util::stream::{SplitSink};
 use maud::{html, Markup, Render};
-use time::{PrimitiveDateTime};
-use tokio::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use time::{OffsetDateTime, PrimitiveDateTime};
+use tokio::sync::{Mutex, Notify, OwnedMutexGuard};
 use tokio::task::JoinHandle;
+use crate::codec::Codec;
 
 #[derive(Queryable, Selectable, Identifiable, Clone)]
 #[diesel(table_name = crate::database::schema::clients)]
@@ -14,41 +18,131 @@ use tokio::task::JoinHandle;
 pub struct Client {
     id: i64,
     pub name: String,
-    status: String,
-    description: Option<String>,
-    location: Option<String>,
-    revoked: bool,
-    auth_key: String,
-    api_key: Option<String>,
-    created_on: PrimitiveDateTime,
-    accessed_on: Option<PrimitiveDateTime>,
+    pub status: String,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    pub revoked: bool,
+    pub auth_key: String,
+    pub api_key: Option<String>,
+    pub created_on: PrimitiveDateTime,
+    pub accessed_on: Option<PrimitiveDateTime>,
+    /// Operators may run arbitrary Lua on the client with `Exec`.
+    pub allow_exec: bool,
+    /// Release channel the client follows, unset to follow its tags, see `crate::releases`.
+    pub channel: Option<String>,
+    /// Lowest level of logs the client sends, see `crate::client_config`.
+    pub log_level: String,
+    pub disabled_modules: Vec<Option<String>>,
 }
 
 /// Unused in database, but used in appstate.
 pub struct ActiveClient {
-    pub sender: SplitSink<WebSocket, Message>,
+    pub sender: ClientSender,
     pub client: Client,
-    pub message_queue: Arc<Mutex<VecDeque<Message>>>,
-    pub thread_handle: JoinHandle<()>
+    pub thread_handle: JoinHandle<()>,
+    /// `client_sessions` row of this connection.
+    pub session_id: i64,
+    pub connected_on: OffsetDateTime,
+    pub traffic: Arc<SessionTraffic>,
+    /// Notified to end the receiving task of a connection the server gave up on.
+    pub evicted: Arc<Notify>,
+    /// Format of binary frames, negotiated through the websocket subprotocol.
+    pub codec: Codec,
+}
+
+impl ActiveClient {
+    /// Send a message to the client, counting it towards the session traffic.
+    pub async fn send(&self, message: Message) -> Result<(), axum::Error> {
+        self.sender.send(message).await
+    }
+}
+
+/// Sending half of a connection. Clones share it, so a sender can be taken out of
+/// `active_clients` and the lock released before waiting on a slow client.
+#[derive(Clone)]
+pub struct ClientSender {
+    sink: Arc<Mutex<SplitSink<WebSocket, Message>>>,
+    traffic: Arc<SessionTraffic>,
+}
+
+impl ClientSender {
+    pub fn new(sink: SplitSink<WebSocket, Message>, traffic: Arc<SessionTraffic>) -> ClientSender {
+        ClientSender { sink: Arc::new(Mutex::new(sink)), traffic }
+    }
+
+    /// Send a message to the client, counting it towards the session traffic.
+    pub async fn send(&self, message: Message) -> Result<(), axum::Error> {
+        self.lock().await.send(message).await
+    }
+
+    /// Exclusive use of the connection, other sends wait until the guard is dropped.
+    pub async fn lock(&self) -> SenderGuard {
+        SenderGuard { sink: self.sink.clone().lock_owned().await, traffic: self.traffic.clone() }
+    }
+}
+
+pub struct SenderGuard {
+    sink: OwnedMutexGuard<SplitSink<WebSocket, Message>>,
+    traffic: Arc<SessionTraffic>,
+}
+
+impl SenderGuard {
+    pub async fn send(&mut self, message: Message) -> Result<(), axum::Error> {
+        self.traffic.record_out(&message);
+        self.sink.send(message).await
+    }
+}
+
+/// Traffic counters of a live connection, shared between its receiving task and `ActiveClient`.
+#[derive(Default)]
+pub struct SessionTraffic {
+    pub bytes_in: AtomicU64,
+    pub bytes_out: AtomicU64,
+    pub messages_in: AtomicU64,
+    pub messages_out: AtomicU64,
+}
+
+impl SessionTraffic {
+    fn message_size(message: &Message) -> u64 {
+        match message {
+            Message::Text(text) => text.len() as u64,
+            Message::Binary(data) | Message::Ping(data) | Message::Pong(data) => data.len() as u64,
+            Message::Close(_) => 0,
+        }
+    }
+
+    pub fn record_in(&self, message: &Message) {
+        self.messages_in.fetch_add(1, Ordering::Relaxed);
+        self.bytes_in.fetch_add(Self::message_size(message), Ordering::Relaxed);
+    }
+
+    pub fn record_out(&self, message: &Message) {
+        self.messages_out.fetch_add(1, Ordering::Relaxed);
+        self.bytes_out.fetch_add(Self::message_size(message), Ordering::Relaxed);
+    }
 }
 
 /// Unused in database, but used as small card size reference in rendering
 pub struct MiniClient {
+    pub id: i64,
     pub name: String,
-    status: String,
-    description: Option<String>,
-    location: Option<String>,
-    accessed_on: Option<PrimitiveDateTime>,
+    pub status: String,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    pub accessed_on: Option<PrimitiveDateTime>,
+    pub tags: Vec<String>,
 }
 
 impl MiniClient {
-    pub fn from_client(original: Client) -> Self {
+    pub fn from_client(original: Client, tags: Vec<String>) -> Self {
         MiniClient {
+            id: original.id,
             name: original.name,
             status: original.status,
             description: original.description,
             location: original.location,
             accessed_on: original.accessed_on,
+            tags,
         }
     }
 }
@@ -56,15 +150,19 @@ impl MiniClient {
 impl Render for MiniClient {
     fn render(&self) -> Markup {
         html! {
-            .flex.border."p-2" {
+            .flex.border."p-2".cursor-pointer
+                hx-get=(format!("/clients/{}", self.id)) hx-target="#body-contents" hx-push-url="true" {
                 .flex.flex-col."pr-4" {
                     img src="/favicon.ico" {}
-                    p { "------" }
+                    p { (self.status) }
                 }
                 .flex.flex-col {
                     p.font-bold { (self.name) }
                     p."flex-1" { (self.location.as_deref().unwrap_or_default()) }
                     p { (self.description.as_deref().unwrap_or_default()) }
+                    .flex.gap-1 {
+                        @for tag in &self.tags { span .badge.badge-sm { (tag) } }
+                    }
                 }
             }
         }
@@ -78,6 +176,117 @@ impl Client {
         Ok(all_clients)
     }
 
+    /// Number of clients in each status.
+    pub fn count_by_status(connection: &mut PgConnection) -> Result<Vec<(String, i64)>, anyhow::Error> {
+        use crate::database::schema::clients::dsl::*;
+        let counts = clients
+            .group_by(status)
+            .select((status, diesel::dsl::count_star()))
+            .order(status)
+            .load(connection)?;
+        Ok(counts)
+    }
+
+    pub fn get_by_id(
+        connection: &mut PgConnection,
+        id_query: i64,
+    ) -> Result<Option<Self>, anyhow::Error> {
+        use crate::database::schema::clients::dsl::*;
+        let client = clients
+            .find(id_query)
+            .select(Client::as_select())
+            .first(connection)
+            .optional()?;
+        Ok(client)
+    }
+
+    /// Apply a validated metadata update, recording every changed field in the client's history.
+    pub fn update_metadata(
+        &self,
+        connection: &mut PgConnection,
+        changes: &ClientMetadata,
+        source: ChangeSource,
+    ) -> Result<Client, anyhow::Error> {
+        use crate::database::schema::client_metadata_history;
+        use crate::database::schema::clients::dsl::*;
+
+        let new_description = changes.description.as_deref().map(metadata_value);
+        let new_location = changes.location.as_deref().map(metadata_value);
+        let history: Vec<NewClientMetadataChange> = [
+            ("description", &self.description, &new_description),
+            ("location", &self.location, &new_location),
+        ]
+            .into_iter()
+            .filter_map(|(field, old_value, new_value)| {
+                let new_value = new_value.as_ref().filter(|value| *value != old_value)?;
+                Some(NewClientMetadataChange {
+                    client_id: self.id,
+                    field: field.to_string(),
+                    old_value: old_value.clone(),
+                    new_value: new_value.clone(),
+                    changed_by: source.as_str().to_string(),
+                })
+            })
+            .collect();
+
+        connection.transaction(|connection| {
+            diesel::update(self)
+                .set((
+                    description.eq(new_description.unwrap_or(self.description.clone())),
+                    location.eq(new_location.unwrap_or(self.location.clone())),
+                ))
+                .execute(connection)?;
+            diesel::insert_into(client_metadata_history::table)
+                .values(&history)
+                .execute(connection)?;
+            clients.find(self.id).select(Client::as_select()).first(connection)
+        }).map_err(Into::into)
+    }
+
+    pub fn set_channel(&self, connection: &mut PgConnection, new_channel: Option<Channel>) -> Result<(), anyhow::Error> {
+        use crate::database::schema::clients::dsl::*;
+        diesel::update(self)
+            .set(channel.eq(new_channel.map(|new_channel| new_channel.as_str())))
+            .execute(connection)?;
+        Ok(())
+    }
+
+    /// Store the client's settings of its generated configuration.
+    pub fn set_config(
+        &self,
+        connection: &mut PgConnection,
+        level: LogLevel,
+        disabled: &[&str],
+    ) -> Result<(), anyhow::Error> {
+        use crate::database::schema::clients::dsl::*;
+        let disabled: Vec<Option<String>> = disabled.iter().map(|module| Some(module.to_string())).collect();
+        diesel::update(self)
+            .set((log_level.eq(level.as_str()), disabled_modules.eq(disabled)))
+            .execute(connection)?;
+        Ok(())
+    }
+
+    /// Stop the client from authenticating. A server holding its connection keeps it open.
+    pub fn revoke(&self, connection: &mut PgConnection) -> Result<(), anyhow::Error> {
+        use crate::database::schema::clients::dsl::*;
+        diesel::update(self).set(revoked.eq(true)).execute(connection)?;
+        Ok(())
+    }
+
+    /// Replace the client's authorization code and drop its API key, so it has to enroll again
+    /// with the new code.
+    pub fn reissue_auth(&self, connection: &mut PgConnection, new_auth_key: &str) -> Result<(), anyhow::Error> {
+        use crate::database::schema::clients::dsl::*;
+        diesel::update(self)
+            .set((
+                auth_key.eq(new_auth_key),
+                api_key.eq(None::<String>),
+                status.eq(Status::Authorized.as_str()),
+            ))
+            .execute(connection)?;
+        Ok(())
+    }
+
     pub fn get_by_auth(
         connection: &mut PgConnection,
         auth_key_query: &str,
@@ -110,6 +319,8 @@ pub struct NewClient {
     pub(crate) auth_key: String,
     pub(crate) status: String,
     pub(crate) revoked: bool,
+    pub(crate) description: Option<String>,
+    pub(crate) location: Option<String>,
 }
 
 impl NewClient {
@@ -119,7 +330,165 @@ impl NewClient {
             auth_key: auth.to_string(),
             status: "Authorized".to_string(),
             revoked: false,
+            description: None,
+            location: None,
+        }
+    }
+
+    pub fn create(&self, connection: &mut PgConnection) -> Result<i64, anyhow::Error> {
+        use crate::database::schema::clients::dsl::*;
+        let created = diesel::insert_into(clients)
+            .values(self)
+            .returning(id)
+            .get_result(connection)?;
+        Ok(created)
+    }
+}
+
+pub const MAX_DESCRIPTION_LENGTH: usize = 256;
+pub const MAX_LOCATION_LENGTH: usize = 128;
+
+/// Who changed a client's metadata.
+#[derive(Debug, Clone, Copy)]
+pub enum ChangeSource {
+    Ui,
+    Api,
+    /// Self-reported by the client when it connected.
+    Client,
+}
+
+impl ChangeSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeSource::Ui => "UI",
+            ChangeSource::Api => "API",
+            ChangeSource::Client => "Client",
+        }
+    }
+}
+
+/// Partial update of a client's editable metadata. Omitted fields are left unchanged and empty
+/// strings clear the field.
+#[derive(Deserialize, Default)]
+pub struct ClientMetadata {
+    pub description: Option<String>,
+    pub location: Option<String>,
+}
+
+impl ClientMetadata {
+    /// Every problem with the update, so they can all be reported at once.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        let fields = [
+            ("Description", &self.description, MAX_DESCRIPTION_LENGTH),
+            ("Location", &self.location, MAX_LOCATION_LENGTH),
+        ];
+        for (field, value, max_length) in fields {
+            let Some(value) = value else { continue };
+            if value.trim().chars().count() > max_length {
+                errors.push(format!("{} must be at most {} characters.", field, max_length));
+            }
+            if value.chars().any(char::is_control) {
+                errors.push(format!("{} must not contain control characters.", field));
+            }
         }
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+}
+
+/// Normalize an update value, blank means the field is cleared.
+pub fn metadata_value(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+#[derive(Queryable, Selectable, Identifiable, Clone)]
+#[diesel(table_name = crate::database::schema::client_logs)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ClientLog {
+    pub id: i64,
+    pub client_id: i64,
+    pub log_time: OffsetDateTime,
+    pub log_message: String,
+    pub log_level: String,
+}
+
+impl ClientLog {
+    pub fn get_recent_for_client(
+        connection: &mut PgConnection,
+        client: i64,
+        limit: i64,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::client_logs::dsl::*;
+        let logs = client_logs
+            .filter(client_id.eq(client))
+            .order(log_time.desc())
+            .limit(limit)
+            .select(ClientLog::as_select())
+            .load(connection)?;
+        Ok(logs)
+    }
+
+    /// The latest logs of the given clients, or of every client, newest first.
+    pub fn get_recent(
+        connection: &mut PgConnection,
+        clients: Option<&[i64]>,
+        limit: i64,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::client_logs::dsl::*;
+        let mut query = client_logs.select(ClientLog::as_select()).into_boxed();
+        if let Some(clients) = clients {
+            query = query.filter(client_id.eq_any(clients.to_vec()));
+        }
+        let logs = query.order(id.desc()).limit(limit).load(connection)?;
+        Ok(logs)
+    }
+
+    /// Logs of the given clients, or of every client, received after the log `after`, oldest first.
+    pub fn get_after(
+        connection: &mut PgConnection,
+        clients: Option<&[i64]>,
+        after: i64,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::client_logs::dsl::*;
+        let mut query = client_logs.select(ClientLog::as_select()).filter(id.gt(after)).into_boxed();
+        if let Some(clients) = clients {
+            query = query.filter(client_id.eq_any(clients.to_vec()));
+        }
+        let logs = query.order(id.asc()).load(connection)?;
+        Ok(logs)
+    }
+
+    /// Number of logs in each level received since `cutoff`.
+    pub fn count_by_level(
+        connection: &mut PgConnection,
+        cutoff: OffsetDateTime,
+    ) -> Result<Vec<(String, i64)>, anyhow::Error> {
+        use crate::database::schema::client_logs::dsl::*;
+        let counts = client_logs
+            .filter(log_time.ge(cutoff))
+            .group_by(log_level)
+            .select((log_level, diesel::dsl::count_star()))
+            .load(connection)?;
+        Ok(counts)
+    }
+
+    /// Number of logs a client sent at `level` between `from` and `to`.
+    pub fn count_for_client(
+        connection: &mut PgConnection,
+        client: i64,
+        level: LogLevel,
+        from: OffsetDateTime,
+        to: OffsetDateTime,
+    ) -> Result<i64, anyhow::Error> {
+        use crate::database::schema::client_logs::dsl::*;
+        let count = client_logs
+            .filter(client_id.eq(client))
+            .filter(log_level.eq(level.as_str()))
+            .filter(log_time.between(from, to))
+            .count()
+            .get_result(connection)?;
+        Ok(count)
     }
 }
 
@@ -128,10 +497,1653 @@ impl NewClient {
 pub struct NewClientLog {
     pub(crate) client_id: i64,
     pub(crate) log_message: String,
+    pub(crate) log_level: String,
+}
+
+impl NewClientLog {
+    /// Build a log from the body of a `Log` command, which may start with a `[Level]` tag.
+    pub fn from_body(client_id: i64, body: &str) -> NewClientLog {
+        let (level, message) = LogLevel::split_prefix(body);
+        NewClientLog {
+            client_id,
+            log_message: message.to_string(),
+            log_level: level.as_str().to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
 }
 
-enum Status {
+impl LogLevel {
+    pub const ALL: [LogLevel; 4] = [LogLevel::Debug, LogLevel::Info, LogLevel::Warn, LogLevel::Error];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Debug => "Debug",
+            LogLevel::Info => "Info",
+            LogLevel::Warn => "Warn",
+            LogLevel::Error => "Error",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<LogLevel> {
+        LogLevel::ALL.into_iter().find(|level| level.as_str().eq_ignore_ascii_case(name.trim()))
+    }
+
+    /// Split a leading `[Level] ` tag off a log message, untagged messages are `Info`.
+    pub fn split_prefix(message: &str) -> (LogLevel, &str) {
+        for level in LogLevel::ALL {
+            let tag = format!("[{}]", level.as_str());
+            if let Some(rest) = message.strip_prefix(&tag) {
+                return (level, rest.trim_start());
+            }
+        }
+        (LogLevel::Info, message)
+    }
+}
+
+pub enum Status {
     Authorized,
     Enrolled,
     Connected,
 }
+
+impl Status {
+    pub const ALL: [Status; 3] = [Status::Authorized, Status::Enrolled, Status::Connected];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Authorized => "Authorized",
+            Status::Enrolled => "Enrolled",
+            Status::Connected => "Connected",
+        }
+    }
+}
+
+/// A single item stack reported in an AE network snapshot. Every row of one snapshot shares the
+/// same `client_id` and `sampled_on`.
+#[derive(Queryable, Selectable, Identifiable, Clone)]
+#[diesel(table_name = crate::database::schema::item_samples)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ItemSample {
+    pub id: i64,
+    pub client_id: i64,
+    pub sampled_on: OffsetDateTime,
+    pub item_name: String,
+    pub label: String,
+    pub amount: i64,
+    pub craftable: bool,
+}
+
+impl ItemSample {
+    /// The most recent snapshot reported by each client.
+    pub fn latest(connection: &mut PgConnection) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::item_samples::dsl::*;
+
+        let latest_times: Vec<(i64, Option<OffsetDateTime>)> = item_samples
+            .group_by(client_id)
+            .select((client_id, diesel::dsl::max(sampled_on)))
+            .load(connection)?;
+
+        let mut samples = Vec::new();
+        for (owner, time) in latest_times {
+            let Some(time) = time else { continue };
+            samples.extend(
+                item_samples
+                    .filter(client_id.eq(owner).and(sampled_on.eq(time)))
+                    .order(amount.desc())
+                    .select(ItemSample::as_select())
+                    .load(connection)?,
+            );
+        }
+        Ok(samples)
+    }
+
+    /// Every sample taken since `cutoff`, oldest first.
+    pub fn since(
+        connection: &mut PgConnection,
+        cutoff: OffsetDateTime,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::item_samples::dsl::*;
+        let samples = item_samples
+            .filter(sampled_on.ge(cutoff))
+            .order(sampled_on.asc())
+            .select(ItemSample::as_select())
+            .load(connection)?;
+        Ok(samples)
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::database::schema::item_samples)]
+pub struct NewItemSample {
+    pub(crate) client_id: i64,
+    pub(crate) sampled_on: OffsetDateTime,
+    pub(crate) item_name: String,
+    pub(crate) label: String,
+    pub(crate) amount: i64,
+    pub(crate) craftable: bool,
+}
+
+/// "Keep at least `min_amount` of `item_name`" policy for the AE network attached to a client.
+#[derive(Queryable, Selectable, Identifiable, Clone)]
+#[diesel(table_name = crate::database::schema::item_thresholds)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ItemThreshold {
+    pub id: i64,
+    pub client_id: i64,
+    pub item_name: String,
+    pub min_amount: i64,
+    pub enabled: bool,
+    pub failure_count: i32,
+    pub flagged: bool,
+    pub created_on: OffsetDateTime,
+}
+
+impl ItemThreshold {
+    pub fn get_all(connection: &mut PgConnection) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::item_thresholds::dsl::*;
+        let thresholds = item_thresholds
+            .order((client_id, item_name))
+            .select(ItemThreshold::as_select())
+            .load(connection)?;
+        Ok(thresholds)
+    }
+
+    /// Thresholds that should currently trigger craft requests for the given client.
+    pub fn get_active(
+        connection: &mut PgConnection,
+        client_id_query: i64,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::item_thresholds::dsl::*;
+        let thresholds = item_thresholds
+            .filter(client_id.eq(client_id_query))
+            .filter(enabled.eq(true))
+            .filter(flagged.eq(false))
+            .select(ItemThreshold::as_select())
+            .load(connection)?;
+        Ok(thresholds)
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::database::schema::item_thresholds)]
+pub struct NewItemThreshold {
+    pub(crate) client_id: i64,
+    pub(crate) item_name: String,
+    pub(crate) min_amount: i64,
+}
+
+/// Lifecycle of a craft request sent to a client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CraftStatus {
+    /// Sent to the client, no response yet.
+    Requested,
+    /// The client has handed the request to its ME network.
+    Accepted,
+    Done,
+    Failed,
+}
+
+impl CraftStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CraftStatus::Requested => "Requested",
+            CraftStatus::Accepted => "Accepted",
+            CraftStatus::Done => "Done",
+            CraftStatus::Failed => "Failed",
+        }
+    }
+
+    pub fn parse(string: &str) -> Option<Self> {
+        match string {
+            "Requested" => Some(CraftStatus::Requested),
+            "Accepted" => Some(CraftStatus::Accepted),
+            "Done" => Some(CraftStatus::Done),
+            "Failed" => Some(CraftStatus::Failed),
+            _ => None,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        matches!(self, CraftStatus::Requested | CraftStatus::Accepted)
+    }
+}
+
+#[derive(Queryable, Selectable, Identifiable, Clone)]
+#[diesel(table_name = crate::database::schema::craft_jobs)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct CraftJob {
+    pub id: i64,
+    pub threshold_id: Option<i64>,
+    pub client_id: i64,
+    pub item_name: String,
+    pub amount: i64,
+    pub status: String,
+    pub message: Option<String>,
+    pub requested_on: OffsetDateTime,
+    pub updated_on: OffsetDateTime,
+}
+
+impl CraftJob {
+    pub fn get_recent(
+        connection: &mut PgConnection,
+        limit: i64,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::craft_jobs::dsl::*;
+        let jobs = craft_jobs
+            .order(requested_on.desc())
+            .limit(limit)
+            .select(CraftJob::as_select())
+            .load(connection)?;
+        Ok(jobs)
+    }
+
+    pub fn get_by_id(
+        connection: &mut PgConnection,
+        job_id: i64,
+    ) -> Result<Option<Self>, anyhow::Error> {
+        use crate::database::schema::craft_jobs::dsl::*;
+        let job = craft_jobs
+            .find(job_id)
+            .select(CraftJob::as_select())
+            .first(connection)
+            .optional()?;
+        Ok(job)
+    }
+
+    /// Whether the threshold already has a craft in flight.
+    pub fn has_open(
+        connection: &mut PgConnection,
+        threshold_id_query: i64,
+    ) -> Result<bool, anyhow::Error> {
+        use crate::database::schema::craft_jobs::dsl::*;
+        use diesel::dsl::{exists, select};
+        let open = select(exists(
+            craft_jobs
+                .filter(threshold_id.eq(threshold_id_query))
+                .filter(status.eq_any([
+                    CraftStatus::Requested.as_str(),
+                    CraftStatus::Accepted.as_str(),
+                ])),
+        ))
+        .get_result(connection)?;
+        Ok(open)
+    }
+
+    /// Open jobs that have not heard back from their client since `cutoff`.
+    pub fn get_stale(
+        connection: &mut PgConnection,
+        cutoff: OffsetDateTime,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::craft_jobs::dsl::*;
+        let jobs = craft_jobs
+            .filter(status.eq_any([
+                CraftStatus::Requested.as_str(),
+                CraftStatus::Accepted.as_str(),
+            ]))
+            .filter(updated_on.lt(cutoff))
+            .select(CraftJob::as_select())
+            .load(connection)?;
+        Ok(jobs)
+    }
+
+    /// Move the job to a new status. Failures count towards flagging the owning threshold once
+    /// `max_failures` happen in a row, a successful craft resets the count.
+    pub fn set_status(
+        &self,
+        connection: &mut PgConnection,
+        new_status: CraftStatus,
+        new_message: Option<&str>,
+        max_failures: i32,
+    ) -> Result<(), anyhow::Error> {
+        use crate::database::schema::craft_jobs::dsl::*;
+        use crate::database::schema::item_thresholds;
+
+        connection.transaction(|connection| {
+            diesel::update(self)
+                .set((
+                    status.eq(new_status.as_str()),
+                    message.eq(new_message),
+                    updated_on.eq(OffsetDateTime::now_utc()),
+                ))
+                .execute(connection)?;
+
+            let Some(threshold) = self.threshold_id else { return Ok(()) };
+            let threshold = item_thresholds::table.find(threshold);
+            match new_status {
+                CraftStatus::Failed => {
+                    diesel::update(threshold)
+                        .set(item_thresholds::failure_count.eq(item_thresholds::failure_count + 1))
+                        .execute(connection)?;
+                    diesel::update(threshold)
+                        .filter(item_thresholds::failure_count.ge(max_failures))
+                        .set(item_thresholds::flagged.eq(true))
+                        .execute(connection)?;
+                }
+                CraftStatus::Done => {
+                    diesel::update(threshold)
+                        .set(item_thresholds::failure_count.eq(0))
+                        .execute(connection)?;
+                }
+                _ => {}
+            }
+            Ok(())
+        })
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::database::schema::craft_jobs)]
+pub struct NewCraftJob {
+    pub(crate) threshold_id: Option<i64>,
+    pub(crate) client_id: i64,
+    pub(crate) item_name: String,
+    pub(crate) amount: i64,
+    pub(crate) status: String,
+}
+
+impl NewCraftJob {
+    pub fn from_threshold(threshold: &ItemThreshold, amount: i64) -> NewCraftJob {
+        NewCraftJob {
+            threshold_id: Some(threshold.id),
+            client_id: threshold.client_id,
+            item_name: threshold.item_name.clone(),
+            amount,
+            status: CraftStatus::Requested.as_str().to_string(),
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::database::schema::power_samples)]
+pub struct NewPowerSample {
+    pub(crate) client_id: i64,
+    pub(crate) stored: f64,
+    pub(crate) capacity: f64,
+    pub(crate) draw: f64,
+}
+
+/// One rolled up bucket of a telemetry metric, see `crate::telemetry`.
+#[derive(Queryable, Selectable, Clone)]
+#[diesel(table_name = crate::database::schema::telemetry_rollups)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct TelemetryPoint {
+    pub client_id: i64,
+    pub metric: String,
+    pub bucket: OffsetDateTime,
+    pub min_value: f64,
+    pub max_value: f64,
+    pub avg_value: f64,
+    pub sample_count: i64,
+}
+
+/// One WebSocket connection of a client, open while `disconnected_on` is unset.
+#[derive(Queryable, Selectable, Identifiable, Clone)]
+#[diesel(table_name = crate::database::schema::client_sessions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ClientSession {
+    pub id: i64,
+    pub client_id: i64,
+    pub connected_on: OffsetDateTime,
+    pub disconnected_on: Option<OffsetDateTime>,
+    pub close_reason: Option<String>,
+    pub remote_addr: Option<String>,
+    pub protocol_version: Option<String>,
+    pub bytes_in: i64,
+    pub bytes_out: i64,
+    pub messages_in: i64,
+    pub messages_out: i64,
+}
+
+impl ClientSession {
+    /// Sessions that were open at any point since `cutoff`.
+    pub fn since(
+        connection: &mut PgConnection,
+        cutoff: OffsetDateTime,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::client_sessions::dsl::*;
+        let sessions = client_sessions
+            .filter(disconnected_on.is_null().or(disconnected_on.ge(cutoff)))
+            .order(connected_on.asc())
+            .select(ClientSession::as_select())
+            .load(connection)?;
+        Ok(sessions)
+    }
+
+    pub fn get_recent_for_client(
+        connection: &mut PgConnection,
+        client: i64,
+        limit: i64,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::client_sessions::dsl::*;
+        let sessions = client_sessions
+            .filter(client_id.eq(client))
+            .order(connected_on.desc())
+            .limit(limit)
+            .select(ClientSession::as_select())
+            .load(connection)?;
+        Ok(sessions)
+    }
+
+    /// Record the end of a session along with the traffic it carried.
+    pub fn finish(
+        connection: &mut PgConnection,
+        session: i64,
+        reason: &str,
+        traffic: &SessionTraffic,
+    ) -> Result<(), anyhow::Error> {
+        use crate::database::schema::client_sessions::dsl::*;
+        diesel::update(client_sessions.find(session))
+            .set((
+                disconnected_on.eq(OffsetDateTime::now_utc()),
+                close_reason.eq(reason),
+                bytes_in.eq(traffic.bytes_in.load(Ordering::Relaxed) as i64),
+                bytes_out.eq(traffic.bytes_out.load(Ordering::Relaxed) as i64),
+                messages_in.eq(traffic.messages_in.load(Ordering::Relaxed) as i64),
+                messages_out.eq(traffic.messages_out.load(Ordering::Relaxed) as i64),
+            ))
+            .execute(connection)?;
+        Ok(())
+    }
+
+    /// Close sessions left open by a previous run of the server.
+    pub fn close_dangling(connection: &mut PgConnection) -> Result<usize, anyhow::Error> {
+        use crate::database::schema::client_sessions::dsl::*;
+        let closed = diesel::update(client_sessions.filter(disconnected_on.is_null()))
+            .set((
+                disconnected_on.eq(OffsetDateTime::now_utc()),
+                close_reason.eq("Server restarted"),
+            ))
+            .execute(connection)?;
+        Ok(closed)
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::database::schema::client_sessions)]
+pub struct NewClientSession {
+    pub(crate) client_id: i64,
+    pub(crate) remote_addr: Option<String>,
+    pub(crate) protocol_version: Option<String>,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Clone)]
+#[diesel(table_name = crate::database::schema::client_metadata_history)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ClientMetadataChange {
+    pub id: i64,
+    pub client_id: i64,
+    pub field: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+    pub changed_by: String,
+    pub changed_on: OffsetDateTime,
+}
+
+impl ClientMetadataChange {
+    pub fn get_for_client(
+        connection: &mut PgConnection,
+        client: i64,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::client_metadata_history::dsl::*;
+        let changes = client_metadata_history
+            .filter(client_id.eq(client))
+            .order(changed_on.desc())
+            .select(ClientMetadataChange::as_select())
+            .load(connection)?;
+        Ok(changes)
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::database::schema::client_metadata_history)]
+pub struct NewClientMetadataChange {
+    pub(crate) client_id: i64,
+    pub(crate) field: String,
+    pub(crate) old_value: Option<String>,
+    pub(crate) new_value: Option<String>,
+    pub(crate) changed_by: String,
+}
+
+pub const MAX_TAG_LENGTH: usize = 32;
+
+#[derive(Queryable, Selectable, Identifiable, Clone)]
+#[diesel(table_name = crate::database::schema::tags)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Tag {
+    pub id: i64,
+    pub name: String,
+    /// Release channel of the clients carrying the tag, unless they have their own.
+    pub channel: Option<String>,
+}
+
+impl Tag {
+    /// Tags are lowercase so selectors match them regardless of how they were typed.
+    pub fn normalize(name: &str) -> Result<String, String> {
+        let name = name.trim().to_lowercase();
+        if name.is_empty() {
+            return Err("Tag must not be empty.".to_string());
+        }
+        if name.chars().count() > MAX_TAG_LENGTH {
+            return Err(format!("Tag must be at most {} characters.", MAX_TAG_LENGTH));
+        }
+        if !name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+            return Err("Tag may only contain letters, digits, '-' and '_'.".to_string());
+        }
+        Ok(name)
+    }
+
+    pub fn get_for_client(
+        connection: &mut PgConnection,
+        client: i64,
+    ) -> Result<Vec<String>, anyhow::Error> {
+        use crate::database::schema::{client_tags, tags};
+        let names = client_tags::table
+            .inner_join(tags::table)
+            .filter(client_tags::client_id.eq(client))
+            .order(tags::name)
+            .select(tags::name)
+            .load(connection)?;
+        Ok(names)
+    }
+
+    /// Every `(client_id, tag)` pair.
+    pub fn get_assignments(connection: &mut PgConnection) -> Result<Vec<(i64, String)>, anyhow::Error> {
+        use crate::database::schema::{client_tags, tags};
+        let assignments = client_tags::table
+            .inner_join(tags::table)
+            .order(tags::name)
+            .select((client_tags::client_id, tags::name))
+            .load(connection)?;
+        Ok(assignments)
+    }
+
+    /// Tag a client, creating the tag if it is new.
+    pub fn add_to_client(
+        connection: &mut PgConnection,
+        client: i64,
+        tag_name: &str,
+    ) -> Result<(), anyhow::Error> {
+        use crate::database::schema::{client_tags, tags};
+        connection.transaction(|connection| {
+            diesel::insert_into(tags::table)
+                .values(tags::name.eq(tag_name))
+                .on_conflict(tags::name)
+                .do_nothing()
+                .execute(connection)?;
+            let tag: i64 = tags::table
+                .filter(tags::name.eq(tag_name))
+                .select(tags::id)
+                .first(connection)?;
+            diesel::insert_into(client_tags::table)
+                .values((client_tags::client_id.eq(client), client_tags::tag_id.eq(tag)))
+                .on_conflict_do_nothing()
+                .execute(connection)?;
+            Ok(())
+        })
+    }
+
+    pub fn get_all(connection: &mut PgConnection) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::tags::dsl::*;
+        let all = tags.order(name).select(Tag::as_select()).load(connection)?;
+        Ok(all)
+    }
+
+    /// Channels of the tags a client carries.
+    pub fn get_channels_for_client(
+        connection: &mut PgConnection,
+        client: i64,
+    ) -> Result<Vec<String>, anyhow::Error> {
+        use crate::database::schema::{client_tags, tags};
+        let channels = client_tags::table
+            .inner_join(tags::table)
+            .filter(client_tags::client_id.eq(client))
+            .filter(tags::channel.is_not_null())
+            .select(tags::channel.assume_not_null())
+            .load(connection)?;
+        Ok(channels)
+    }
+
+    pub fn set_channel(
+        connection: &mut PgConnection,
+        tag_name: &str,
+        new_channel: Option<&str>,
+    ) -> Result<bool, anyhow::Error> {
+        use crate::database::schema::tags::dsl::*;
+        let updated = diesel::update(tags.filter(name.eq(tag_name)))
+            .set(channel.eq(new_channel))
+            .execute(connection)?;
+        Ok(updated > 0)
+    }
+
+    /// Untag a client, tags nobody uses any more are dropped unless they carry a channel.
+    pub fn remove_from_client(
+        connection: &mut PgConnection,
+        client: i64,
+        tag_name: &str,
+    ) -> Result<(), anyhow::Error> {
+        use crate::database::schema::{client_tags, tags};
+        connection.transaction(|connection| {
+            let tag = tags::table
+                .filter(tags::name.eq(tag_name))
+                .select(tags::id);
+            diesel::delete(client_tags::table
+                .filter(client_tags::client_id.eq(client))
+                .filter(client_tags::tag_id.eq_any(tag)))
+                .execute(connection)?;
+            diesel::delete(tags::table
+                .filter(tags::channel.is_null())
+                .filter(diesel::dsl::not(diesel::dsl::exists(
+                    client_tags::table.filter(client_tags::tag_id.eq(tags::id)),
+                ))))
+                .execute(connection)?;
+            Ok(())
+        })
+    }
+}
+
+pub enum QueueStatus {
+    /// Waiting for the client to connect.
+    Pending,
+    /// Sent, waiting for the client's `Ack`.
+    Delivered,
+    Acked,
+    /// Not delivered before its TTL ran out.
+    Expired,
+    /// Never acknowledged after the maximum number of attempts.
+    Failed,
+}
+
+impl QueueStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QueueStatus::Pending => "Pending",
+            QueueStatus::Delivered => "Delivered",
+            QueueStatus::Acked => "Acked",
+            QueueStatus::Expired => "Expired",
+            QueueStatus::Failed => "Failed",
+        }
+    }
+
+    const OPEN: [&'static str; 2] = ["Pending", "Delivered"];
+}
+
+#[derive(Queryable, Selectable, Identifiable, Clone)]
+#[diesel(table_name = crate::database::schema::queued_commands)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct QueuedCommand {
+    pub id: i64,
+    pub client_id: i64,
+    /// Full command text, e.g. `Craft 4 minecraft:stick 64`.
+    pub command: String,
+    pub status: String,
+    pub attempts: i32,
+    pub queued_on: OffsetDateTime,
+    pub expires_on: OffsetDateTime,
+    pub last_sent_on: Option<OffsetDateTime>,
+    pub acked_on: Option<OffsetDateTime>,
+}
+
+impl QueuedCommand {
+    pub fn get_recent_for_client(
+        connection: &mut PgConnection,
+        client: i64,
+        limit: i64,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::queued_commands::dsl::*;
+        let entries = queued_commands
+            .filter(client_id.eq(client))
+            .order(id.desc())
+            .limit(limit)
+            .select(QueuedCommand::as_select())
+            .load(connection)?;
+        Ok(entries)
+    }
+
+    /// Unexpired commands the client has not acknowledged yet, oldest first.
+    pub fn get_unacked_for_client(
+        connection: &mut PgConnection,
+        client: i64,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::queued_commands::dsl::*;
+        let entries = queued_commands
+            .filter(client_id.eq(client))
+            .filter(status.eq_any(QueueStatus::OPEN))
+            .filter(expires_on.gt(OffsetDateTime::now_utc()))
+            .order(id.asc())
+            .select(QueuedCommand::as_select())
+            .load(connection)?;
+        Ok(entries)
+    }
+
+    /// Delivered commands last sent before `cutoff` that are still waiting for an ack.
+    pub fn get_overdue(
+        connection: &mut PgConnection,
+        cutoff: OffsetDateTime,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::queued_commands::dsl::*;
+        let entries = queued_commands
+            .filter(status.eq(QueueStatus::Delivered.as_str()))
+            .filter(last_sent_on.lt(cutoff))
+            .order(id.asc())
+            .select(QueuedCommand::as_select())
+            .load(connection)?;
+        Ok(entries)
+    }
+
+    pub fn mark_sent(connection: &mut PgConnection, entries: &[i64]) -> Result<(), anyhow::Error> {
+        use crate::database::schema::queued_commands::dsl::*;
+        diesel::update(queued_commands.filter(id.eq_any(entries)))
+            .set((
+                status.eq(QueueStatus::Delivered.as_str()),
+                attempts.eq(attempts + 1),
+                last_sent_on.eq(OffsetDateTime::now_utc()),
+            ))
+            .execute(connection)?;
+        Ok(())
+    }
+
+    pub fn mark_failed(connection: &mut PgConnection, entries: &[i64]) -> Result<(), anyhow::Error> {
+        use crate::database::schema::queued_commands::dsl::*;
+        diesel::update(queued_commands.filter(id.eq_any(entries)))
+            .set(status.eq(QueueStatus::Failed.as_str()))
+            .execute(connection)?;
+        Ok(())
+    }
+
+    /// Record the client's acknowledgement, false if it did not match an open command of theirs.
+    pub fn ack(connection: &mut PgConnection, client: i64, entry: i64) -> Result<bool, anyhow::Error> {
+        use crate::database::schema::queued_commands::dsl::*;
+        let updated = diesel::update(queued_commands
+            .find(entry)
+            .filter(client_id.eq(client))
+            .filter(status.eq_any(QueueStatus::OPEN)))
+            .set((
+                status.eq(QueueStatus::Acked.as_str()),
+                acked_on.eq(OffsetDateTime::now_utc()),
+            ))
+            .execute(connection)?;
+        Ok(updated > 0)
+    }
+
+    /// Give up on open commands whose TTL ran out.
+    pub fn expire(connection: &mut PgConnection) -> Result<usize, anyhow::Error> {
+        use crate::database::schema::queued_commands::dsl::*;
+        let expired = diesel::update(queued_commands
+            .filter(status.eq_any(QueueStatus::OPEN))
+            .filter(expires_on.le(OffsetDateTime::now_utc())))
+            .set(status.eq(QueueStatus::Expired.as_str()))
+            .execute(connection)?;
+        Ok(expired)
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::database::schema::queued_commands)]
+pub struct NewQueuedCommand {
+    pub(crate) client_id: i64,
+    pub(crate) command: String,
+    pub(crate) expires_on: OffsetDateTime,
+}
+
+pub enum ExecStatus {
+    /// Sent to the client, no response yet.
+    Running,
+    Done,
+    /// The snippet failed to compile or raised an error.
+    Error,
+    /// The client was offline or the send failed.
+    Undelivered,
+    /// No response within `exec::EXEC_TIMEOUT`.
+    TimedOut,
+}
+
+impl ExecStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExecStatus::Running => "Running",
+            ExecStatus::Done => "Done",
+            ExecStatus::Error => "Error",
+            ExecStatus::Undelivered => "Undelivered",
+            ExecStatus::TimedOut => "Timed out",
+        }
+    }
+}
+
+#[derive(Queryable, Selectable, Identifiable, Clone)]
+#[diesel(table_name = crate::database::schema::executions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Execution {
+    pub id: i64,
+    pub client_id: i64,
+    pub code: String,
+    pub status: String,
+    /// Everything the snippet printed.
+    pub output: Option<String>,
+    /// Its return values.
+    pub result: Option<String>,
+    pub error: Option<String>,
+    pub duration_ms: Option<i64>,
+    pub requested_on: OffsetDateTime,
+    pub completed_on: Option<OffsetDateTime>,
+}
+
+impl Execution {
+    pub fn get_recent_for_client(
+        connection: &mut PgConnection,
+        client: i64,
+        limit: i64,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::executions::dsl::*;
+        let runs = executions
+            .filter(client_id.eq(client))
+            .order(requested_on.desc())
+            .limit(limit)
+            .select(Execution::as_select())
+            .load(connection)?;
+        Ok(runs)
+    }
+
+    /// Store the client's response to a running execution, false if there was none to complete.
+    pub fn complete(
+        connection: &mut PgConnection,
+        client: i64,
+        execution: i64,
+        new_status: ExecStatus,
+        response: &ExecResponse,
+    ) -> Result<bool, anyhow::Error> {
+        use crate::database::schema::executions::dsl::*;
+        let updated = diesel::update(executions
+            .find(execution)
+            .filter(client_id.eq(client))
+            .filter(status.eq(ExecStatus::Running.as_str())))
+            .set((
+                status.eq(new_status.as_str()),
+                output.eq(&response.output),
+                result.eq(&response.result),
+                error.eq(&response.error),
+                duration_ms.eq(response.duration_ms),
+                completed_on.eq(OffsetDateTime::now_utc()),
+            ))
+            .execute(connection)?;
+        Ok(updated > 0)
+    }
+
+    pub fn set_status(
+        connection: &mut PgConnection,
+        runs: &[i64],
+        new_status: ExecStatus,
+    ) -> Result<(), anyhow::Error> {
+        use crate::database::schema::executions::dsl::*;
+        diesel::update(executions.filter(id.eq_any(runs)))
+            .set(status.eq(new_status.as_str()))
+            .execute(connection)?;
+        Ok(())
+    }
+
+    /// Time out executions that have been running since before `cutoff`.
+    pub fn expire(connection: &mut PgConnection, cutoff: OffsetDateTime) -> Result<usize, anyhow::Error> {
+        use crate::database::schema::executions::dsl::*;
+        let expired = diesel::update(executions
+            .filter(status.eq(ExecStatus::Running.as_str()))
+            .filter(requested_on.lt(cutoff)))
+            .set(status.eq(ExecStatus::TimedOut.as_str()))
+            .execute(connection)?;
+        Ok(expired)
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::database::schema::executions)]
+pub struct NewExecution {
+    pub(crate) client_id: i64,
+    pub(crate) code: String,
+}
+
+/// JSON body of `Response Exec <id> <json>`.
+#[derive(Deserialize)]
+pub struct ExecResponse {
+    pub ok: bool,
+    #[serde(default)]
+    pub output: Option<String>,
+    #[serde(default)]
+    pub result: Option<String>,
+    #[serde(default)]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub duration_ms: Option<i64>,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Clone)]
+#[diesel(table_name = crate::database::schema::shell_sessions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ShellSession {
+    pub id: i64,
+    pub client_id: i64,
+    pub started_on: OffsetDateTime,
+    pub ended_on: Option<OffsetDateTime>,
+    pub close_reason: Option<String>,
+}
+
+impl ShellSession {
+    pub fn start(connection: &mut PgConnection, client: i64) -> Result<i64, anyhow::Error> {
+        use crate::database::schema::shell_sessions::dsl::*;
+        let session = diesel::insert_into(shell_sessions)
+            .values(client_id.eq(client))
+            .returning(id)
+            .get_result(connection)?;
+        Ok(session)
+    }
+
+    pub fn get_by_id(connection: &mut PgConnection, session: i64) -> Result<Option<Self>, anyhow::Error> {
+        use crate::database::schema::shell_sessions::dsl::*;
+        let found = shell_sessions
+            .find(session)
+            .select(ShellSession::as_select())
+            .first(connection)
+            .optional()?;
+        Ok(found)
+    }
+
+    pub fn get_recent_for_client(
+        connection: &mut PgConnection,
+        client: i64,
+        limit: i64,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::shell_sessions::dsl::*;
+        let sessions = shell_sessions
+            .filter(client_id.eq(client))
+            .order(started_on.desc())
+            .limit(limit)
+            .select(ShellSession::as_select())
+            .load(connection)?;
+        Ok(sessions)
+    }
+
+    pub fn finish(connection: &mut PgConnection, session: i64, reason: &str) -> Result<(), anyhow::Error> {
+        use crate::database::schema::shell_sessions::dsl::*;
+        diesel::update(shell_sessions.find(session))
+            .set((ended_on.eq(OffsetDateTime::now_utc()), close_reason.eq(reason)))
+            .execute(connection)?;
+        Ok(())
+    }
+}
+
+/// Recorded traffic of a shell session, `direction` is `input` for keystrokes and `output` for
+/// what the client sent back.
+#[derive(Queryable, Selectable, Identifiable, Serialize, Clone)]
+#[diesel(table_name = crate::database::schema::shell_events)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ShellEvent {
+    #[serde(skip)]
+    pub id: i64,
+    #[serde(skip)]
+    pub session_id: i64,
+    pub offset_ms: i64,
+    pub direction: String,
+    pub data: String,
+}
+
+impl ShellEvent {
+    pub fn get_for_session(connection: &mut PgConnection, session: i64) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::shell_events::dsl::*;
+        let events = shell_events
+            .filter(session_id.eq(session))
+            .order(id.asc())
+            .select(ShellEvent::as_select())
+            .load(connection)?;
+        Ok(events)
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::database::schema::shell_events)]
+pub struct NewShellEvent<'a> {
+    pub(crate) session_id: i64,
+    pub(crate) offset_ms: i64,
+    pub(crate) direction: &'a str,
+    pub(crate) data: &'a str,
+}
+
+/// What a client advertised in the `Hello` of its latest connection, see `crate::handshake`.
+#[derive(Queryable, Selectable, Insertable, AsChangeset, Clone)]
+#[diesel(table_name = crate::database::schema::client_capabilities)]
+#[diesel(check_for_backend(diesel::pg::Pg), treat_none_as_null = true)]
+pub struct Capabilities {
+    pub client_id: i64,
+    pub protocol_version: i32,
+    /// `build` of the manifest the client was last updated from, see `crate::manifest`.
+    pub client_build: Option<String>,
+    pub components: Vec<Option<String>>,
+    pub free_memory: i64,
+    pub total_memory: i64,
+    pub reported_on: OffsetDateTime,
+}
+
+impl Capabilities {
+    pub fn has_component(&self, name: &str) -> bool {
+        self.components.iter().any(|component| component.as_deref() == Some(name))
+    }
+
+    pub fn get_for_client(connection: &mut PgConnection, client: i64) -> Result<Option<Self>, anyhow::Error> {
+        use crate::database::schema::client_capabilities::dsl::*;
+        let found = client_capabilities
+            .find(client)
+            .select(Capabilities::as_select())
+            .first(connection)
+            .optional()?;
+        Ok(found)
+    }
+
+    pub fn get_for_clients(
+        connection: &mut PgConnection,
+        clients: &[i64],
+    ) -> Result<HashMap<i64, Self>, anyhow::Error> {
+        use crate::database::schema::client_capabilities::dsl::*;
+        let found = client_capabilities
+            .filter(client_id.eq_any(clients))
+            .select(Capabilities::as_select())
+            .load(connection)?
+            .into_iter()
+            .map(|capabilities: Capabilities| (capabilities.client_id, capabilities))
+            .collect();
+        Ok(found)
+    }
+
+    /// Replace what was stored for the client.
+    pub fn store(&self, connection: &mut PgConnection) -> Result<(), anyhow::Error> {
+        use crate::database::schema::client_capabilities::dsl::*;
+        diesel::insert_into(client_capabilities)
+            .values(self)
+            .on_conflict(client_id)
+            .do_update()
+            .set(self)
+            .execute(connection)?;
+        Ok(())
+    }
+}
+
+/// Release channels, from the most to the least tested.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Channel {
+    Stable,
+    Beta,
+    Dev,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 3] = [Channel::Stable, Channel::Beta, Channel::Dev];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Channel::Stable => "stable",
+            Channel::Beta => "beta",
+            Channel::Dev => "dev",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Channel> {
+        Channel::ALL.into_iter().find(|channel| channel.as_str().eq_ignore_ascii_case(name.trim()))
+    }
+}
+
+/// A build of the Lua client, `build` being the build of its manifest, see `crate::manifest`.
+#[derive(Queryable, Selectable, Identifiable, Clone)]
+#[diesel(table_name = crate::database::schema::releases)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Release {
+    pub id: i64,
+    pub version: String,
+    pub channel: String,
+    pub build: String,
+    pub notes: Option<String>,
+    /// Share of the channel's clients the release is offered to.
+    pub rollout_percent: i32,
+    pub created_on: OffsetDateTime,
+    /// Share of its updates that may fail before the rollout is halted, see `crate::releases`.
+    pub max_failure_percent: i32,
+    /// Set once too many updates failed, halted releases are no longer offered.
+    pub halted_on: Option<OffsetDateTime>,
+    pub halt_reason: Option<String>,
+}
+
+impl Release {
+    pub fn get_all(connection: &mut PgConnection) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::releases::dsl::*;
+        let all = releases.order(id.desc()).select(Release::as_select()).load(connection)?;
+        Ok(all)
+    }
+
+    pub fn get_by_id(connection: &mut PgConnection, release: i64) -> Result<Option<Self>, anyhow::Error> {
+        use crate::database::schema::releases::dsl::*;
+        let found = releases
+            .find(release)
+            .select(Release::as_select())
+            .first(connection)
+            .optional()?;
+        Ok(found)
+    }
+
+    /// Releases of a channel, newest first.
+    pub fn get_for_channel(
+        connection: &mut PgConnection,
+        release_channel: Channel,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::releases::dsl::*;
+        let found = releases
+            .filter(channel.eq(release_channel.as_str()))
+            .order(id.desc())
+            .select(Release::as_select())
+            .load(connection)?;
+        Ok(found)
+    }
+
+    /// Newest release with the given build.
+    pub fn get_by_build(connection: &mut PgConnection, release_build: &str) -> Result<Option<Self>, anyhow::Error> {
+        use crate::database::schema::releases::dsl::*;
+        let found = releases
+            .filter(build.eq(release_build))
+            .order(id.desc())
+            .select(Release::as_select())
+            .first(connection)
+            .optional()?;
+        Ok(found)
+    }
+
+    /// Store a release along with its files, keyed by install path. `None` when the version was
+    /// already released.
+    pub fn create(
+        connection: &mut PgConnection,
+        release: &NewRelease,
+        files: &[(String, Vec<u8>)],
+    ) -> Result<Option<i64>, anyhow::Error> {
+        use crate::database::schema::{release_files, releases};
+        connection.transaction(|connection| {
+            let release_id = diesel::insert_into(releases::table)
+                .values(release)
+                .on_conflict(releases::version)
+                .do_nothing()
+                .returning(releases::id)
+                .get_result(connection)
+                .optional()?;
+            let Some(release_id) = release_id else {
+                return Ok(None);
+            };
+            let rows: Vec<_> = files
+                .iter()
+                .map(|(path, contents)| (
+                    release_files::release_id.eq(release_id),
+                    release_files::path.eq(path),
+                    release_files::contents.eq(contents),
+                ))
+                .collect();
+            diesel::insert_into(release_files::table).values(&rows).execute(connection)?;
+            Ok(Some(release_id))
+        })
+    }
+
+    pub fn set_rollout(connection: &mut PgConnection, release: i64, percent: i32) -> Result<bool, anyhow::Error> {
+        use crate::database::schema::releases::dsl::*;
+        let updated = diesel::update(releases.find(release))
+            .set(rollout_percent.eq(percent))
+            .execute(connection)?;
+        Ok(updated > 0)
+    }
+
+    pub fn halt(connection: &mut PgConnection, release: i64, reason: &str) -> Result<(), anyhow::Error> {
+        use crate::database::schema::releases::dsl::*;
+        diesel::update(releases.find(release))
+            .set((halted_on.eq(OffsetDateTime::now_utc()), halt_reason.eq(reason)))
+            .execute(connection)?;
+        Ok(())
+    }
+
+    pub fn resume(connection: &mut PgConnection, release: i64) -> Result<bool, anyhow::Error> {
+        use crate::database::schema::releases::dsl::*;
+        let updated = diesel::update(releases.find(release))
+            .set((halted_on.eq(None::<OffsetDateTime>), halt_reason.eq(None::<String>)))
+            .execute(connection)?;
+        Ok(updated > 0)
+    }
+
+    /// Every file of the release as `(path, contents)`.
+    pub fn files(&self, connection: &mut PgConnection) -> Result<Vec<(String, Vec<u8>)>, anyhow::Error> {
+        use crate::database::schema::release_files::dsl::*;
+        let files = release_files
+            .filter(release_id.eq(self.id))
+            .select((path, contents))
+            .load(connection)?;
+        Ok(files)
+    }
+
+    pub fn file(&self, connection: &mut PgConnection, file_path: &str) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        use crate::database::schema::release_files::dsl::*;
+        let file = release_files
+            .filter(release_id.eq(self.id))
+            .filter(path.eq(file_path))
+            .select(contents)
+            .first(connection)
+            .optional()?;
+        Ok(file)
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::database::schema::releases)]
+pub struct NewRelease {
+    pub version: String,
+    pub channel: String,
+    pub build: String,
+    pub notes: Option<String>,
+    pub rollout_percent: i32,
+    pub max_failure_percent: i32,
+}
+
+pub enum UpdateStatus {
+    /// `Update` was sent, waiting for the client to come back with the release's build.
+    Sent,
+    /// The client came back with the release, its logs are watched for errors for a while.
+    Installed,
+    Healthy,
+    /// The client did not come back in time or reported errors after updating.
+    Failed,
+}
+
+impl UpdateStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UpdateStatus::Sent => "Sent",
+            UpdateStatus::Installed => "Installed",
+            UpdateStatus::Healthy => "Healthy",
+            UpdateStatus::Failed => "Failed",
+        }
+    }
+}
+
+/// An `Update` sent to a client for a release.
+#[derive(Queryable, Selectable, Identifiable, Clone)]
+#[diesel(table_name = crate::database::schema::client_updates)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ClientUpdate {
+    pub id: i64,
+    pub client_id: i64,
+    pub release_id: i64,
+    pub status: String,
+    pub sent_on: OffsetDateTime,
+    pub completed_on: Option<OffsetDateTime>,
+    pub failure: Option<String>,
+}
+
+impl ClientUpdate {
+    pub fn record(connection: &mut PgConnection, client: i64, release: i64) -> Result<(), anyhow::Error> {
+        use crate::database::schema::client_updates::dsl::*;
+        diesel::insert_into(client_updates)
+            .values((client_id.eq(client), release_id.eq(release)))
+            .execute(connection)?;
+        Ok(())
+    }
+
+    /// Whether the release was already sent to the client since `cutoff` without it coming back.
+    pub fn sent_since(
+        connection: &mut PgConnection,
+        client: i64,
+        release: i64,
+        cutoff: OffsetDateTime,
+    ) -> Result<bool, anyhow::Error> {
+        use crate::database::schema::client_updates::dsl::*;
+        let sent = diesel::select(diesel::dsl::exists(
+            client_updates
+                .filter(client_id.eq(client))
+                .filter(release_id.eq(release))
+                .filter(status.eq_any([UpdateStatus::Sent.as_str(), UpdateStatus::Failed.as_str()]))
+                .filter(sent_on.ge(cutoff)),
+        ))
+            .get_result(connection)?;
+        Ok(sent)
+    }
+
+    /// Complete the client's pending updates to releases with the build it now reports.
+    pub fn mark_installed(connection: &mut PgConnection, client: i64, build: &str) -> Result<usize, anyhow::Error> {
+        use crate::database::schema::{client_updates, releases};
+        let installed = diesel::update(client_updates::table
+            .filter(client_updates::client_id.eq(client))
+            .filter(client_updates::status.eq(UpdateStatus::Sent.as_str()))
+            .filter(client_updates::release_id.eq_any(
+                releases::table.filter(releases::build.eq(build)).select(releases::id),
+            )))
+            .set((
+                client_updates::status.eq(UpdateStatus::Installed.as_str()),
+                client_updates::completed_on.eq(OffsetDateTime::now_utc()),
+            ))
+            .execute(connection)?;
+        Ok(installed)
+    }
+
+    /// Updates in `current` status since before `cutoff`, by when they were sent for `Sent` and
+    /// when they were installed otherwise.
+    pub fn get_due(
+        connection: &mut PgConnection,
+        current: UpdateStatus,
+        cutoff: OffsetDateTime,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::client_updates::dsl::*;
+        let query = client_updates.filter(status.eq(current.as_str())).into_boxed();
+        let query = match current {
+            UpdateStatus::Sent => query.filter(sent_on.lt(cutoff)),
+            _ => query.filter(completed_on.lt(cutoff)),
+        };
+        let due = query.select(ClientUpdate::as_select()).load(connection)?;
+        Ok(due)
+    }
+
+    pub fn get_for_release(connection: &mut PgConnection, release: i64) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::client_updates::dsl::*;
+        let updates = client_updates
+            .filter(release_id.eq(release))
+            .order(sent_on.desc())
+            .select(ClientUpdate::as_select())
+            .load(connection)?;
+        Ok(updates)
+    }
+
+    /// Number of updates per `(release, status)`.
+    pub fn count_by_release(connection: &mut PgConnection) -> Result<Vec<(i64, String, i64)>, anyhow::Error> {
+        use crate::database::schema::client_updates::dsl::*;
+        let counts = client_updates
+            .group_by((release_id, status))
+            .select((release_id, status, diesel::dsl::count_star()))
+            .load(connection)?;
+        Ok(counts)
+    }
+
+    pub fn set_status(
+        &self,
+        connection: &mut PgConnection,
+        new_status: UpdateStatus,
+        new_failure: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        use crate::database::schema::client_updates::dsl::*;
+        diesel::update(self)
+            .set((status.eq(new_status.as_str()), failure.eq(new_failure)))
+            .execute(connection)?;
+        Ok(())
+    }
+
+    pub fn get_recent(connection: &mut PgConnection, limit: i64) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::client_updates::dsl::*;
+        let updates = client_updates
+            .order(sent_on.desc())
+            .limit(limit)
+            .select(ClientUpdate::as_select())
+            .load(connection)?;
+        Ok(updates)
+    }
+}
+
+#[derive(Queryable, Selectable, Identifiable, Clone)]
+#[diesel(table_name = crate::database::schema::schedules)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Schedule {
+    pub id: i64,
+    pub name: String,
+    /// See `crate::cron`.
+    pub cron: String,
+    /// Clients the command is sent to, see `crate::selector`.
+    pub selector: String,
+    pub command: String,
+    pub args: String,
+    /// Keep the command for offline clients this long, e.g. `6h`.
+    pub queue_ttl: Option<String>,
+    pub paused: bool,
+    pub next_run_on: OffsetDateTime,
+    pub created_on: OffsetDateTime,
+}
+
+impl Schedule {
+    pub fn get_all(connection: &mut PgConnection) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::schedules::dsl::*;
+        let all = schedules.order(id.asc()).select(Schedule::as_select()).load(connection)?;
+        Ok(all)
+    }
+
+    pub fn get_by_id(connection: &mut PgConnection, schedule: i64) -> Result<Option<Self>, anyhow::Error> {
+        use crate::database::schema::schedules::dsl::*;
+        let found = schedules
+            .find(schedule)
+            .select(Schedule::as_select())
+            .first(connection)
+            .optional()?;
+        Ok(found)
+    }
+
+    /// Running schedules that were due to fire at `now`, most overdue first.
+    pub fn get_due(connection: &mut PgConnection, now: OffsetDateTime) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::schedules::dsl::*;
+        let due = schedules
+            .filter(paused.eq(false))
+            .filter(next_run_on.le(now))
+            .order(next_run_on.asc())
+            .select(Schedule::as_select())
+            .load(connection)?;
+        Ok(due)
+    }
+
+    pub fn create(connection: &mut PgConnection, schedule: &NewSchedule) -> Result<i64, anyhow::Error> {
+        use crate::database::schema::schedules::dsl::*;
+        let created = diesel::insert_into(schedules)
+            .values(schedule)
+            .returning(id)
+            .get_result(connection)?;
+        Ok(created)
+    }
+
+    pub fn set_next_run(
+        connection: &mut PgConnection,
+        schedule: i64,
+        next_run: OffsetDateTime,
+    ) -> Result<(), anyhow::Error> {
+        use crate::database::schema::schedules::dsl::*;
+        diesel::update(schedules.find(schedule))
+            .set(next_run_on.eq(next_run))
+            .execute(connection)?;
+        Ok(())
+    }
+
+    /// Pause or resume a schedule, resuming with its next run from now on. False if there is no
+    /// such schedule.
+    pub fn set_paused(
+        connection: &mut PgConnection,
+        schedule: i64,
+        pause: bool,
+        next_run: OffsetDateTime,
+    ) -> Result<bool, anyhow::Error> {
+        use crate::database::schema::schedules::dsl::*;
+        let updated = diesel::update(schedules.find(schedule))
+            .set((paused.eq(pause), next_run_on.eq(next_run)))
+            .execute(connection)?;
+        Ok(updated > 0)
+    }
+
+    /// Delete a schedule along with its runs, false if there was none.
+    pub fn delete(connection: &mut PgConnection, schedule: i64) -> Result<bool, anyhow::Error> {
+        use crate::database::schema::schedules::dsl::*;
+        let deleted = diesel::delete(schedules.find(schedule)).execute(connection)?;
+        Ok(deleted > 0)
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::database::schema::schedules)]
+pub struct NewSchedule {
+    pub name: String,
+    pub cron: String,
+    pub selector: String,
+    pub command: String,
+    pub args: String,
+    pub queue_ttl: Option<String>,
+    pub next_run_on: OffsetDateTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunStatus {
+    /// Every matched client got the command or has it queued.
+    Sent,
+    /// Some matched clients did not get it.
+    Partial,
+    /// No client got it, or the schedule could not be run at all.
+    Failed,
+    /// The selector matched no clients.
+    NoTargets,
+}
+
+impl RunStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunStatus::Sent => "Sent",
+            RunStatus::Partial => "Partial",
+            RunStatus::Failed => "Failed",
+            RunStatus::NoTargets => "No targets",
+        }
+    }
+}
+
+#[derive(Queryable, Selectable, Identifiable, Clone)]
+#[diesel(table_name = crate::database::schema::schedule_runs)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ScheduleRun {
+    pub id: i64,
+    pub schedule_id: i64,
+    pub status: String,
+    /// Clients the selector matched.
+    pub matched: i32,
+    pub sent: i32,
+    /// Clients that did not get the command now but will when they reconnect.
+    pub queued: i32,
+    pub failed: i32,
+    pub error: Option<String>,
+    pub started_on: OffsetDateTime,
+}
+
+impl ScheduleRun {
+    pub fn get_recent(connection: &mut PgConnection, limit: i64) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::schedule_runs::dsl::*;
+        let runs = schedule_runs
+            .order(started_on.desc())
+            .limit(limit)
+            .select(ScheduleRun::as_select())
+            .load(connection)?;
+        Ok(runs)
+    }
+
+    pub fn record(connection: &mut PgConnection, run: &NewScheduleRun) -> Result<Self, anyhow::Error> {
+        use crate::database::schema::schedule_runs::dsl::*;
+        let recorded = diesel::insert_into(schedule_runs)
+            .values(run)
+            .returning(ScheduleRun::as_returning())
+            .get_result(connection)?;
+        Ok(recorded)
+    }
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = crate::database::schema::schedule_runs)]
+pub struct NewScheduleRun {
+    pub schedule_id: i64,
+    pub status: String,
+    pub matched: i32,
+    pub sent: i32,
+    pub queued: i32,
+    pub failed: i32,
+    pub error: Option<String>,
+    pub started_on: OffsetDateTime,
+}
+
+/// A stored server setting, see `crate::settings`.
+#[derive(Queryable, Selectable, Clone)]
+#[diesel(table_name = crate::database::schema::settings)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Setting {
+    pub key: String,
+    pub value: String,
+    pub updated_on: OffsetDateTime,
+}
+
+impl Setting {
+    pub fn get_all(connection: &mut PgConnection) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::settings::dsl::*;
+        let all = settings.order(key.asc()).select(Setting::as_select()).load(connection)?;
+        Ok(all)
+    }
+
+    /// Store `(key, value)` pairs, replacing the previous values.
+    pub fn set_all(connection: &mut PgConnection, values: &[(&str, String)]) -> Result<(), anyhow::Error> {
+        use crate::database::schema::settings::dsl::*;
+        let now = OffsetDateTime::now_utc();
+        connection.transaction(|connection| {
+            for (setting, new_value) in values {
+                diesel::insert_into(settings)
+                    .values((key.eq(setting), value.eq(new_value), updated_on.eq(now)))
+                    .on_conflict(key)
+                    .do_update()
+                    .set((value.eq(new_value), updated_on.eq(now)))
+                    .execute(connection)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// An account of someone running the server, see `crate::users`.
+#[derive(Queryable, Selectable, Identifiable, Clone)]
+#[diesel(table_name = crate::database::schema::users)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct User {
+    pub id: i64,
+    pub username: String,
+    /// Argon2 in the PHC string format.
+    pub password_hash: String,
+    pub created_on: OffsetDateTime,
+    pub password_changed_on: OffsetDateTime,
+}
+
+impl User {
+    pub fn get_all(connection: &mut PgConnection) -> Result<Vec<Self>, anyhow::Error> {
+        use crate::database::schema::users::dsl::*;
+        let all = users.order(username.asc()).select(User::as_select()).load(connection)?;
+        Ok(all)
+    }
+
+    pub fn get_by_username(connection: &mut PgConnection, name: &str) -> Result<Option<Self>, anyhow::Error> {
+        use crate::database::schema::users::dsl::*;
+        let user = users
+            .filter(username.eq(name))
+            .select(User::as_select())
+            .first(connection)
+            .optional()?;
+        Ok(user)
+    }
+
+    pub fn create(connection: &mut PgConnection, name: &str, hash: &str) -> Result<i64, anyhow::Error> {
+        use crate::database::schema::users::dsl::*;
+        let created = diesel::insert_into(users)
+            .values((username.eq(name), password_hash.eq(hash)))
+            .returning(id)
+            .get_result(connection)?;
+        Ok(created)
+    }
+
+    pub fn set_password_hash(&self, connection: &mut PgConnection, hash: &str) -> Result<(), anyhow::Error> {
+        use crate::database::schema::users::dsl::*;
+        diesel::update(self)
+            .set((password_hash.eq(hash), password_changed_on.eq(OffsetDateTime::now_utc())))
+            .execute(connection)?;
+        Ok(())
+    }
+}
@@ -1,11 +1,71 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    client_capabilities (client_id) {
+        client_id -> Int8,
+        protocol_version -> Int4,
+        client_build -> Nullable<Text>,
+        components -> Array<Nullable<Text>>,
+        free_memory -> Int8,
+        total_memory -> Int8,
+        reported_on -> Timestamptz,
+    }
+}
+
 diesel::table! {
     client_logs (id) {
         id -> Int8,
         client_id -> Int8,
         log_time -> Timestamptz,
         log_message -> Text,
+        log_level -> Text,
+    }
+}
+
+diesel::table! {
+    client_metadata_history (id) {
+        id -> Int8,
+        client_id -> Int8,
+        field -> Text,
+        old_value -> Nullable<Text>,
+        new_value -> Nullable<Text>,
+        changed_by -> Text,
+        changed_on -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    client_sessions (id) {
+        id -> Int8,
+        client_id -> Int8,
+        connected_on -> Timestamptz,
+        disconnected_on -> Nullable<Timestamptz>,
+        close_reason -> Nullable<Text>,
+        remote_addr -> Nullable<Text>,
+        protocol_version -> Nullable<Text>,
+        bytes_in -> Int8,
+        bytes_out -> Int8,
+        messages_in -> Int8,
+        messages_out -> Int8,
+    }
+}
+
+diesel::table! {
+    client_tags (client_id, tag_id) {
+        client_id -> Int8,
+        tag_id -> Int8,
+    }
+}
+
+diesel::table! {
+    client_updates (id) {
+        id -> Int8,
+        client_id -> Int8,
+        release_id -> Int8,
+        status -> Text,
+        sent_on -> Timestamptz,
+        completed_on -> Nullable<Timestamptz>,
+        failure -> Nullable<Text>,
     }
 }
 
@@ -21,12 +81,246 @@ diesel::table! {
         api_key -> Nullable<Text>,
         created_on -> Timestamp,
         accessed_on -> Nullable<Timestamp>,
+        allow_exec -> Bool,
+        channel -> Nullable<Text>,
+        log_level -> Text,
+        disabled_modules -> Array<Nullable<Text>>,
+    }
+}
+
+diesel::table! {
+    craft_jobs (id) {
+        id -> Int8,
+        threshold_id -> Nullable<Int8>,
+        client_id -> Int8,
+        item_name -> Text,
+        amount -> Int8,
+        status -> Text,
+        message -> Nullable<Text>,
+        requested_on -> Timestamptz,
+        updated_on -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    executions (id) {
+        id -> Int8,
+        client_id -> Int8,
+        code -> Text,
+        status -> Text,
+        output -> Nullable<Text>,
+        result -> Nullable<Text>,
+        error -> Nullable<Text>,
+        duration_ms -> Nullable<Int8>,
+        requested_on -> Timestamptz,
+        completed_on -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    item_samples (id) {
+        id -> Int8,
+        client_id -> Int8,
+        sampled_on -> Timestamptz,
+        item_name -> Text,
+        label -> Text,
+        amount -> Int8,
+        craftable -> Bool,
+    }
+}
+
+diesel::table! {
+    item_thresholds (id) {
+        id -> Int8,
+        client_id -> Int8,
+        item_name -> Text,
+        min_amount -> Int8,
+        enabled -> Bool,
+        failure_count -> Int4,
+        flagged -> Bool,
+        created_on -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    power_samples (id) {
+        id -> Int8,
+        client_id -> Int8,
+        sampled_on -> Timestamptz,
+        stored -> Float8,
+        capacity -> Float8,
+        draw -> Float8,
+    }
+}
+
+diesel::table! {
+    queued_commands (id) {
+        id -> Int8,
+        client_id -> Int8,
+        command -> Text,
+        status -> Text,
+        attempts -> Int4,
+        queued_on -> Timestamptz,
+        expires_on -> Timestamptz,
+        last_sent_on -> Nullable<Timestamptz>,
+        acked_on -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    release_files (release_id, path) {
+        release_id -> Int8,
+        path -> Text,
+        contents -> Bytea,
+    }
+}
+
+diesel::table! {
+    releases (id) {
+        id -> Int8,
+        version -> Text,
+        channel -> Text,
+        build -> Text,
+        notes -> Nullable<Text>,
+        rollout_percent -> Int4,
+        created_on -> Timestamptz,
+        max_failure_percent -> Int4,
+        halted_on -> Nullable<Timestamptz>,
+        halt_reason -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    schedule_runs (id) {
+        id -> Int8,
+        schedule_id -> Int8,
+        status -> Text,
+        matched -> Int4,
+        sent -> Int4,
+        queued -> Int4,
+        failed -> Int4,
+        error -> Nullable<Text>,
+        started_on -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    schedules (id) {
+        id -> Int8,
+        name -> Text,
+        cron -> Text,
+        selector -> Text,
+        command -> Text,
+        args -> Text,
+        queue_ttl -> Nullable<Text>,
+        paused -> Bool,
+        next_run_on -> Timestamptz,
+        created_on -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    settings (key) {
+        key -> Text,
+        value -> Text,
+        updated_on -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    shell_events (id) {
+        id -> Int8,
+        session_id -> Int8,
+        offset_ms -> Int8,
+        direction -> Text,
+        data -> Text,
+    }
+}
+
+diesel::table! {
+    shell_sessions (id) {
+        id -> Int8,
+        client_id -> Int8,
+        started_on -> Timestamptz,
+        ended_on -> Nullable<Timestamptz>,
+        close_reason -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    tags (id) {
+        id -> Int8,
+        name -> Text,
+        channel -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    telemetry_rollups (client_id, metric, resolution, bucket) {
+        client_id -> Int8,
+        metric -> Text,
+        resolution -> Int4,
+        bucket -> Timestamptz,
+        min_value -> Float8,
+        max_value -> Float8,
+        avg_value -> Float8,
+        sample_count -> Int8,
+    }
+}
+
+diesel::table! {
+    users (id) {
+        id -> Int8,
+        username -> Text,
+        password_hash -> Text,
+        created_on -> Timestamptz,
+        password_changed_on -> Timestamptz,
     }
 }
 
+diesel::joinable!(client_capabilities -> clients (client_id));
 diesel::joinable!(client_logs -> clients (client_id));
+diesel::joinable!(client_metadata_history -> clients (client_id));
+diesel::joinable!(client_sessions -> clients (client_id));
+diesel::joinable!(client_tags -> clients (client_id));
+diesel::joinable!(client_tags -> tags (tag_id));
+diesel::joinable!(client_updates -> clients (client_id));
+diesel::joinable!(client_updates -> releases (release_id));
+diesel::joinable!(craft_jobs -> clients (client_id));
+diesel::joinable!(craft_jobs -> item_thresholds (threshold_id));
+diesel::joinable!(executions -> clients (client_id));
+diesel::joinable!(item_samples -> clients (client_id));
+diesel::joinable!(item_thresholds -> clients (client_id));
+diesel::joinable!(power_samples -> clients (client_id));
+diesel::joinable!(queued_commands -> clients (client_id));
+diesel::joinable!(release_files -> releases (release_id));
+diesel::joinable!(schedule_runs -> schedules (schedule_id));
+diesel::joinable!(shell_events -> shell_sessions (session_id));
+diesel::joinable!(shell_sessions -> clients (client_id));
+diesel::joinable!(telemetry_rollups -> clients (client_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    client_capabilities,
     client_logs,
+    client_metadata_history,
+    client_sessions,
+    client_tags,
+    client_updates,
     clients,
+    craft_jobs,
+    executions,
+    item_samples,
+    item_thresholds,
+    power_samples,
+    queued_commands,
+    release_files,
+    releases,
+    schedule_runs,
+    schedules,
+    settings,
+    shell_events,
+    shell_sessions,
+    tags,
+    telemetry_rollups,
+    users,
 );
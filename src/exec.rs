@@ -0,0 +1,82 @@
+//! Remote Lua execution.
+//!
+//! A snippet is sent as `Exec <execution id> <code>` to every selected client that allows it, each
+//! answers with `Response Exec <execution id> <json>` carrying what it printed, its return values
+//! or error and how long it ran, see `ExecResponse`.
+use std::collections::HashMap;
+use std::time::Duration;
+use anyhow::anyhow;
+use diesel::prelude::*;
+use time::OffsetDateTime;
+use crate::AppState;
+use crate::database::models::{Client, ExecResponse, ExecStatus, Execution, NewExecution};
+use crate::database::schema::executions;
+use crate::error::ServerError;
+use crate::routes::api::{dispatch_each, Delivery, DeliveryStatus, ServerWSCommand};
+
+/// Executions without a response after this long are marked as timed out.
+pub const EXEC_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+pub const MAX_CODE_LENGTH: usize = 16 * 1024;
+
+/// Run a snippet on every target, clients that do not allow `Exec` are reported as failed.
+pub async fn run(state: &AppState, targets: &[Client], code: &str) -> Result<Vec<Delivery>, anyhow::Error> {
+    let (allowed, denied): (Vec<Client>, Vec<Client>) =
+        targets.iter().cloned().partition(|client| client.allow_exec);
+
+    let mut conn = state.pool.get()?;
+    let new_runs: Vec<NewExecution> = allowed
+        .iter()
+        .map(|client| NewExecution { client_id: *client.id(), code: code.to_string() })
+        .collect();
+    let runs: HashMap<i64, i64> = if new_runs.is_empty() {
+        HashMap::new()
+    } else {
+        diesel::insert_into(executions::table)
+            .values(&new_runs)
+            .returning((executions::client_id, executions::id))
+            .get_results::<(i64, i64)>(&mut conn)?
+            .into_iter()
+            .collect()
+    };
+
+    let mut deliveries = dispatch_each(
+        state,
+        &allowed,
+        |client| ServerWSCommand::Exec.to_message(&format!("{} {}", runs[client.id()], code)),
+        None,
+    ).await?;
+    let undelivered: Vec<i64> = deliveries
+        .iter()
+        .filter(|delivery| !matches!(delivery.status, DeliveryStatus::Sent))
+        .map(|delivery| runs[&delivery.client_id])
+        .collect();
+    Execution::set_status(&mut conn, &undelivered, ExecStatus::Undelivered)?;
+
+    deliveries.extend(denied.into_iter().map(|client| Delivery {
+        client_id: *client.id(),
+        name: client.name,
+        status: DeliveryStatus::Failed("Exec is not allowed on this client".to_string()),
+        queued: None,
+    }));
+    Ok(deliveries)
+}
+
+/// Handle `<execution id> <json>` from a `Response Exec`.
+pub fn handle_exec_response(client_id: i64, body: &str, state: &AppState) -> Result<(), ServerError> {
+    let (execution, json) = body.split_once(' ').ok_or(anyhow!("Missing execution result."))?;
+    let execution: i64 = execution.parse()?;
+    let response: ExecResponse = serde_json::from_str(json)?;
+    let status = if response.ok { ExecStatus::Done } else { ExecStatus::Error };
+
+    let mut conn = state.pool.get()?;
+    if !Execution::complete(&mut conn, client_id, execution, status, &response)? {
+        return Err(anyhow!("No running execution {}", execution).into());
+    }
+    Ok(())
+}
+
+/// Recent executions of a client, timing out the ones that never got a response.
+pub fn history(conn: &mut PgConnection, client: i64, limit: i64) -> Result<Vec<Execution>, anyhow::Error> {
+    Execution::expire(conn, OffsetDateTime::now_utc() - EXEC_TIMEOUT)?;
+    Execution::get_recent_for_client(conn, client, limit)
+}
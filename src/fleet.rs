@@ -0,0 +1,143 @@
+//! In-memory fleet activity counters backing the statistics page.
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
+use crate::AppState;
+use crate::database::models::ClientSession;
+
+/// How often the number of connected clients is sampled.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+/// How far back connection samples and message counts are kept.
+pub const HISTORY_LENGTH: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Default)]
+pub struct FleetStats {
+    /// Number of connected clients at each sample.
+    connected: VecDeque<(OffsetDateTime, usize)>,
+    /// Messages received from each client, bucketed per minute.
+    messages: HashMap<i64, VecDeque<(OffsetDateTime, u64)>>,
+}
+
+fn minute_start(time: OffsetDateTime) -> OffsetDateTime {
+    time.replace_second(0)
+        .and_then(|time| time.replace_nanosecond(0))
+        .unwrap_or(time)
+}
+
+impl FleetStats {
+    /// Count an incoming message from a client.
+    pub fn record_message(&mut self, client_id: i64) {
+        let minute = minute_start(OffsetDateTime::now_utc());
+        let buckets = self.messages.entry(client_id).or_default();
+        match buckets.back_mut() {
+            Some((bucket, count)) if *bucket == minute => *count += 1,
+            _ => buckets.push_back((minute, 1)),
+        }
+    }
+
+    /// Store a connected client sample and drop everything older than `HISTORY_LENGTH`.
+    pub fn record_connected(&mut self, count: usize) {
+        let now = OffsetDateTime::now_utc();
+        let cutoff = now - HISTORY_LENGTH;
+        self.connected.push_back((now, count));
+        while self.connected.front().is_some_and(|(time, _)| *time < cutoff) {
+            self.connected.pop_front();
+        }
+        for buckets in self.messages.values_mut() {
+            while buckets.front().is_some_and(|(time, _)| *time < cutoff) {
+                buckets.pop_front();
+            }
+        }
+        self.messages.retain(|_, buckets| !buckets.is_empty());
+    }
+
+    pub fn connected_history(&self) -> impl Iterator<Item = &(OffsetDateTime, usize)> {
+        self.connected.iter()
+    }
+
+    /// Messages received from the client in each of the last `minutes` minutes, oldest first.
+    pub fn messages_per_minute(&self, client_id: i64, minutes: usize) -> Vec<u64> {
+        let current = minute_start(OffsetDateTime::now_utc());
+        let mut counts = vec![0; minutes];
+        for (bucket, count) in self.messages.get(&client_id).into_iter().flatten() {
+            let age = ((current - *bucket).whole_minutes()) as usize;
+            if age < minutes {
+                counts[minutes - 1 - age] = *count;
+            }
+        }
+        counts
+    }
+
+    /// Clients that sent the most messages within `window`, busiest first.
+    pub fn top_talkers(&self, window: Duration, limit: usize) -> Vec<(i64, u64)> {
+        let cutoff = minute_start(OffsetDateTime::now_utc() - window);
+        let mut totals: Vec<(i64, u64)> = self
+            .messages
+            .iter()
+            .map(|(client_id, buckets)| {
+                let total = buckets
+                    .iter()
+                    .filter(|(time, _)| *time >= cutoff)
+                    .map(|(_, count)| count)
+                    .sum();
+                (*client_id, total)
+            })
+            .filter(|(_, total)| *total > 0)
+            .collect();
+        totals.sort_by_key(|(_, total)| std::cmp::Reverse(*total));
+        totals.truncate(limit);
+        totals
+    }
+}
+
+/// Connection history of a client over a window of time.
+pub struct Availability {
+    /// Fraction of the window the client was connected, from 0 to 1.
+    pub uptime: f64,
+    /// Connections opened within the window after the first one.
+    pub reconnects: usize,
+    /// When the client was last connected, `None` while it still is.
+    pub last_online: Option<OffsetDateTime>,
+}
+
+/// Availability of every client with sessions overlapping the window starting at `start`.
+pub fn availability(sessions: &[ClientSession], start: OffsetDateTime) -> HashMap<i64, Availability> {
+    let now = OffsetDateTime::now_utc();
+    let window = (now - start).as_seconds_f64();
+    let mut clients: HashMap<i64, Availability> = HashMap::new();
+
+    for session in sessions {
+        let end = session.disconnected_on.unwrap_or(now);
+        let connected = (end - session.connected_on.max(start)).as_seconds_f64().max(0.0);
+        let entry = clients.entry(session.client_id).or_insert(Availability {
+            uptime: 0.0,
+            reconnects: 0,
+            last_online: Some(end),
+        });
+        entry.uptime += connected / window;
+        if session.connected_on >= start {
+            entry.reconnects += 1;
+        }
+        entry.last_online = match (entry.last_online, session.disconnected_on) {
+            (None, _) | (_, None) => None,
+            (Some(last), Some(end)) => Some(last.max(end)),
+        };
+    }
+
+    for entry in clients.values_mut() {
+        entry.uptime = entry.uptime.min(1.0);
+        entry.reconnects = entry.reconnects.saturating_sub(1);
+    }
+    clients
+}
+
+/// Background task sampling the number of connected clients every `SAMPLE_INTERVAL`.
+pub async fn sample_task(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let connected = state.active_clients.lock().await.len();
+        state.fleet_stats.lock().await.record_connected(connected);
+    }
+}
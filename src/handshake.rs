@@ -0,0 +1,135 @@
+//! The `Hello` every client opens its connection with.
+//!
+//! Before anything else a client sends `Hello <json>` describing itself:
+//!
+//! ```text
+//! {"protocol": 1, "build": "<build of its manifest>", "components": ["gpu", "me_interface"],
+//!  "free_memory": 81234, "total_memory": 196608}
+//! ```
+//!
+//! Connections that do not start with a valid `Hello` within `HELLO_TIMEOUT` are closed with
+//! `CLOSE_HANDSHAKE_FAILED`, clients speaking a protocol version the server does not support with
+//! `CLOSE_UNSUPPORTED_VERSION`. What the client advertised is kept in `client_capabilities`, and
+//! commands needing a component the client lacks are not sent to it, see `route`.
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::time::Duration;
+use anyhow::anyhow;
+use axum::extract::ws::{CloseFrame, Message, WebSocket};
+use diesel::{Identifiable, PgConnection};
+use serde::Deserialize;
+use time::OffsetDateTime;
+use crate::database::models::{Capabilities, Client};
+use crate::routes::api::{Delivery, DeliveryStatus, ServerWSCommand};
+
+/// Protocol version spoken by this server, and the oldest one it still accepts.
+pub const PROTOCOL_VERSION: i32 = 1;
+pub const MIN_PROTOCOL_VERSION: i32 = 1;
+pub const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Close codes from the range reserved for applications.
+pub const CLOSE_UNSUPPORTED_VERSION: u16 = 4000;
+pub const CLOSE_HANDSHAKE_FAILED: u16 = 4001;
+
+#[derive(Deserialize)]
+pub struct Hello {
+    pub protocol: i32,
+    #[serde(default)]
+    pub build: Option<String>,
+    #[serde(default)]
+    pub components: Vec<String>,
+    pub free_memory: i64,
+    pub total_memory: i64,
+}
+
+impl Hello {
+    pub fn parse(text: &str) -> Result<Hello, anyhow::Error> {
+        let body = text.strip_prefix("Hello ").ok_or(anyhow!("Expected Hello as the first message."))?;
+        Ok(serde_json::from_str(body)?)
+    }
+
+    pub fn to_capabilities(&self, client_id: i64) -> Capabilities {
+        let mut components: Vec<Option<String>> = self.components.iter().cloned().map(Some).collect();
+        components.sort();
+        components.dedup();
+        Capabilities {
+            client_id,
+            protocol_version: self.protocol,
+            client_build: self.build.clone(),
+            components,
+            free_memory: self.free_memory,
+            total_memory: self.total_memory,
+            reported_on: OffsetDateTime::now_utc(),
+        }
+    }
+}
+
+/// Why a connection was refused, sent to the client as its close frame.
+#[derive(Debug)]
+pub struct Rejection {
+    pub code: u16,
+    pub reason: String,
+}
+
+impl Rejection {
+    fn handshake(reason: impl ToString) -> Rejection {
+        Rejection { code: CLOSE_HANDSHAKE_FAILED, reason: reason.to_string() }
+    }
+
+    pub async fn close(self, socket: &mut WebSocket) {
+        let frame = CloseFrame { code: self.code, reason: Cow::Owned(self.reason) };
+        socket.send(Message::Close(Some(frame))).await.ok();
+    }
+}
+
+/// Wait for the client's `Hello` and check that its protocol version is supported.
+pub async fn receive_hello(socket: &mut WebSocket) -> Result<Hello, Rejection> {
+    let message = match tokio::time::timeout(HELLO_TIMEOUT, socket.recv()).await {
+        Err(_) => return Err(Rejection::handshake("No Hello received")),
+        Ok(None) => return Err(Rejection::handshake("Connection closed before Hello")),
+        Ok(Some(Err(e))) => return Err(Rejection::handshake(e)),
+        Ok(Some(Ok(message))) => message,
+    };
+    let Message::Text(text) = message else {
+        return Err(Rejection::handshake("Expected Hello as the first message."));
+    };
+    let hello = Hello::parse(&text).map_err(Rejection::handshake)?;
+    if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&hello.protocol) {
+        return Err(Rejection {
+            code: CLOSE_UNSUPPORTED_VERSION,
+            reason: format!(
+                "Unsupported protocol version {}, expected {} to {}",
+                hello.protocol, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+            ),
+        });
+    }
+    Ok(hello)
+}
+
+/// Split targets into the clients `command` can be sent to and failed deliveries for those that
+/// advertised they lack the component it needs. Clients that never sent a `Hello` are kept.
+pub fn route(
+    conn: &mut PgConnection,
+    targets: Vec<Client>,
+    command: &ServerWSCommand,
+) -> Result<(Vec<Client>, Vec<Delivery>), anyhow::Error> {
+    let Some(component) = command.required_component() else {
+        return Ok((targets, Vec::new()));
+    };
+    let ids: Vec<i64> = targets.iter().map(|client| *client.id()).collect();
+    let capabilities: HashMap<i64, Capabilities> = Capabilities::get_for_clients(conn, &ids)?;
+
+    let (supported, lacking): (Vec<Client>, Vec<Client>) = targets.into_iter().partition(|client| {
+        capabilities.get(client.id()).is_none_or(|capabilities| capabilities.has_component(component))
+    });
+    let rejected = lacking
+        .into_iter()
+        .map(|client| Delivery {
+            client_id: *client.id(),
+            name: client.name,
+            status: DeliveryStatus::Failed(format!("Client has no {} component", component)),
+            queued: None,
+        })
+        .collect();
+    Ok((supported, rejected))
+}
@@ -0,0 +1,85 @@
+-- bootstrap installer of the client, generated by the server at /install
+--   wget -f <server>/install /tmp/install.lua && /tmp/install.lua
+local filesystem = require("filesystem");
+local internet = require("internet");
+local term = require("term");
+
+--[[GENERATED]]
+
+local API_KEY_LOCATION = "/home/.APIKEY"
+local CONFIG_LOCATION = "/etc/client.cfg"
+local MANIFEST_LOCATION = "/etc/client.manifest"
+
+local function request(path, body, headers, method)
+    local handle = internet.request(SERVER_URL .. path, body, headers, method)
+    local result = ""
+    for chunk in handle do result = result .. chunk end
+    return result
+end
+
+local function write_file(path, contents)
+    filesystem.makeDirectory(filesystem.path(path))
+    local file = filesystem.open(path, "w")
+    if file == nil then
+        error("Error creating output file " .. path)
+    end
+    file:write(contents)
+    file:close()
+end
+
+-- exchange the authorization code for an api key, unless the computer already has one
+local function enroll()
+    -- a baked in code enrolls again, e.g. after the key was rotated
+    if AUTH_CODE == nil and filesystem.exists(API_KEY_LOCATION) then
+        print("Already enrolled, keeping the existing API key")
+        local file = io.open(API_KEY_LOCATION, "rb")
+        local api_key = file:read("*a"):match("%w+")
+        file:close()
+        return api_key
+    end
+
+    local code = AUTH_CODE
+    if code == nil then
+        print("Create an authorization key and enter it here: ")
+        code = (term.read() or ""):gsub("%s+$", "")
+    end
+    if not code:match("^%w+$") then
+        error("Invalid authorization key " .. code)
+    end
+    local api_key = request("/api/authenticate", string.format([[{"authcode": "%s"}]], code),
+        {["Content-Type"] = "application/json"}, "POST")
+    -- the key length is a server setting, anything else is an error message
+    if not api_key:match("^%w+$") then
+        error("Enrollment failed: " .. api_key)
+    end
+    write_file(API_KEY_LOCATION, api_key)
+    print("Enrolled and obtained an API key")
+    return api_key
+end
+
+local function install()
+    for _, file in ipairs(FILES) do
+        print("Installing " .. file.path)
+        local contents = request(file.url)
+        if #contents ~= file.size then
+            error("Size mismatch for " .. file.path .. ", expected " .. file.size .. " got " .. #contents)
+        end
+        write_file(file.path, contents)
+    end
+    -- the updater only downloads what changed from here on
+    write_file(MANIFEST_LOCATION, MANIFEST)
+end
+
+-- server addresses, log level and modules of this client
+local function configure(api_key)
+    local config = request("/api/config", nil, {["X-API-Key"] = api_key})
+    if load(config, "=config", "t", {}) == nil then
+        error("Failed to get the client configuration: " .. config)
+    end
+    write_file(CONFIG_LOCATION, config)
+end
+
+local api_key = enroll()
+install()
+configure(api_key)
+print(string.format("Installed %d files of %s, start the client with: app", #FILES, VERSION))
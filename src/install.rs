@@ -0,0 +1,49 @@
+//! Bootstrap installer for new computers, served at `/install`.
+//!
+//! The script is `install.lua` with the server's address, the files to install and optionally an
+//! authorization code baked in, so a fresh computer needs nothing but
+//! `wget -f <server>/install?code=<code> /tmp/install.lua && /tmp/install.lua`. It enrolls with the
+//! code, or keeps the computer's API key when none was baked in and prompts for a code if it has
+//! none either. Then it installs every file of the manifest, saves the manifest for the updater and
+//! downloads the client's configuration.
+use crate::client_config::lua_string;
+use crate::manifest::Manifest;
+
+const TEMPLATE: &str = include_str!("install.lua");
+const GENERATED_MARKER: &str = "--[[GENERATED]]";
+
+/// The installer for clients reaching the server at `server_url`.
+pub fn script(server_url: &str, auth_code: Option<&str>, manifest: &Manifest) -> Result<String, anyhow::Error> {
+    let files: Vec<String> = manifest
+        .files
+        .iter()
+        .map(|file| format!(
+            "    {{path = {}, url = {}, size = {}}},\n",
+            lua_string(&file.path),
+            lua_string(&file.url),
+            file.size,
+        ))
+        .collect();
+    let generated = format!(
+        "local SERVER_URL = {}\n\
+         local AUTH_CODE = {}\n\
+         local VERSION = {}\n\
+         local MANIFEST = {}\n\
+         local FILES = {{\n{}}}",
+        lua_string(server_url),
+        auth_code.map(lua_string).unwrap_or("nil".to_string()),
+        lua_string(&manifest.version),
+        lua_string(&serde_json::to_string(manifest)?),
+        files.concat(),
+    );
+    Ok(TEMPLATE.replacen(GENERATED_MARKER, &generated, 1))
+}
+
+/// One line to paste into a new computer's shell.
+pub fn command(server_url: &str, auth_code: Option<&str>) -> String {
+    let url = match auth_code {
+        Some(code) => format!("{}/install?code={}", server_url, code),
+        None => format!("{}/install", server_url),
+    };
+    format!("wget -f {} /tmp/install.lua && /tmp/install.lua", url)
+}
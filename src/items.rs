@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use anyhow::anyhow;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use crate::AppState;
+use crate::database::models::{CraftJob, CraftStatus, ItemSample, ItemThreshold, NewCraftJob, NewItemSample};
+use crate::database::schema::{craft_jobs, item_samples};
+use crate::error::ServerError;
+use crate::routes::api::{send_command, ServerWSCommand};
+use crate::telemetry::{self, ITEM_METRIC_PREFIX, RAW_RETENTION};
+
+/// Consecutive failed crafts before a threshold is flagged and stops requesting crafts.
+pub const MAX_CRAFT_FAILURES: i32 = 3;
+/// Open craft jobs with no word from the client for this long are considered failed.
+pub const CRAFT_JOB_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+/// Item stack as reported by the client, mirrors the fields of `me_interface.getItemsInNetwork()`.
+#[derive(Deserialize)]
+struct ReportedItem {
+    name: String,
+    label: String,
+    size: i64,
+    #[serde(rename = "isCraftable", default)]
+    craftable: bool,
+}
+
+/// Store an `Items <json>` snapshot and request crafts for any threshold it falls short of.
+pub async fn handle_snapshot(client_id: i64, body: &str, state: &AppState) -> Result<(), ServerError> {
+    let reported: Vec<ReportedItem> = serde_json::from_str(body)?;
+    let sampled_on = OffsetDateTime::now_utc();
+    let samples: Vec<NewItemSample> = reported
+        .into_iter()
+        .map(|item| NewItemSample {
+            client_id,
+            sampled_on,
+            item_name: item.name,
+            label: item.label,
+            amount: item.size,
+            craftable: item.craftable,
+        })
+        .collect();
+
+    {
+        let mut conn = state.pool.get()?;
+        diesel::insert_into(item_samples::table)
+            .values(&samples)
+            .execute(&mut conn)?;
+    }
+
+    check_thresholds(client_id, &samples, state).await
+}
+
+/// Compare a snapshot against the client's thresholds, sending a `Craft` command for the deficit
+/// of each item that is below its minimum and has no craft in flight.
+async fn check_thresholds(
+    client_id: i64,
+    samples: &[NewItemSample],
+    state: &AppState,
+) -> Result<(), ServerError> {
+    let mut conn = state.pool.get()?;
+    expire_stale_jobs(&mut conn)?;
+    let thresholds = ItemThreshold::get_active(&mut conn, client_id)?;
+    drop(conn);
+
+    for threshold in thresholds {
+        let current: i64 = samples
+            .iter()
+            .filter(|sample| sample.item_name == threshold.item_name)
+            .map(|sample| sample.amount)
+            .sum();
+        let mut conn = state.pool.get()?;
+        if current >= threshold.min_amount || CraftJob::has_open(&mut conn, threshold.id)? {
+            continue;
+        }
+
+        let job = diesel::insert_into(craft_jobs::table)
+            .values(NewCraftJob::from_threshold(&threshold, threshold.min_amount - current))
+            .returning(CraftJob::as_returning())
+            .get_result(&mut conn)?;
+        // a slow client must not hold on to a pooled connection
+        drop(conn);
+
+        let args = format!("{} {} {}", job.id, job.item_name, job.amount);
+        if let Err(e) = send_command(state, client_id, ServerWSCommand::Craft.to_message(&args)).await {
+            let reason = format!("Could not send craft request: {}", e.0);
+            job.set_status(&mut *state.pool.get()?, CraftStatus::Failed, Some(&reason), MAX_CRAFT_FAILURES)?;
+        }
+    }
+    Ok(())
+}
+
+/// Fail any open job that has timed out, so its threshold can retry or get flagged.
+fn expire_stale_jobs(conn: &mut PgConnection) -> Result<(), ServerError> {
+    let cutoff = OffsetDateTime::now_utc() - CRAFT_JOB_TIMEOUT;
+    for job in CraftJob::get_stale(conn, cutoff)? {
+        job.set_status(conn, CraftStatus::Failed, Some("Timed out waiting for client"), MAX_CRAFT_FAILURES)?;
+    }
+    Ok(())
+}
+
+/// Handle `Response Craft <job id> <status> [message]` sent back by the client.
+pub async fn handle_craft_response(client_id: i64, body: &str, state: &AppState) -> Result<(), ServerError> {
+    let mut parts = body.splitn(3, ' ');
+    let job_id: i64 = parts.next().unwrap_or_default().parse()?;
+    let new_status = parts
+        .next()
+        .and_then(CraftStatus::parse)
+        .ok_or(anyhow!("Invalid craft status in response."))?;
+    let message = parts.next().filter(|message| !message.is_empty());
+
+    let mut conn = state.pool.get()?;
+    let job = CraftJob::get_by_id(&mut conn, job_id)?
+        .filter(|job| job.client_id == client_id)
+        .ok_or(anyhow!("No craft job {} for this client.", job_id))?;
+
+    let current = CraftStatus::parse(&job.status);
+    if !current.is_some_and(|status| status.is_open()) {
+        return Err(anyhow!("Craft job {} is already finished.", job_id).into());
+    }
+
+    job.set_status(&mut conn, new_status, message, MAX_CRAFT_FAILURES)?;
+    Ok(())
+}
+
+/// Production (positive) or consumption (negative) of an item over a window of snapshots.
+#[derive(Serialize, Clone)]
+pub struct ItemRate {
+    pub client_id: i64,
+    pub item_name: String,
+    pub label: String,
+    /// Amount in the latest snapshot of the window.
+    pub amount: i64,
+    pub per_hour: f64,
+    /// Projected hours until the item runs out, only set while it is being consumed.
+    pub hours_remaining: Option<f64>,
+}
+
+/// Per-item rates across every client, computed from the first and last reading in `window`.
+/// Readings come from raw snapshots while the window is within `RAW_RETENTION` and from telemetry
+/// rollups beyond that. Items missing from a client's latest reading are treated as run out.
+pub fn item_rates(conn: &mut PgConnection, window: Duration) -> Result<Vec<ItemRate>, anyhow::Error> {
+    let now = OffsetDateTime::now_utc();
+    let start = time::Duration::try_from(window)
+        .ok()
+        .and_then(|window| now.checked_sub(window))
+        .ok_or(anyhow!("Window {:?} is too long", window))?;
+
+    // (client, item, time, amount), oldest first
+    let readings: Vec<(i64, String, OffsetDateTime, i64)> = if window <= RAW_RETENTION {
+        ItemSample::since(conn, start)?
+            .into_iter()
+            .map(|sample| (sample.client_id, sample.item_name, sample.sampled_on, sample.amount))
+            .collect()
+    } else {
+        let (_, mut points) = telemetry::query(conn, None, &format!("{}%", ITEM_METRIC_PREFIX), start, now)?;
+        points.sort_by_key(|point| point.bucket);
+        points
+            .into_iter()
+            .map(|point| {
+                let item = point.metric.trim_start_matches(ITEM_METRIC_PREFIX).to_string();
+                (point.client_id, item, point.bucket, point.avg_value.round() as i64)
+            })
+            .collect()
+    };
+
+    let labels: HashMap<String, String> = ItemSample::latest(conn)?
+        .into_iter()
+        .map(|sample| (sample.item_name, sample.label))
+        .collect();
+
+    let mut latest_reading: HashMap<i64, OffsetDateTime> = HashMap::new();
+    // first and last (time, amount) of each item, stacks reported at the same time are summed
+    let mut ranges: HashMap<(i64, String), [(OffsetDateTime, i64); 2]> = HashMap::new();
+    for (client_id, item_name, time, amount) in readings {
+        latest_reading.insert(client_id, time);
+        ranges
+            .entry((client_id, item_name))
+            .and_modify(|[first, last]| {
+                if last.0 == time {
+                    last.1 += amount;
+                    if first.0 == time {
+                        first.1 = last.1;
+                    }
+                } else {
+                    *last = (time, amount);
+                }
+            })
+            .or_insert([(time, amount), (time, amount)]);
+    }
+
+    let mut rates = Vec::new();
+    for ((client_id, item_name), [first, last]) in ranges {
+        let (end, amount) = match latest_reading.get(&client_id) {
+            Some(&latest) if latest > last.0 => (latest, 0),
+            _ => last,
+        };
+        let hours = (end - first.0).as_seconds_f64() / 3600.0;
+        if hours <= 0.0 {
+            continue;
+        }
+
+        let per_hour = (amount - first.1) as f64 / hours;
+        let hours_remaining = (per_hour < 0.0 && amount > 0).then(|| amount as f64 / -per_hour);
+        rates.push(ItemRate {
+            client_id,
+            label: labels.get(&item_name).cloned().unwrap_or_else(|| item_name.clone()),
+            item_name,
+            amount,
+            per_hour,
+            hours_remaining,
+        });
+    }
+    Ok(rates)
+}
+
+/// Items with the largest absolute rate of change first.
+pub fn top_movers(mut rates: Vec<ItemRate>, limit: usize) -> Vec<ItemRate> {
+    rates.sort_by(|a, b| b.per_hour.abs().total_cmp(&a.per_hour.abs()));
+    rates.truncate(limit);
+    rates
+}
+
+/// Items being consumed, soonest to run out first.
+pub fn running_out(rates: Vec<ItemRate>, limit: usize) -> Vec<ItemRate> {
+    let mut rates: Vec<ItemRate> = rates
+        .into_iter()
+        .filter(|rate| rate.hours_remaining.is_some())
+        .collect();
+    rates.sort_by(|a, b| a.hours_remaining.unwrap_or_default().total_cmp(&b.hours_remaining.unwrap_or_default()));
+    rates.truncate(limit);
+    rates
+}
@@ -23,6 +23,7 @@ pub fn head() -> Markup {
             head {
                 meta charset="utf-8";
                 link rel="stylesheet" type="text/css" href="/static/style.css";
+                link rel="stylesheet" type="text/css" href="https://unpkg.com/xterm@5.3.0/css/xterm.css";
                 title { "OpenComputer Access Terminal" }
             }
     }
@@ -32,6 +33,7 @@ pub fn head() -> Markup {
 pub fn footer() -> Markup {
     html! {
         script src="https://unpkg.com/htmx.org@1.9.12" {}
+        script src="https://unpkg.com/xterm@5.3.0/lib/xterm.js" {}
         script src="/static/script.js" {}
     }
 }
@@ -138,6 +140,32 @@ impl Render for Modal {
     }
 }
 
+/// Inline svg line chart of `values` scaled to fit, oldest value first.
+pub fn sparkline(values: &[f64]) -> Markup {
+    const WIDTH: f64 = 200.0;
+    const HEIGHT: f64 = 40.0;
+
+    if values.len() < 2 {
+        return html! { span .text-xs { "Not enough data" } };
+    }
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let range = if max > min { max - min } else { 1.0 };
+    let step = WIDTH / (values.len() - 1) as f64;
+    let points: Vec<String> = values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| format!("{:.1},{:.1}", i as f64 * step, HEIGHT - (value - min) / range * HEIGHT))
+        .collect();
+
+    html! {
+        svg xmlns="http://www.w3.org/2000/svg" width=(WIDTH) height=(HEIGHT)
+            viewBox=(format!("0 0 {} {}", WIDTH, HEIGHT)) class="stroke-current" fill="none" {
+            polyline points=(points.join(" ")) stroke-width="1.5" {}
+        }
+    }
+}
+
 pub struct Link {
     pub(crate) name: &'static str,
     pub(crate) url: &'static str,
@@ -149,11 +177,13 @@ impl Link {
     }
 }
 
-pub static BUTTONS: [Link; 5] = [
+pub static BUTTONS: [Link; 7] = [
     Link::new("Home", "/"),
     Link::new("Clients", "/clients"),
     Link::new("Energy", "/energy"),
     Link::new("Items", "/items"),
+    Link::new("Releases", "/releases"),
+    Link::new("Schedules", "/schedules"),
     Link::new("Statistics", "/stats"),
 ];
 
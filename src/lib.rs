@@ -1,19 +1,64 @@
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 use tokio::signal;
 use tokio::sync::Mutex;
 use database::models::ActiveClient;
+use crate::assets::Assets;
+use crate::config::Config;
 use crate::database::Pool;
+use crate::fleet::FleetStats;
+use crate::screen::Screen;
+use crate::settings::Settings;
+use crate::shell::ShellBridge;
 
+pub mod admin;
+pub mod assets;
+pub mod client_config;
+pub mod codec;
+pub mod config;
+pub mod cron;
 pub mod error;
+pub mod exec;
 pub mod layout;
+pub mod manifest;
 pub mod routes;
 pub mod database;
+pub mod fleet;
+pub mod handshake;
+pub mod install;
+pub mod items;
+pub mod queue;
+pub mod releases;
+pub mod screen;
+pub mod scheduler;
+pub mod selector;
+pub mod settings;
+pub mod shell;
+pub mod telemetry;
+pub mod users;
 
 /// Primary app state engine
 pub struct AppState {
     pub pool: Pool,
-    pub active_clients: Arc<Mutex<HashMap<i64, ActiveClient>>>
+    pub active_clients: Arc<Mutex<HashMap<i64, ActiveClient>>>,
+    pub fleet_stats: Arc<Mutex<FleetStats>>,
+    /// Browser shell sessions by `shell_sessions` id.
+    pub shells: Arc<Mutex<HashMap<i64, ShellBridge>>>,
+    /// Last mirrored screen of each client, kept after it disconnects.
+    pub screens: Arc<Mutex<HashMap<i64, Screen>>>,
+    /// Static files and the client bundle, from disk or embedded.
+    pub assets: Assets,
+    pub config: Config,
+    /// Replaced whenever the settings page is saved.
+    pub settings: RwLock<Settings>,
+}
+
+impl AppState {
+    /// The current settings with the configuration's overrides, see `crate::settings`.
+    pub fn settings(&self) -> Settings {
+        self.settings.read().unwrap().clone().overridden(&self.config)
+    }
 }
 
 
@@ -41,4 +86,55 @@ pub async fn shutdown_signal() {
     }
 }
 
+/// Longest duration `parse_duration` accepts, so it can be added to or subtracted from any time
+/// the server deals with.
+pub const MAX_DURATION: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// Parse a short duration such as `90s`, `15m`, `6h` or `7d`, at most `MAX_DURATION`.
+pub fn parse_duration(string: &str) -> Option<Duration> {
+    let string = string.trim();
+    let (unit_start, _) = string.char_indices().last()?;
+    let (amount, unit) = string.split_at(unit_start);
+    let amount: u64 = amount.parse().ok()?;
+    let unit_seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 60 * 60 * 24,
+        _ => return None,
+    };
+    let seconds = amount.checked_mul(unit_seconds)?;
+    (seconds > 0 && seconds <= MAX_DURATION.as_secs()).then(|| Duration::from_secs(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    #[test]
+    fn durations_parse_in_every_unit() {
+        assert_eq!(parse_duration("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("15m"), Some(Duration::from_secs(15 * 60)));
+        assert_eq!(parse_duration("6h"), Some(Duration::from_secs(6 * 60 * 60)));
+        assert_eq!(parse_duration(" 7d "), Some(Duration::from_secs(7 * 24 * 60 * 60)));
+    }
+
+    #[test]
+    fn malformed_durations_are_rejected() {
+        for string in ["", "s", "10", "0s", "1.5h", "-1h", "6 h", "6H", "6w", "h6", "1٣s"] {
+            assert_eq!(parse_duration(string), None, "{}", string);
+        }
+    }
+
+    #[test]
+    fn durations_are_bounded() {
+        assert_eq!(parse_duration("365d"), Some(MAX_DURATION));
+        assert_eq!(parse_duration(&format!("{}s", MAX_DURATION.as_secs())), Some(MAX_DURATION));
+        assert_eq!(parse_duration(&format!("{}s", MAX_DURATION.as_secs() + 1)), None);
+        assert_eq!(parse_duration("366d"), None);
+        assert_eq!(parse_duration("999999999d"), None);
+        assert_eq!(parse_duration(&format!("{}d", u64::MAX)), None);
+        assert_eq!(parse_duration(&format!("{}s", u64::MAX)), None);
+        assert_eq!(parse_duration("18446744073709551616s"), None);
+    }
+}
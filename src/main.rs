@@ -1,10 +1,52 @@
 use std::collections::HashMap;
-use site::{database, routes, shutdown_signal, AppState};
-use std::env;
-use std::sync::Arc;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+use site::{admin, assets, database, fleet, queue, releases, routes, scheduler, shutdown_signal, telemetry, AppState};
+use site::admin::{ClientCommand, LogsCommand, UserCommand};
+use site::assets::Assets;
+use site::config::{Config, ConfigArgs};
+use site::settings::Settings;
+use site::database::models::ClientSession;
+use std::process::ExitCode;
+use std::sync::{Arc, RwLock};
 use tokio::sync::Mutex;
+use tracing::{error, info};
 
-const SERVER_ADDR: &str = "0.0.0.0:3000";
+/// OpenComputers control server
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Serve the site and the client API, the default
+    Serve,
+    /// Apply pending database migrations
+    Migrate,
+    /// Manage clients
+    #[command(subcommand)]
+    Client(ClientCommand),
+    /// Manage user accounts
+    #[command(subcommand)]
+    User(UserCommand),
+    /// Read client logs
+    #[command(subcommand)]
+    Logs(LogsCommand),
+    /// Write the settings, tags, clients, schedules and users as JSON, to stdout without a file
+    Export {
+        file: Option<PathBuf>,
+    },
+    /// Load an export into a database without clients, tags, schedules or users
+    Import {
+        file: PathBuf,
+    },
+}
 
 /*
    ae_history {
@@ -44,22 +86,81 @@ const SERVER_ADDR: &str = "0.0.0.0:3000";
 // websocket handler and command/control portion of website/client
 //
 
+fn main() -> ExitCode {
+    // a .env file is only a convenience during development
+    dotenv::dotenv().ok();
+    let cli = Cli::parse();
+    let config = match Config::load(&cli.config) {
+        Ok(config) => config,
+        Err(errors) => {
+            eprintln!("Invalid configuration:");
+            for error in errors {
+                eprintln!("  {}", error);
+            }
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let connect = || database::establish_connection(&config.database_url);
+    let result = match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => return serve(config),
+        Command::Migrate => connect().and_then(|mut conn| admin::migrate(&mut conn)),
+        Command::Client(command) => connect().and_then(|mut conn| admin::client(&mut conn, &config, command)),
+        Command::User(command) => connect().and_then(|mut conn| admin::user(&mut conn, command)),
+        Command::Logs(command) => connect().and_then(|mut conn| admin::logs(&mut conn, command)),
+        Command::Export { file } => connect().and_then(|mut conn| admin::export(&mut conn, file.as_deref())),
+        Command::Import { file } => connect().and_then(|mut conn| admin::import(&mut conn, &file)),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
 #[tokio::main]
-async fn main() {
-    tracing_subscriber::fmt::init();
-    dotenv::dotenv().expect("Failed to load .env"); // TODO TMP DEV-REMOVE-ME
+async fn serve(config: Config) -> ExitCode {
+    config.log_format.init();
+
+    let pool = database::establish_connection_pool(&config.database_url, config.pool_size);
+    let mut conn = pool.get().expect("Failed to get a database connection");
+    let settings = Settings::load(&mut conn).expect("Failed to load the settings");
+    ClientSession::close_dangling(&mut conn).expect("Failed to close dangling client sessions");
+    drop(conn);
 
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
     let state = Arc::new(AppState {
-        pool: database::establish_connection_pool(&database_url),
+        pool,
         active_clients: Arc::new(Mutex::new(HashMap::new())),
+        fleet_stats: Arc::new(Mutex::new(Default::default())),
+        shells: Arc::new(Mutex::new(HashMap::new())),
+        screens: Arc::new(Mutex::new(HashMap::new())),
+        assets: Assets::new(config.static_dir.clone()),
+        settings: RwLock::new(settings),
+        config,
     });
-    
-    let listener = tokio::net::TcpListener::bind(SERVER_ADDR).await.unwrap();
 
-    println!("Serving at {SERVER_ADDR}!");
-    axum::serve(listener, routes::router(state))
+    tokio::spawn(telemetry::rollup_task(state.clone()));
+    tokio::spawn(fleet::sample_task(state.clone()));
+    tokio::spawn(queue::redelivery_task(state.clone()));
+    tokio::spawn(releases::watch_task(state.clone()));
+    tokio::spawn(assets::reload_task(state.clone()));
+    tokio::spawn(scheduler::schedule_task(state.clone()));
+
+    let bind_address = state.settings().bind_address;
+    let listener = match tokio::net::TcpListener::bind(bind_address).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to listen on {}: {}", bind_address, e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    info!("Serving at {bind_address}!");
+    axum::serve(listener, routes::router(state).into_make_service_with_connect_info::<SocketAddr>())
         .with_graceful_shutdown(shutdown_signal())
         .await
         .unwrap();
+    ExitCode::SUCCESS
 }
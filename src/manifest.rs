@@ -0,0 +1,84 @@
+//! Update manifest of the Lua client, generated from its bundle, see `crate::assets`.
+//!
+//! Served as JSON at `/api/manifest`:
+//!
+//! ```text
+//! {"version": "0.1.0", "build": "<sha256>", "files": [
+//!     {"path": "/usr/bin/app.lua", "url": "/static/client/usr/bin/app.lua", "sha256": "<sha256>", "size": 1234}
+//! ]}
+//! ```
+//!
+//! `path` is where the file is installed on the client and `url` where the updater downloads it
+//! from. `build` hashes every path along with its file hash, so it changes whenever any file does.
+//! The updater keeps the last manifest it installed, only downloads files whose hash changed and
+//! reports its `build` in the `Hello`.
+use include_dir::{Dir, File};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::routes::ASSETS_DIR;
+
+/// Directory of the client bundle inside the static assets.
+pub const CLIENT_DIR: &str = "client";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ManifestFile {
+    pub path: String,
+    pub url: String,
+    pub sha256: String,
+    pub size: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Manifest {
+    pub version: String,
+    pub build: String,
+    pub files: Vec<ManifestFile>,
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn collect_files<'a>(dir: &'a Dir<'a>, files: &mut Vec<&'a File<'a>>) {
+    files.extend(dir.files());
+    for child in dir.dirs() {
+        collect_files(child, files);
+    }
+}
+
+impl Manifest {
+    /// Manifest of files given by install path and contents, downloadable below `url_prefix`.
+    pub fn from_files<'a>(
+        version: &str,
+        url_prefix: &str,
+        files: impl IntoIterator<Item = (String, &'a [u8])>,
+    ) -> Manifest {
+        let mut files: Vec<ManifestFile> = files
+            .into_iter()
+            .map(|(path, contents)| ManifestFile {
+                url: format!("{}{}", url_prefix, path),
+                sha256: sha256_hex(contents),
+                size: contents.len() as u64,
+                path,
+            })
+            .collect();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        let listing: String = files.iter().map(|file| format!("{} {}\n", file.path, file.sha256)).collect();
+        Manifest { version: version.to_string(), build: sha256_hex(listing.as_bytes()), files }
+    }
+}
+
+/// Files of the bundle embedded in the server as `(install path, contents)`.
+pub fn embedded_files() -> Vec<(String, &'static [u8])> {
+    let mut files = Vec::new();
+    if let Some(dir) = ASSETS_DIR.get_dir(CLIENT_DIR) {
+        collect_files(dir, &mut files);
+    }
+    files
+        .into_iter()
+        .filter_map(|file| {
+            let path = file.path().strip_prefix(CLIENT_DIR).ok()?.to_str()?;
+            Some((format!("/{}", path.trim_start_matches('/')), file.contents()))
+        })
+        .collect()
+}
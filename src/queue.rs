@@ -0,0 +1,122 @@
+//! Persistent outbound command queue with acknowledgements.
+//!
+//! Queued commands are stored in `queued_commands` and sent wrapped as `Queued <id> <command>`,
+//! the client answers with `Ack <id>` before running the command. Commands for offline clients are
+//! sent in order when they reconnect, commands that go unacknowledged are resent until
+//! `MAX_DELIVERY_ATTEMPTS`, and anything still open when its TTL runs out is expired.
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::anyhow;
+use axum::extract::ws::Message;
+use diesel::prelude::*;
+use time::OffsetDateTime;
+use crate::AppState;
+use crate::database::models::{ClientSender, NewQueuedCommand, QueuedCommand, SenderGuard};
+use crate::database::schema::queued_commands;
+use crate::routes::api::ServerWSCommand;
+use tracing::error;
+
+/// How long a delivered command may go unacknowledged before it is resent.
+pub const ACK_TIMEOUT: Duration = Duration::from_secs(60);
+pub const MAX_DELIVERY_ATTEMPTS: i32 = 5;
+/// How often overdue and expired commands are checked.
+pub const REDELIVERY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Queue a `(client, command)` pair each, returning the queue entry id per client.
+pub fn enqueue(
+    conn: &mut PgConnection,
+    commands: &[(i64, &str)],
+    ttl: Duration,
+) -> Result<HashMap<i64, i64>, anyhow::Error> {
+    if commands.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let expires_on = time::Duration::try_from(ttl)
+        .ok()
+        .and_then(|ttl| OffsetDateTime::now_utc().checked_add(ttl))
+        .ok_or(anyhow!("Queue TTL {:?} is too long", ttl))?;
+    let entries: Vec<NewQueuedCommand> = commands
+        .iter()
+        .map(|&(client_id, command)| NewQueuedCommand { client_id, command: command.to_string(), expires_on })
+        .collect();
+    let queued: Vec<(i64, i64)> = diesel::insert_into(queued_commands::table)
+        .values(&entries)
+        .returning((queued_commands::client_id, queued_commands::id))
+        .get_results(conn)?;
+    Ok(queued.into_iter().collect())
+}
+
+/// The frame carrying a queued command, the client acks it by id.
+pub fn to_message(entry: i64, command: &str) -> Message {
+    ServerWSCommand::Queued.to_message(&format!("{} {}", entry, command))
+}
+
+/// Send every open command of a freshly connected client, oldest first.
+pub async fn deliver_pending(state: &AppState, client_id: i64, outbox: &mut SenderGuard) -> Result<usize, anyhow::Error> {
+    let entries = QueuedCommand::get_unacked_for_client(&mut *state.pool.get()?, client_id)?;
+    let mut sent = Vec::with_capacity(entries.len());
+    for entry in &entries {
+        // stop at the first failure so later commands never overtake it
+        if outbox.send(to_message(entry.id, &entry.command)).await.is_err() {
+            break;
+        }
+        sent.push(entry.id);
+    }
+    QueuedCommand::mark_sent(&mut *state.pool.get()?, &sent)?;
+    Ok(sent.len())
+}
+
+/// Handle `Ack <id>` from a client.
+pub fn handle_ack(client_id: i64, body: &str, state: &AppState) -> Result<(), anyhow::Error> {
+    let entry: i64 = body.trim().parse()?;
+    let mut conn = state.pool.get()?;
+    if !QueuedCommand::ack(&mut conn, client_id, entry)? {
+        return Err(anyhow!("No open queued command {}", entry));
+    }
+    Ok(())
+}
+
+pub async fn redelivery_task(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(REDELIVERY_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = redeliver(&state).await {
+            error!("Command redelivery failed: {}", e);
+        }
+    }
+}
+
+/// Expire stale commands, then resend or give up on the ones that went unacknowledged.
+async fn redeliver(state: &AppState) -> Result<(), anyhow::Error> {
+    let overdue = {
+        let mut conn = state.pool.get()?;
+        QueuedCommand::expire(&mut conn)?;
+        let cutoff = OffsetDateTime::now_utc() - ACK_TIMEOUT;
+        let (exhausted, overdue): (Vec<QueuedCommand>, Vec<QueuedCommand>) =
+            QueuedCommand::get_overdue(&mut conn, cutoff)?
+                .into_iter()
+                .partition(|entry| entry.attempts >= MAX_DELIVERY_ATTEMPTS);
+        let exhausted: Vec<i64> = exhausted.iter().map(|entry| entry.id).collect();
+        QueuedCommand::mark_failed(&mut conn, &exhausted)?;
+        overdue
+    };
+
+    // offline clients get everything again when they reconnect
+    let senders: HashMap<i64, ClientSender> = {
+        let active_clients = state.active_clients.lock().await;
+        overdue
+            .iter()
+            .filter_map(|entry| Some((entry.client_id, active_clients.get(&entry.client_id)?.sender.clone())))
+            .collect()
+    };
+    let mut sent = Vec::new();
+    for entry in &overdue {
+        let Some(sender) = senders.get(&entry.client_id) else { continue };
+        if sender.send(to_message(entry.id, &entry.command)).await.is_ok() {
+            sent.push(entry.id);
+        }
+    }
+    QueuedCommand::mark_sent(&mut *state.pool.get()?, &sent)?;
+    Ok(())
+}
@@ -0,0 +1,284 @@
+//! Client software releases and their staged rollout.
+//!
+//! Every release belongs to a channel. A client follows its own channel if it has one, otherwise
+//! the least tested channel among its tags (so tagging a few clients `beta` makes them canaries)
+//! and `stable` when neither is set. Of a channel's releases a client is offered the newest one
+//! whose rollout covers it: clients are spread over 100 buckets per release, and a release at 20%
+//! goes to buckets 0 to 19, so raising the percentage only ever adds clients.
+//!
+//! Connected clients whose reported build differs from their target are sent
+//! `Update <manifest url>`, when they connect and whenever releases, rollouts or channels change.
+//!
+//! An update fails when the client does not come back with the release's build within
+//! `RECONNECT_DEADLINE`, or logs errors during the `OBSERVATION_WINDOW` after it did. Once more
+//! than a release's `max_failure_percent` of its updates failed the release is halted: it is no
+//! longer offered, and its clients are sent the release they would be on without it, or the bundle
+//! served by the server when their channel has nothing older.
+//!
+//! Clients log at Error level when a thread of the app crashes, a command fails or the updater
+//! does.
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::Duration;
+use diesel::{Identifiable, PgConnection};
+use time::OffsetDateTime;
+use crate::AppState;
+use crate::database::models::{Capabilities, Channel, Client, ClientLog, ClientUpdate, LogLevel, Release, Tag, UpdateStatus};
+use crate::manifest::{sha256_hex, Manifest};
+use crate::routes::api::{send_command, ServerWSCommand};
+use tracing::{error, warn};
+
+/// An `Update` that did not result in the release's build is sent again after this long.
+pub const UPDATE_RETRY: Duration = Duration::from_secs(30 * 60);
+/// How long a client has to come back with the release after being sent `Update`.
+pub const RECONNECT_DEADLINE: Duration = Duration::from_secs(10 * 60);
+/// How long errors a client logs after updating count against the release.
+pub const OBSERVATION_WINDOW: Duration = Duration::from_secs(10 * 60);
+/// How often updates are checked against their deadlines.
+pub const WATCH_INTERVAL: Duration = Duration::from_secs(30);
+pub const DEFAULT_MAX_FAILURE_PERCENT: i32 = 20;
+
+/// Stable bucket of a client for a release, from 0 to 99.
+pub fn bucket(client_id: i64, release_id: i64) -> i32 {
+    let hash = sha256_hex(format!("{}:{}", release_id, client_id).as_bytes());
+    (u32::from_str_radix(&hash[..8], 16).unwrap_or_default() % 100) as i32
+}
+
+/// Channel a client follows, see the module documentation.
+pub fn channel_for(client: &Client, tag_channels: &[String]) -> Channel {
+    client
+        .channel
+        .as_deref()
+        .and_then(Channel::parse)
+        .or_else(|| tag_channels.iter().filter_map(|channel| Channel::parse(channel)).max())
+        .unwrap_or(Channel::Stable)
+}
+
+/// Release the client should be running, `None` when its channel has nothing for it yet.
+pub fn target_release(conn: &mut PgConnection, client: &Client) -> Result<Option<Release>, anyhow::Error> {
+    let channel = channel_for(client, &Tag::get_channels_for_client(conn, *client.id())?);
+    let target = Release::get_for_channel(conn, channel)?
+        .into_iter()
+        .find(|release| release.halted_on.is_none() && bucket(*client.id(), release.id) < release.rollout_percent);
+    Ok(target)
+}
+
+/// Manifest of a release, its files downloadable from the release's API.
+pub fn manifest(conn: &mut PgConnection, release: &Release) -> Result<Manifest, anyhow::Error> {
+    let files = release.files(conn)?;
+    Ok(Manifest::from_files(
+        &release.version,
+        &format!("/api/releases/{}/files", release.id),
+        files.iter().map(|(path, contents)| (path.clone(), contents.as_slice())),
+    ))
+}
+
+/// What to call the build a client reported in the fleet view, `bundle` being the manifest of
+/// the bundle the server serves itself.
+pub fn version_label(conn: &mut PgConnection, bundle: &Manifest, build: Option<&str>) -> Result<String, anyhow::Error> {
+    let Some(build) = build else {
+        return Ok("Unknown".to_string());
+    };
+    if let Some(release) = Release::get_by_build(conn, build)? {
+        return Ok(release.version);
+    }
+    if build == bundle.build {
+        return Ok(format!("{} (server bundle)", bundle.version));
+    }
+    Ok(format!("Unreleased {}", build.chars().take(12).collect::<String>()))
+}
+
+/// Send `Update` to a connected client if it is not on its target release, returns whether it was.
+async fn update_client(state: &AppState, client: &Client, build: Option<&str>) -> Result<bool, anyhow::Error> {
+    // decided up front so no pooled connection is held while the client is sent anything, a
+    // rollout sends to many clients at once
+    let target = {
+        let mut conn = state.pool.get()?;
+        let Some(target) = target_release(&mut conn, client)? else {
+            // nothing to run in its channel, only clients stuck on a halted release are sent back
+            let halted = match build {
+                Some(build) => Release::get_by_build(&mut conn, build)?.is_some_and(|release| release.halted_on.is_some()),
+                None => false,
+            };
+            drop(conn);
+            if halted {
+                send_command(state, *client.id(), ServerWSCommand::Update.to_message("")).await.map_err(|e| e.0)?;
+            }
+            return Ok(halted);
+        };
+        if build == Some(target.build.as_str())
+            || ClientUpdate::sent_since(&mut conn, *client.id(), target.id, OffsetDateTime::now_utc() - UPDATE_RETRY)?
+        {
+            return Ok(false);
+        }
+        target
+    };
+
+    let manifest_url = format!("/api/releases/{}/manifest", target.id);
+    send_command(state, *client.id(), ServerWSCommand::Update.to_message(&manifest_url))
+        .await
+        .map_err(|e| e.0)?;
+    ClientUpdate::record(&mut *state.pool.get()?, *client.id(), target.id)?;
+    Ok(true)
+}
+
+/// Handle a client that just said `Hello`, completing its pending update or sending it one.
+pub async fn client_connected(state: &AppState, client: &Client, build: Option<&str>) -> Result<(), anyhow::Error> {
+    if let Some(build) = build {
+        ClientUpdate::mark_installed(&mut *state.pool.get()?, *client.id(), build)?;
+    }
+    update_client(state, client, build).await?;
+    Ok(())
+}
+
+/// Send `Update` to every connected client that is not on its target release, returns how many
+/// were sent one.
+pub async fn roll_out(state: &AppState) -> Result<usize, anyhow::Error> {
+    let connected: Vec<i64> = state.active_clients.lock().await.keys().copied().collect();
+    update_clients(state, &connected).await
+}
+
+/// `roll_out` limited to the given clients, those not connected are skipped.
+async fn update_clients(state: &AppState, ids: &[i64]) -> Result<usize, anyhow::Error> {
+    let connected: Vec<i64> = {
+        let active_clients = state.active_clients.lock().await;
+        ids.iter().copied().filter(|id| active_clients.contains_key(id)).collect()
+    };
+    let (clients, capabilities) = {
+        let mut conn = state.pool.get()?;
+        let clients: Vec<Client> = Client::get_all(&mut conn)?
+            .into_iter()
+            .filter(|client| connected.contains(client.id()))
+            .collect();
+        (clients, Capabilities::get_for_clients(&mut conn, &connected)?)
+    };
+
+    let mut updated = 0;
+    for client in &clients {
+        let build = capabilities.get(client.id()).and_then(|capabilities| capabilities.client_build.as_deref());
+        match update_client(state, client, build).await {
+            Ok(true) => updated += 1,
+            Ok(false) => {}
+            Err(e) => warn!("Failed to update client {}: {}", client.id(), e),
+        }
+    }
+    Ok(updated)
+}
+
+/// Send `Update` to connected clients without a release that still run the server's previous
+/// bundle, see `crate::assets`. Returns how many were sent one.
+pub async fn bundle_changed(state: &AppState, previous_build: &str) -> Result<usize, anyhow::Error> {
+    let connected: Vec<i64> = state.active_clients.lock().await.keys().copied().collect();
+    let targets: Vec<i64> = {
+        let mut conn = state.pool.get()?;
+        let capabilities = Capabilities::get_for_clients(&mut conn, &connected)?;
+        let mut targets = Vec::new();
+        for client in Client::get_all(&mut conn)?.into_iter().filter(|client| connected.contains(client.id())) {
+            let build = capabilities.get(client.id()).and_then(|capabilities| capabilities.client_build.as_deref());
+            if build == Some(previous_build) && target_release(&mut conn, &client)?.is_none() {
+                targets.push(*client.id());
+            }
+        }
+        targets
+    };
+
+    for client in &targets {
+        if let Err(e) = send_command(state, *client, ServerWSCommand::Update.to_message("")).await {
+            warn!("Failed to update client {}: {}", client, e.0);
+        }
+    }
+    Ok(targets.len())
+}
+
+/// Fail updates past their deadline, settle those that made it through their observation window
+/// and halt the releases that failed too often.
+async fn check_updates(state: &AppState) -> Result<(), anyhow::Error> {
+    let now = OffsetDateTime::now_utc();
+    let mut failed_releases = BTreeSet::new();
+    {
+        let mut conn = state.pool.get()?;
+        for update in ClientUpdate::get_due(&mut conn, UpdateStatus::Sent, now - RECONNECT_DEADLINE)? {
+            update.set_status(&mut conn, UpdateStatus::Failed, Some("Did not come back with the release in time"))?;
+            failed_releases.insert(update.release_id);
+        }
+        for update in ClientUpdate::get_due(&mut conn, UpdateStatus::Installed, now - OBSERVATION_WINDOW)? {
+            let installed = update.completed_on.unwrap_or(update.sent_on);
+            let errors = ClientLog::count_for_client(
+                &mut conn,
+                update.client_id,
+                LogLevel::Error,
+                installed,
+                installed + OBSERVATION_WINDOW,
+            )?;
+            if errors > 0 {
+                let failure = format!("Logged {} errors after updating", errors);
+                update.set_status(&mut conn, UpdateStatus::Failed, Some(&failure))?;
+                failed_releases.insert(update.release_id);
+            } else {
+                update.set_status(&mut conn, UpdateStatus::Healthy, None)?;
+            }
+        }
+    }
+
+    for release in failed_releases {
+        if let Err(e) = evaluate(state, release).await {
+            error!("Failed to evaluate release {}: {}", release, e);
+        }
+    }
+    Ok(())
+}
+
+/// Whether `failed` of `total` updates is more than a release with `max_failure_percent` allows.
+fn exceeds_failure_budget(failed: usize, total: usize, max_failure_percent: i32) -> bool {
+    failed * 100 > total * max_failure_percent as usize
+}
+
+/// Halt the release if too many of its updates failed and roll its connected clients back,
+/// returns whether it was halted. Clients that are offline are rolled back when they reconnect.
+async fn evaluate(state: &AppState, release_id: i64) -> Result<bool, anyhow::Error> {
+    let (release, updates) = {
+        let mut conn = state.pool.get()?;
+        let Some(release) = Release::get_by_id(&mut conn, release_id)? else {
+            return Ok(false);
+        };
+        (release, ClientUpdate::get_for_release(&mut conn, release_id)?)
+    };
+    let failed = updates.iter().filter(|update| update.status == UpdateStatus::Failed.as_str()).count();
+    if release.halted_on.is_some() || !exceeds_failure_budget(failed, updates.len(), release.max_failure_percent) {
+        return Ok(false);
+    }
+
+    let reason = format!("{} of {} updates failed", failed, updates.len());
+    Release::halt(&mut *state.pool.get()?, release.id, &reason)?;
+    let affected: BTreeSet<i64> = updates.iter().map(|update| update.client_id).collect();
+    let affected: Vec<i64> = affected.into_iter().collect();
+    let rolled_back = update_clients(state, &affected).await?;
+    warn!("Halted release {}, {}. Rolled back {} connected clients.", release.version, reason, rolled_back);
+    Ok(true)
+}
+
+pub async fn watch_task(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(WATCH_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = check_updates(&state).await {
+            error!("Failed to check client updates: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn releases_halt_past_their_failure_budget() {
+        assert!(!exceeds_failure_budget(0, 0, 0));
+        assert!(!exceeds_failure_budget(0, 5, 0));
+        assert!(exceeds_failure_budget(1, 1, 0));
+        // at the budget is still fine, past it halts
+        assert!(!exceeds_failure_budget(1, 5, 20));
+        assert!(exceeds_failure_budget(2, 5, 20));
+        assert!(!exceeds_failure_budget(5, 5, 100));
+    }
+}
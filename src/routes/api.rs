@@ -1,69 +1,137 @@
-use std::collections::VecDeque;
+use std::collections::HashMap;
+use std::time::Duration;
 use std::sync::Arc;
-use crate::database::models::{ActiveClient, Client, NewClient, NewClientLog};
-use crate::{AppState};
+use crate::database::models::{metadata_value, ActiveClient, ChangeSource, Client, ClientSender, ClientMetadata, ClientSession, NewClient, NewClientLog, NewClientSession, QueuedCommand, Release, SessionTraffic, Status};
+use crate::{client_config, codec, install, exec, handshake, items, parse_duration, queue, releases, screen, shell, telemetry, AppState};
+use crate::client_config::ClientConfig;
 use anyhow::anyhow;
-use axum::extract::ws::{Message, WebSocket};
-use axum::extract::WebSocketUpgrade;
+use std::net::SocketAddr;
+use axum::extract::ws::{CloseFrame, Message, WebSocket};
+use axum::extract::{ConnectInfo, Path, Query, WebSocketUpgrade};
 use axum::response::{IntoResponse, Response};
-use axum::routing::{get, post};
+use axum::routing::{get, patch, post};
 use axum::{Extension, Form, Json, Router};
 use diesel::prelude::*;
-use diesel::{RunQueryDsl, SelectableHelper};
+use diesel::RunQueryDsl;
 use http::{HeaderMap, StatusCode};
 use maud::{html, Markup};
 use rand::Rng;
 use serde::Deserialize;
 use serde_json::Value;
 use time::{OffsetDateTime, PrimitiveDateTime};
-use futures_util::{stream::{StreamExt, SplitStream}};
+use futures_util::future::join_all;
+use futures_util::stream::{StreamExt, SplitStream};
 use phf::phf_map;
-use tokio::sync::Mutex;
+use tokio::sync::Notify;
 use crate::database::schema::client_logs::dsl::client_logs;
-use crate::error::ServerError;
+use crate::codec::{Codec, WireMessage};
+use crate::manifest::Manifest;
+use crate::error::{AppError, ServerError};
+use crate::routes::stats::RateQuery;
+use crate::selector::Selector;
+use tracing::{error, warn};
 
-const KEY_LENGTH: usize = 48;
-const AUTHORIZATION_KEY_LENGTH: usize = 8;
 // client sends auth key as json with key named key
 // server sends back either a key with key key or an error with key error
 
 
 // commands issued BY the server, directed at one or many clients
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ServerWSCommand {
-    Update, About, Response,
+    Update, About, Response, Craft, Rekey, Queued, Exec, Shell, Screen,
 }
 
 static SERVER_WS_COMMAND_STRINGS: phf::Map<&'static str, ServerWSCommand> = phf_map! {
-
+    "Update" => ServerWSCommand::Update,
+    "About" => ServerWSCommand::About,
+    "Response" => ServerWSCommand::Response,
+    "Craft" => ServerWSCommand::Craft,
+    "Rekey" => ServerWSCommand::Rekey,
+    "Queued" => ServerWSCommand::Queued,
+    "Exec" => ServerWSCommand::Exec,
+    "Shell" => ServerWSCommand::Shell,
+    "Screen" => ServerWSCommand::Screen,
 };
 
 static CLIENT_WS_COMMAND_STRINGS: phf::Map<&'static str, ClientWSCommand> = phf_map! {
-    "Log" => ClientWSCommand::Log(None)
+    "Log" => ClientWSCommand::Log(None),
+    "Response" => ClientWSCommand::Response(None),
+    "Items" => ClientWSCommand::Items(None),
+    "Power" => ClientWSCommand::Power(None),
+    "Metadata" => ClientWSCommand::Metadata(None),
+    "Ack" => ClientWSCommand::Ack(None),
+    "Shell" => ClientWSCommand::Shell(None),
 };
 
 impl ServerWSCommand {
-    // pub fn from_str(string: &str) -> Result<ServerWSCommand, ServerError> {
-    //     let command = if let Some((cmd_str, data)) = string.split_once(" ") {
-    //         let cmd = SERVER_WS_COMMAND_STRINGS.get(cmd_str).ok_or(anyhow!("Invalid command."))?;
-    //     } else {
-    //         SERVER_WS_COMMAND_STRINGS.get(string).ok_or(anyhow!("Invalid command."))?;
-    //     };
-    // }
+    pub const ALL: [ServerWSCommand; 9] = [
+        ServerWSCommand::Update,
+        ServerWSCommand::About,
+        ServerWSCommand::Response,
+        ServerWSCommand::Craft,
+        ServerWSCommand::Rekey,
+        ServerWSCommand::Queued,
+        ServerWSCommand::Exec,
+        ServerWSCommand::Shell,
+        ServerWSCommand::Screen,
+    ];
+
+    pub fn new(string: &str) -> Result<ServerWSCommand, ServerError> {
+        let cmd = SERVER_WS_COMMAND_STRINGS.get(string).ok_or(anyhow!("Invalid command."))?;
+        Ok(cmd.clone())
+    }
     pub fn as_str(&self) -> &'static str {
         match self {
             ServerWSCommand::Update => "Update",
             ServerWSCommand::About => "About",
             ServerWSCommand::Response => "Response",
+            ServerWSCommand::Craft => "Craft",
+            ServerWSCommand::Rekey => "Rekey",
+            ServerWSCommand::Queued => "Queued",
+            ServerWSCommand::Exec => "Exec",
+            ServerWSCommand::Shell => "Shell",
+            ServerWSCommand::Screen => "Screen",
+        }
+    }
+    /// Parse a command an operator may send directly, `Exec` and `Shell` are only sent through
+    /// their own pages and `Queued` only by the queue.
+    pub fn operator_command(string: &str) -> Result<ServerWSCommand, AppError> {
+        match ServerWSCommand::new(string.trim()) {
+            Ok(ServerWSCommand::Exec | ServerWSCommand::Shell | ServerWSCommand::Queued) | Err(_) => {
+                Err(AppError::BadRequest(format!("Unknown command \"{}\"", string)))
+            }
+            Ok(command) => Ok(command),
+        }
+    }
+    /// Component a client has to have for the command to do anything, see `handshake::route`.
+    pub fn required_component(&self) -> Option<&'static str> {
+        match self {
+            ServerWSCommand::Craft => Some("me_interface"),
+            ServerWSCommand::Screen => Some("gpu"),
+            _ => None,
+        }
+    }
+    /// Text frame sent to the client, arguments are separated from the command by a space.
+    pub fn to_message(&self, args: &str) -> Message {
+        if args.is_empty() {
+            Message::Text(self.as_str().to_string())
+        } else {
+            Message::Text(format!("{} {}", self.as_str(), args))
         }
     }
 }
 
 // commands issued by the client, directed at the server
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ClientWSCommand {
     Log(Option<String>),
     Response(Option<String>),
     Discord(Option<String>),
+    Items(Option<String>),
+    Power(Option<String>),
+    Metadata(Option<String>),
+    Ack(Option<String>),
+    Shell(Option<String>),
 }
 
 impl ClientWSCommand {
@@ -74,6 +142,11 @@ impl ClientWSCommand {
                 ClientWSCommand::Log(None)      => Ok(ClientWSCommand::Log(Some(data.to_string()))),
                 ClientWSCommand::Response(None) => Ok(ClientWSCommand::Response(Some(data.to_string()))),
                 ClientWSCommand::Discord(None)  => Ok(ClientWSCommand::Discord(Some(data.to_string()))),
+                ClientWSCommand::Items(None)    => Ok(ClientWSCommand::Items(Some(data.to_string()))),
+                ClientWSCommand::Power(None)    => Ok(ClientWSCommand::Power(Some(data.to_string()))),
+                ClientWSCommand::Metadata(None) => Ok(ClientWSCommand::Metadata(Some(data.to_string()))),
+                ClientWSCommand::Ack(None)      => Ok(ClientWSCommand::Ack(Some(data.to_string()))),
+                ClientWSCommand::Shell(None)    => Ok(ClientWSCommand::Shell(Some(data.to_string()))),
                 _ => Err(anyhow!("WTF").into()) // WTF as in how did we end up here! unreachable
             }
         } else {
@@ -86,7 +159,11 @@ impl ClientWSCommand {
             ClientWSCommand::Log(_)      => "Log",
             ClientWSCommand::Response(_) => "Response",
             ClientWSCommand::Discord(_)  => "Discord",
-            _ => "None"
+            ClientWSCommand::Items(_)    => "Items",
+            ClientWSCommand::Power(_)    => "Power",
+            ClientWSCommand::Metadata(_) => "Metadata",
+            ClientWSCommand::Ack(_)      => "Ack",
+            ClientWSCommand::Shell(_)    => "Shell",
         }
     }
 }
@@ -96,6 +173,14 @@ pub fn router(state: Extension<Arc<AppState>>) -> Router {
         .route("/new", post(generate_auth_snippet))
         .route("/authenticate", post(generate_api_snippet))
         .route("/ws", get(websocket))
+        .route("/manifest", get(client_manifest))
+        .route("/config", get(client_config))
+        .route("/releases/:id/manifest", get(release_manifest))
+        .route("/releases/:id/files/*path", get(release_file))
+        .route("/items/rates", get(item_rates))
+        .route("/clients/:id", patch(update_client_metadata))
+        .route("/commands", post(post_command))
+        .route("/clients/:id/shell", get(shell_socket))
         .layer(Extension(state))
 }
 
@@ -103,6 +188,7 @@ pub fn router(state: Extension<Arc<AppState>>) -> Router {
 /// websocket handler.
 async fn websocket(
     Extension(state): Extension<Arc<AppState>>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
     ws: WebSocketUpgrade,
     header_map: HeaderMap,
 ) -> Result<Response, ServerError> {
@@ -114,7 +200,18 @@ async fn websocket(
 
     let maybe_client = Client::get_by_api(&mut conn, api_key.unwrap().to_str()?)?;
     if let Some(client) = maybe_client {
-        Ok(ws.on_upgrade(|socket| websocket_handle(socket, client, state)))
+        if client.revoked {
+            return Ok((StatusCode::FORBIDDEN, "Client access has been revoked!").into_response());
+        }
+        // the protocol version is filled in from the client's Hello
+        let session = NewClientSession {
+            client_id: *client.id(),
+            remote_addr: Some(remote_addr.to_string()),
+            protocol_version: None,
+        };
+        Ok(ws
+            .protocols(codec::PROTOCOLS)
+            .on_upgrade(|socket| websocket_handle(socket, client, session, state)))
     } else {
         Ok((
             StatusCode::UNAUTHORIZED,
@@ -125,61 +222,627 @@ async fn websocket(
 }
 
 // handle incoming websocket connections by storing each socket in the local state
-async fn websocket_handle(socket: WebSocket, client: Client, state: Arc<AppState>) {
-    let (sender, receiver) = socket.split();
-    let message_queue: Arc<Mutex<VecDeque<Message>>> = Arc::new(Mutex::new(VecDeque::new()));
+async fn websocket_handle(
+    mut socket: WebSocket,
+    client: Client,
+    mut session: NewClientSession,
+    state: Arc<AppState>,
+) {
     let client_id = *client.id();
-    let thread_handle = tokio::spawn(handle_client(client_id, receiver, state.clone()));
-    let active = ActiveClient {
-        sender,
-        client,
-        message_queue,
-        thread_handle
+    let codec = Codec::negotiate(socket.protocol().and_then(|protocol| protocol.to_str().ok()));
+    let hello = match handshake::receive_hello(&mut socket).await {
+        Ok(hello) => hello,
+        Err(rejection) => {
+            warn!("Rejected connection from client {}: {}", client_id, rejection.reason);
+            rejection.close(&mut socket).await;
+            return;
+        }
     };
-    
-    
-    state.active_clients.lock().await.insert(client_id, active);
+    session.protocol_version = Some(hello.protocol.to_string());
+    if let Err(e) = state.pool.get().map_err(anyhow::Error::from)
+        .and_then(|mut conn| hello.to_capabilities(client_id).store(&mut conn)) {
+        error!("Failed to store capabilities of client {}: {}", client_id, e);
+    }
+
+    let session_id = match start_session(&client, &session, &state) {
+        Ok(session_id) => session_id,
+        Err(e) => {
+            error!("Failed to start session for client {}: {}", client_id, e);
+            return;
+        }
+    };
+
+    let (sender, receiver) = socket.split();
+    let traffic = Arc::new(SessionTraffic::default());
+    let evicted = Arc::new(Notify::new());
+    let sender = ClientSender::new(sender, traffic.clone());
+    // taken before the client is listed, so commands dispatched to it wait for the queued ones
+    let mut outbox = sender.lock().await;
+
+    {
+        // hold the lock until inserted, so a connection that ends immediately is still cleaned up
+        let mut active_clients = state.active_clients.lock().await;
+        let thread_handle = tokio::spawn(supervise_client(
+            client_id,
+            session_id,
+            receiver,
+            traffic.clone(),
+            evicted.clone(),
+            codec,
+            state.clone(),
+        ));
+        active_clients.insert(client_id, ActiveClient {
+            sender,
+            client: client.clone(),
+            thread_handle,
+            session_id,
+            connected_on: OffsetDateTime::now_utc(),
+            traffic,
+            evicted,
+            codec,
+        });
+    }
+
+    if let Err(e) = queue::deliver_pending(&state, client_id, &mut outbox).await {
+        error!("Failed to deliver queued commands to client {}: {}", client_id, e);
+    }
+    drop(outbox);
+
+    if let Err(e) = releases::client_connected(&state, &client, hello.build.as_deref()).await {
+        error!("Failed to check release of client {}: {}", client_id, e);
+    }
+}
+
+/// Open a `client_sessions` row and mark the client as connected.
+fn start_session(
+    client: &Client,
+    session: &NewClientSession,
+    state: &AppState,
+) -> Result<i64, anyhow::Error> {
+    use crate::database::schema::client_sessions;
+    use crate::database::schema::clients::dsl::*;
+
+    let mut conn = state.pool.get()?;
+    let time_now = OffsetDateTime::now_utc();
+    diesel::update(client)
+        .set((
+            status.eq(Status::Connected.as_str()),
+            accessed_on.eq(PrimitiveDateTime::new(time_now.date(), time_now.time())),
+        ))
+        .execute(&mut conn)?;
+
+    let session_id = diesel::insert_into(client_sessions::table)
+        .values(session)
+        .returning(client_sessions::id)
+        .get_result(&mut conn)?;
+    Ok(session_id)
+}
+
+/// Run the receiving task of a connection and record how it ended, even if the task panicked.
+async fn supervise_client(
+    id: i64,
+    session_id: i64,
+    receiver: SplitStream<WebSocket>,
+    traffic: Arc<SessionTraffic>,
+    evicted: Arc<Notify>,
+    codec: Codec,
+    state: Arc<AppState>,
+) {
+    let reason = tokio::spawn(handle_client(id, receiver, traffic.clone(), evicted, codec, state.clone()))
+        .await
+        .unwrap_or_else(|e| format!("Handler failed: {}", e));
+
+    let mut active_clients = state.active_clients.lock().await;
+    // a reconnect may already have replaced this connection
+    if active_clients.get(&id).is_some_and(|active| active.session_id == session_id) {
+        active_clients.remove(&id);
+    }
+    let still_connected = active_clients.contains_key(&id);
+    drop(active_clients);
+    if !still_connected {
+        shell::client_disconnected(id, &state).await;
+    }
+
+    if let Err(e) = end_session(id, session_id, &reason, &traffic, still_connected, &state) {
+        error!("Failed to end session {} for client {}: {}", session_id, id, e);
+    }
 }
 
-/// Tokio task for each active client to receive incoming ws messages.
-async fn handle_client(id: i64, mut receiver: SplitStream<WebSocket>, state: Arc<AppState>) {
-    while let Some(message) = receiver.next().await {
+fn end_session(
+    client: i64,
+    session_id: i64,
+    reason: &str,
+    traffic: &SessionTraffic,
+    still_connected: bool,
+    state: &AppState,
+) -> Result<(), anyhow::Error> {
+    use crate::database::schema::clients::dsl::*;
+
+    let mut conn = state.pool.get()?;
+    ClientSession::finish(&mut conn, session_id, reason, traffic)?;
+    if !still_connected {
+        let time_now = OffsetDateTime::now_utc();
+        diesel::update(clients.find(client))
+            .set((
+                status.eq(Status::Enrolled.as_str()),
+                accessed_on.eq(PrimitiveDateTime::new(time_now.date(), time_now.time())),
+            ))
+            .execute(&mut conn)?;
+    }
+    Ok(())
+}
+
+fn close_reason(frame: Option<CloseFrame>) -> String {
+    match frame {
+        Some(frame) if frame.reason.is_empty() => format!("Closed by client ({})", frame.code),
+        Some(frame) => format!("Closed by client ({}): {}", frame.code, frame.reason),
+        None => "Closed by client".to_string(),
+    }
+}
+
+/// Tokio task for each active client to receive incoming ws messages, returns why the
+/// connection ended.
+async fn handle_client(
+    id: i64,
+    mut receiver: SplitStream<WebSocket>,
+    traffic: Arc<SessionTraffic>,
+    evicted: Arc<Notify>,
+    codec: Codec,
+    state: Arc<AppState>,
+) -> String {
+    loop {
+        let message = tokio::select! {
+            message = receiver.next() => message,
+            _ = evicted.notified() => return "Evicted by server".to_string(),
+        };
+        let Some(message) = message else { break };
         if let Err(e) = message {
-            eprintln!("Error receiving message for client {}: {:?}", id, e);
-            break;
+            warn!("Error receiving message for client {}: {:?}", id, e);
+            return format!("Receive error: {}", e);
         }
-        dbg!(&message);
-        match message.unwrap() {
+        let message = message.unwrap();
+        traffic.record_in(&message);
+        state.fleet_stats.lock().await.record_message(id);
+        match message {
             Message::Text(text) => {
-                let cmd = ClientWSCommand::new(&text); // TODO unwrap
-                match cmd.unwrap() {
-                    ClientWSCommand::Log(log_message) => {
-                        let mut conn = state.pool.get().unwrap();
-                        NewClientLog {
-                            client_id: id,
-                            log_message: log_message.unwrap_or("-- no log body sent --".to_string())
-                        }
-                            .insert_into(client_logs)
-                            .execute(&mut conn)
-                            .expect("Failed");
+                let Ok(cmd) = ClientWSCommand::new(&text) else {
+                    warn!("Unknown command from client {}: {}", id, text);
+                    continue;
+                };
+                handle_command(id, cmd, &state).await;
+            }
+            Message::Binary(frame) if codec.compact => {
+                let messages = match codec.decode(&frame) {
+                    Ok(messages) => messages,
+                    Err(e) => {
+                        warn!("Invalid binary frame from client {}: {}", id, e);
+                        continue;
                     }
-                    ClientWSCommand::Response(_) => {}
-                    ClientWSCommand::Discord(text) => {
-                        let json_body = r#"{"name":"test","content":"test message"}"#;
-                        todo!()
+                };
+                for message in messages {
+                    match message {
+                        WireMessage::Client(cmd) => handle_command(id, cmd, &state).await,
+                        WireMessage::Screen(frame) => {
+                            if let Err(e) = screen::apply_frame(id, frame, &state).await {
+                                warn!("Failed to apply screen frame from client {}: {}", id, e);
+                            }
+                        }
                     }
                 }
-                // desired messages
             }
-            Message::Binary(_) => unimplemented!("Client Driver Unsupported"),
+            Message::Binary(frame) => {
+                if let Err(e) = screen::handle_frame(id, &frame, &state).await {
+                    warn!("Invalid screen frame from client {}: {}", id, e);
+                }
+            }
             Message::Ping(_) => {} // todo we should reply with a pong
             Message::Pong(_) => {} // safely ignore pong messages
-            Message::Close(_) => return,
+            Message::Close(frame) => return close_reason(frame),
         }
-        // handle different message types here
-        // let mut queue = message_queue.lock().await;
-        // queue.push_back(msg);
     }
+    "Connection lost".to_string()
+}
+
+/// Handle a command from a client, whether it came as text or inside a binary frame.
+async fn handle_command(id: i64, cmd: ClientWSCommand, state: &AppState) {
+    match cmd {
+        ClientWSCommand::Log(log_message) => {
+            if let Err(e) = handle_log(id, log_message.as_deref(), state) {
+                error!("Failed to store log from client {}: {}", id, e);
+            }
+        }
+        ClientWSCommand::Response(Some(body)) => {
+            if let Err(e) = handle_response(id, &body, state).await {
+                warn!("Invalid response from client {}: {}", id, e.0);
+            }
+        }
+        ClientWSCommand::Response(None) => {}
+        ClientWSCommand::Items(Some(snapshot)) => {
+            if let Err(e) = items::handle_snapshot(id, &snapshot, state).await {
+                warn!("Failed to store item snapshot for client {}: {}", id, e.0);
+            }
+        }
+        ClientWSCommand::Items(None) => {}
+        ClientWSCommand::Power(Some(report)) => {
+            if let Err(e) = telemetry::handle_power(id, &report, state).await {
+                warn!("Failed to store power report for client {}: {}", id, e.0);
+            }
+        }
+        ClientWSCommand::Power(None) => {}
+        ClientWSCommand::Metadata(Some(report)) => {
+            if let Err(e) = handle_metadata(id, &report, state) {
+                warn!("Rejected metadata from client {}: {}", id, e);
+            }
+        }
+        ClientWSCommand::Metadata(None) => {}
+        ClientWSCommand::Ack(Some(entry)) => {
+            if let Err(e) = queue::handle_ack(id, &entry, state) {
+                warn!("Invalid ack from client {}: {}", id, e);
+            }
+        }
+        ClientWSCommand::Ack(None) => {}
+        ClientWSCommand::Shell(Some(body)) => {
+            if let Err(e) = shell::handle_shell(id, &body, state).await {
+                warn!("Invalid shell message from client {}: {}", id, e);
+            }
+        }
+        ClientWSCommand::Shell(None) => {}
+        ClientWSCommand::Discord(_) => {
+            warn!("Dropped a Discord message from client {}, relaying them is not supported yet.", id);
+        }
+    }
+}
+
+/// Store the body of a `Log` command, see `NewClientLog::from_body`.
+fn handle_log(id: i64, body: Option<&str>, state: &AppState) -> Result<(), anyhow::Error> {
+    let mut conn = state.pool.get()?;
+    NewClientLog::from_body(id, body.unwrap_or("-- no log body sent --"))
+        .insert_into(client_logs)
+        .execute(&mut conn)?;
+    Ok(())
+}
+
+/// Apply `Metadata <json>` self-reported by a client when it connects.
+fn handle_metadata(id: i64, body: &str, state: &AppState) -> Result<(), anyhow::Error> {
+    let changes: ClientMetadata = serde_json::from_str(body)?;
+    changes.validate().map_err(|errors| anyhow!(errors.join(" ")))?;
+
+    let mut conn = state.pool.get()?;
+    let client = Client::get_by_id(&mut conn, id)?.ok_or(anyhow!("Client no longer exists."))?;
+    client.update_metadata(&mut conn, &changes, ChangeSource::Client)?;
+    Ok(())
+}
+
+/// Update manifest of the client bundle, see `crate::manifest`.
+async fn client_manifest(Extension(state): Extension<Arc<AppState>>) -> Json<Manifest> {
+    Json(state.assets.manifest().as_ref().clone())
+}
+
+/// Generated configuration of the client presenting its API key, see `crate::client_config`.
+async fn client_config(
+    Extension(state): Extension<Arc<AppState>>,
+    header_map: HeaderMap,
+) -> Result<String, AppError> {
+    let api_key = header_map
+        .get("X-API-Key")
+        .and_then(|api_key| api_key.to_str().ok())
+        .ok_or(AppError::BadRequest("Missing API key in request!".to_string()))?;
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    let client = Client::get_by_api(&mut conn, api_key)
+        .ok()
+        .flatten()
+        .ok_or(AppError::Unauthorized("No access authorized with given api key!".to_string()))?;
+    if client.revoked {
+        return Err(AppError::Forbidden("Client access has been revoked!".to_string()));
+    }
+
+    let server_url = client_config::server_url(state.config.public_url.as_deref(), &header_map)
+        .ok_or(AppError::BadRequest("Unknown server address, set PUBLIC_URL.".to_string()))?;
+    let config = ClientConfig::for_client(&server_url, &client).map_err(AppError::internal)?;
+    Ok(config.to_lua())
+}
+
+fn get_release(state: &AppState, release_id: i64) -> Result<Release, AppError> {
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    Release::get_by_id(&mut conn, release_id)
+        .map_err(AppError::internal)?
+        .ok_or(AppError::NotFound(format!("No release with id {}", release_id)))
+}
+
+/// Update manifest of a release, see `crate::releases`.
+async fn release_manifest(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(release_id): Path<i64>,
+) -> Result<Json<Manifest>, AppError> {
+    let release = get_release(&state, release_id)?;
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    releases::manifest(&mut conn, &release).map(Json).map_err(AppError::internal)
+}
+
+async fn release_file(
+    Extension(state): Extension<Arc<AppState>>,
+    Path((release_id, path)): Path<(i64, String)>,
+) -> Result<Vec<u8>, AppError> {
+    let release = get_release(&state, release_id)?;
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    release
+        .file(&mut conn, &format!("/{}", path))
+        .map_err(AppError::internal)?
+        .ok_or(AppError::NotFound(format!("Release {} has no file /{}", release.version, path)))
+}
+
+/// Browser end of a remote shell, only for clients that allow `Exec` since a shell can run anything.
+async fn shell_socket(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(client_id): Path<i64>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, AppError> {
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    let client = Client::get_by_id(&mut conn, client_id)
+        .map_err(AppError::internal)?
+        .ok_or(AppError::NotFound(format!("No client with id {}", client_id)))?;
+    if !client.allow_exec || client.revoked {
+        return Err(AppError::Forbidden("Remote shells are not allowed on this client.".to_string()));
+    }
+
+    Ok(ws.on_upgrade(move |socket| shell::bridge(socket, client_id, state)))
+}
+
+/// Route a `Response <Command> <body>` from a client to whatever issued the original command.
+async fn handle_response(id: i64, body: &str, state: &AppState) -> Result<(), ServerError> {
+    let (cmd_str, data) = body.split_once(' ').unwrap_or((body, ""));
+    match ServerWSCommand::new(cmd_str)? {
+        ServerWSCommand::Craft => items::handle_craft_response(id, data, state).await,
+        ServerWSCommand::Exec => exec::handle_exec_response(id, data, state),
+        _ => Ok(()),
+    }
+}
+
+/// Send a command to a single connected client.
+pub async fn send_command(
+    state: &AppState,
+    client_id: i64,
+    message: Message,
+) -> Result<(), ServerError> {
+    let sender = state.active_clients.lock().await
+        .get(&client_id)
+        .map(|client| client.sender.clone())
+        .ok_or(anyhow!("Client {} is not connected.", client_id))?;
+    sender.send(message).await?;
+    Ok(())
+}
+
+/// How long a single client may take to accept a command before it is considered broken.
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How sending a command to one selected client went.
+pub enum DeliveryStatus {
+    Sent,
+    Offline,
+    TimedOut,
+    Failed(String),
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Sent => "Sent",
+            DeliveryStatus::Offline => "Offline",
+            DeliveryStatus::TimedOut => "Timed out",
+            DeliveryStatus::Failed(_) => "Failed",
+        }
+    }
+}
+
+pub struct Delivery {
+    pub client_id: i64,
+    pub name: String,
+    pub status: DeliveryStatus,
+    /// Queue entry when the command was sent with guaranteed delivery.
+    pub queued: Option<i64>,
+}
+
+/// Send a command to every target concurrently, reporting the outcome for each of them.
+/// Connections that fail or time out are evicted so the next dispatch does not wait on them.
+///
+/// With a `queue_ttl` the command is also stored in the outbound queue, so clients that did not
+/// get it now receive it when they reconnect, see `crate::queue`.
+pub async fn dispatch_command(
+    state: &AppState,
+    targets: &[Client],
+    message: Message,
+    queue_ttl: Option<Duration>,
+) -> Result<Vec<Delivery>, anyhow::Error> {
+    dispatch_each(state, targets, |_| message.clone(), queue_ttl).await
+}
+
+/// `dispatch_command` for a command an operator issued, clients lacking the component it needs are
+/// reported as failed instead, see `handshake::route`.
+pub async fn dispatch_operator_command(
+    state: &AppState,
+    targets: Vec<Client>,
+    command: &ServerWSCommand,
+    args: &str,
+    queue_ttl: Option<Duration>,
+) -> Result<Vec<Delivery>, anyhow::Error> {
+    let (targets, mut rejected) = handshake::route(&mut *state.pool.get()?, targets, command)?;
+    let mut deliveries = dispatch_command(state, &targets, command.to_message(args), queue_ttl).await?;
+    deliveries.append(&mut rejected);
+    Ok(deliveries)
+}
+
+/// `dispatch_command` with a message of its own for each target.
+pub async fn dispatch_each(
+    state: &AppState,
+    targets: &[Client],
+    message_for: impl Fn(&Client) -> Message,
+    queue_ttl: Option<Duration>,
+) -> Result<Vec<Delivery>, anyhow::Error> {
+    let mut messages: HashMap<i64, Message> = targets
+        .iter()
+        .map(|target| (*target.id(), message_for(target)))
+        .collect();
+    let queued = match queue_ttl {
+        Some(ttl) => {
+            let commands: Vec<(i64, &str)> = messages
+                .iter()
+                .filter_map(|(&client_id, message)| match message {
+                    Message::Text(text) => Some((client_id, text.as_str())),
+                    _ => None,
+                })
+                .collect();
+            queue::enqueue(&mut *state.pool.get()?, &commands, ttl)?
+        }
+        None => HashMap::new(),
+    };
+    for (client_id, &entry) in &queued {
+        if let Some(Message::Text(text)) = messages.get(client_id) {
+            let wrapped = queue::to_message(entry, text);
+            messages.insert(*client_id, wrapped);
+        }
+    }
+    // the lock is only held to pick the connections, a slow client must not stall everyone else
+    let connections: Vec<(i64, i64, ClientSender, Message)> = {
+        let active_clients = state.active_clients.lock().await;
+        active_clients
+            .iter()
+            .filter_map(|(&client_id, client)| {
+                Some((client_id, client.session_id, client.sender.clone(), messages.remove(&client_id)?))
+            })
+            .collect()
+    };
+    let sessions: HashMap<i64, i64> = connections.iter().map(|&(client_id, session_id, ..)| (client_id, session_id)).collect();
+    let sends = connections.into_iter().map(|(client_id, _, sender, message)| async move {
+        let status = match tokio::time::timeout(SEND_TIMEOUT, sender.send(message)).await {
+            Ok(Ok(())) => DeliveryStatus::Sent,
+            Ok(Err(e)) => DeliveryStatus::Failed(e.to_string()),
+            Err(_) => DeliveryStatus::TimedOut,
+        };
+        (client_id, status)
+    });
+    let mut statuses: HashMap<i64, DeliveryStatus> = join_all(sends).await.into_iter().collect();
+
+    let mut active_clients = state.active_clients.lock().await;
+    for (client_id, status) in &statuses {
+        if matches!(status, DeliveryStatus::Sent) {
+            continue;
+        }
+        // only the connection that failed, the client may have reconnected in the meantime
+        if active_clients.get(client_id).map(|client| client.session_id) != sessions.get(client_id).copied() {
+            continue;
+        }
+        if let Some(client) = active_clients.remove(client_id) {
+            warn!("Evicting client {} after failed send: {}", client_id, status.as_str());
+            client.evicted.notify_one();
+        }
+    }
+    drop(active_clients);
+
+    let sent: Vec<i64> = statuses
+        .iter()
+        .filter(|(_, status)| matches!(status, DeliveryStatus::Sent))
+        .filter_map(|(client_id, _)| queued.get(client_id).copied())
+        .collect();
+    QueuedCommand::mark_sent(&mut *state.pool.get()?, &sent)?;
+
+    Ok(targets
+        .iter()
+        .map(|target| Delivery {
+            client_id: *target.id(),
+            name: target.name.clone(),
+            status: statuses.remove(target.id()).unwrap_or(DeliveryStatus::Offline),
+            queued: queued.get(target.id()).copied(),
+        })
+        .collect())
+}
+
+/// Parse a queue TTL such as `30m` or `7d`.
+pub fn parse_queue_ttl(ttl: &str) -> Result<Duration, AppError> {
+    parse_duration(ttl).ok_or(AppError::BadRequest(format!("Invalid queue TTL \"{}\", e.g. 6h, at most 365d", ttl)))
+}
+
+#[derive(Deserialize)]
+struct CommandRequest {
+    selector: String,
+    command: String,
+    #[serde(default)]
+    args: String,
+    /// Queue the command for clients that do not get it right away, e.g. `6h`.
+    queue_ttl: Option<String>,
+}
+
+/// POST a command to every client matched by a selector, see `crate::selector`.
+async fn post_command(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(request): Json<CommandRequest>,
+) -> Result<Json<Value>, AppError> {
+    let selector = Selector::parse(&request.selector).map_err(AppError::BadRequest)?;
+    let command = ServerWSCommand::operator_command(&request.command)?;
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    let targets = selector.resolve(&mut conn).map_err(AppError::internal)?;
+    let queue_ttl = request.queue_ttl.as_deref().map(parse_queue_ttl).transpose()?;
+    let deliveries = dispatch_operator_command(&state, targets, &command, request.args.trim(), queue_ttl)
+        .await
+        .map_err(AppError::internal)?;
+
+    let results: Vec<Value> = deliveries
+        .iter()
+        .map(|delivery| serde_json::json!({
+            "id": delivery.client_id,
+            "name": delivery.name,
+            "status": delivery.status.as_str(),
+            "queued": delivery.queued,
+            "error": match &delivery.status {
+                DeliveryStatus::Failed(error) => Some(error),
+                _ => None,
+            },
+        }))
+        .collect();
+    Ok(Json(serde_json::json!({
+        "selector": selector.to_string(),
+        "command": command.as_str(),
+        "results": results,
+    })))
+}
+
+/// JSON item rates, `?window=6h&limit=20` selects the snapshot window and number of items.
+async fn item_rates(
+    Extension(state): Extension<Arc<AppState>>,
+    Query(query): Query<RateQuery>,
+) -> Result<Json<Value>, AppError> {
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    let rates = items::item_rates(&mut conn, query.window()?).map_err(AppError::internal)?;
+    let running_out = items::running_out(rates.clone(), query.limit());
+    let top_movers = items::top_movers(rates, query.limit());
+
+    Ok(Json(serde_json::json!({
+        "top_movers": top_movers,
+        "running_out": running_out,
+    })))
+}
+
+/// PATCH a client's description and location, see `ClientMetadata`.
+async fn update_client_metadata(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(client_id): Path<i64>,
+    Json(changes): Json<ClientMetadata>,
+) -> Result<Json<Value>, AppError> {
+    changes.validate().map_err(|errors| AppError::BadRequest(errors.join(" ")))?;
+
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    let client = Client::get_by_id(&mut conn, client_id)
+        .map_err(AppError::internal)?
+        .ok_or(AppError::NotFound(format!("No client with id {}", client_id)))?;
+    let updated = client
+        .update_metadata(&mut conn, &changes, ChangeSource::Api)
+        .map_err(AppError::internal)?;
+
+    Ok(Json(serde_json::json!({
+        "id": client_id,
+        "name": updated.name,
+        "description": updated.description,
+        "location": updated.location,
+    })))
 }
 
 pub fn request_auth_snippet() -> Markup {
@@ -188,6 +851,8 @@ pub fn request_auth_snippet() -> Markup {
             div id="key-result" class="flex flex-col align-center" {}
             form .flex.flex-col hx-post="/api/new" hx-target="#key-result" {
                 input name="name" type="text" placeholder="Client Name" .input.input-bordered.input-primary.w-full.max-w-xs;
+                input name="description" type="text" placeholder="Description (optional)" .input.input-bordered.w-full.max-w-xs;
+                input name="location" type="text" placeholder="Location (optional)" .input.input-bordered.w-full.max-w-xs;
                 button class="btn btn-ghost" action="submit" { "Generate" }
             }
         }
@@ -197,28 +862,36 @@ pub fn request_auth_snippet() -> Markup {
 #[derive(Deserialize)]
 pub struct AuthFormData {
     name: String,
+    #[serde(flatten)]
+    metadata: ClientMetadata,
 }
 
 // can add controls and restraints here, will return result
 pub async fn generate_auth_snippet(
     Extension(state): Extension<Arc<AppState>>,
+    header_map: HeaderMap,
     Form(data): Form<AuthFormData>,
 ) -> Result<Markup, ServerError> {
-    use crate::database::schema::clients;
+    if let Err(errors) = data.metadata.validate() {
+        return Ok(html! { @for error in errors { p { (error) } } });
+    }
 
     let mut connection = state.pool.get()?;
-    let auth_key = create_api_key(AUTHORIZATION_KEY_LENGTH);
-    let client = NewClient::new(&data.name, &auth_key);
-    let inserted = diesel::insert_into(clients::table)
-        .values(&client)
-        .returning(Client::as_returning())
-        .get_result(&mut connection)?;
+    let auth_key = create_api_key(state.settings().auth_code_length);
+    let mut client = NewClient::new(&data.name, &auth_key);
+    client.description = data.metadata.description.as_deref().and_then(metadata_value);
+    client.location = data.metadata.location.as_deref().and_then(metadata_value);
+    client.create(&mut connection)?;
 
     Ok(html! {
         p {"Successfully created new client with name: \"" (data.name) "\"!"}
         code class="text-center p-4" {
             (auth_key)
         }
+        @if let Some(server_url) = client_config::server_url(state.config.public_url.as_deref(), &header_map) {
+            p { "To install and enroll a new computer in one step, run:" }
+            code class="p-4" { (install::command(&server_url, Some(&auth_key))) }
+        }
     })
 }
 
@@ -236,8 +909,11 @@ pub async fn generate_api_snippet(
     let auth = strip_outer_quotes(&auth);
     let new_client = Client::get_by_auth(&mut conn, auth)?
         .ok_or(ServerError(anyhow!("Invalid authcode received!")))?;
+    if new_client.revoked {
+        return Ok((StatusCode::FORBIDDEN, "Client access has been revoked!").into_response());
+    }
 
-    let new_key = create_api_key(KEY_LENGTH);
+    let new_key = create_api_key(state.settings().api_key_length);
     let time_now = OffsetDateTime::now_utc();
 
     diesel::update(&new_client)
@@ -271,3 +947,66 @@ pub fn create_api_key(length: usize) -> String {
 pub async fn api_sitemap() -> impl IntoResponse {
     (StatusCode::NOT_IMPLEMENTED, "Unimplemented")
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::RwLock;
+    use diesel::r2d2::{ConnectionManager, Pool};
+    use tokio::sync::Mutex;
+    use crate::assets::Assets;
+    use crate::config::{Config, LogFormat};
+    use crate::settings::Settings;
+    use super::*;
+
+    /// State whose database is unreachable, so every handler that needs it fails quickly.
+    fn offline_state() -> AppState {
+        let database_url = "postgres://site@127.0.0.1:1/site";
+        let pool = Pool::builder()
+            .connection_timeout(Duration::from_millis(50))
+            .build_unchecked(ConnectionManager::new(database_url));
+        AppState {
+            pool,
+            active_clients: Arc::new(Mutex::new(HashMap::new())),
+            fleet_stats: Arc::new(Mutex::new(Default::default())),
+            shells: Arc::new(Mutex::new(HashMap::new())),
+            screens: Arc::new(Mutex::new(HashMap::new())),
+            assets: Assets::new(None),
+            settings: RwLock::new(Settings::default()),
+            config: Config {
+                bind_address: None,
+                database_url: database_url.to_string(),
+                pool_size: 1,
+                request_timeout: None,
+                log_format: LogFormat::Full,
+                static_dir: None,
+                public_url: None,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn decoded_commands_never_panic() {
+        let state = offline_state();
+        let codec = Codec::negotiate(Some(codec::COMPACT));
+        for tag in 1..=8u8 {
+            for payload in [None, Some(""), Some("garbage"), Some("Craft {}"), Some("{}")] {
+                let mut message = vec![tag];
+                match payload {
+                    Some(payload) => {
+                        message.push(1);
+                        message.extend_from_slice(payload.as_bytes());
+                    }
+                    None => message.push(0),
+                }
+                let mut frame = vec![0];
+                frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+                frame.extend_from_slice(&message);
+
+                for message in codec.decode(&frame).unwrap() {
+                    let WireMessage::Client(cmd) = message else { panic!("tag {} is not a command", tag) };
+                    handle_command(1, cmd, &state).await;
+                }
+            }
+        }
+    }
+}
@@ -1,53 +1,878 @@
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
 use axum::{Extension, Form, Router};
-use axum::extract::ws::Message;
-use axum::routing::{get, post};
-use futures_util::SinkExt;
+use axum::extract::Path;
+use http::HeaderMap;
+use axum::extract::ws::{close_code, CloseFrame, Message};
+use axum::routing::{delete, get, patch, post};
+use diesel::prelude::*;
 use maud::{html, Markup};
 use serde::Deserialize;
-use crate::AppState;
-use crate::error::ServerError;
-use crate::database::models::{ActiveClient, Client, MiniClient};
+use time::OffsetDateTime;
+use crate::{client_config, exec, releases, telemetry, AppState};
+use crate::client_config::ClientConfig;
+use crate::error::{AppError, ServerError};
+use crate::database::models::{Capabilities, ChangeSource, Client, ClientLog, ClientMetadata, ClientMetadataChange, ClientSession, LogLevel, MiniClient, QueuedCommand, ShellEvent, ShellSession, Tag, MAX_DESCRIPTION_LENGTH, MAX_LOCATION_LENGTH, MAX_TAG_LENGTH};
+use crate::layout::sparkline;
+use crate::routes::release_routes::{channel_select, parse_channel, roll_out};
+use crate::routes::api::{create_api_key, dispatch_operator_command, parse_queue_ttl, send_command, Delivery, DeliveryStatus, ServerWSCommand};
+use crate::selector::Selector;
 
 pub fn router(state: Extension<Arc<AppState>>) -> Router {
     Router::new()
         .route("/", get(clients_page))
         .route("/broadcast", post(clients_broadcast))
+        .route("/exec", post(run_exec))
+        .route("/:id", get(client_page))
+        .route("/:id/live", get(client_live))
+        .route("/:id/screen", get(client_screen))
+        .route("/:id/command", post(client_command))
+        .route("/:id/queue", get(client_queue))
+        .route("/:id/exec", get(client_executions))
+        .route("/:id/exec/toggle", post(toggle_exec))
+        .route("/:id/shell", get(shell_page))
+        .route("/:id/shell/:session", get(shell_replay))
+        .route("/:id/rotate", post(rotate_key))
+        .route("/:id/revoke", post(revoke_client))
+        .route("/:id/metadata", patch(edit_metadata))
+        .route("/:id/tags", post(add_tag))
+        .route("/:id/tags/:tag", delete(remove_tag))
+        .route("/:id/channel", post(set_channel))
+        .route("/:id/config", post(edit_config))
         .layer(Extension(state))
 }
 
-async fn ws_broadcast(receiving_clients: &mut HashMap<i64, ActiveClient>,
-                      message:  Message) -> Result<(), ServerError> {
-    for client in receiving_clients.values_mut() {
-        let message = message.clone();
-        client.sender.send(message).await?;
-    }
-    Ok(())
-}
-
 async fn clients_page(Extension(state): Extension<Arc<AppState>>) -> Result<Markup, ServerError> {
     let mut conn = state.pool.get()?;
     let received_clients = Client::get_all(&mut conn)?;
+    let mut tags: HashMap<i64, Vec<String>> = HashMap::new();
+    for (client, tag) in Tag::get_assignments(&mut conn)? {
+        tags.entry(client).or_default().push(tag);
+    }
 
     Ok(html! {
-        form hx-post="/clients/broadcast" hx-target="#broadcast-response" {
-            input type="text" placeholder="Query" name="query"
+        form .flex.gap-2 hx-post="/clients/broadcast" hx-target="#broadcast-response" {
+            input type="text" placeholder="Selector, e.g. tag:factory status:connected" name="selector"
+                value="all" class="input input-bordered w-full max-w-xs";
+            input type="text" placeholder="Command" name="command"
+                class="input input-bordered w-full max-w-xs";
+            input type="text" placeholder="Arguments" name="args"
                 class="input input-bordered w-full max-w-xs";
+            input type="text" placeholder="Queue TTL, e.g. 6h" name="queue_ttl"
+                title="Keep the command for offline clients this long, blank sends only to connected ones"
+                class="input input-bordered w-36";
+            button .btn.btn-primary type="submit" { "Send" }
         }
         #broadcast-response {}
         @for client in received_clients {
-            (MiniClient::from_client(client))
+            @let client_tags = tags.remove(client.id()).unwrap_or_default();
+            (MiniClient::from_client(client, client_tags))
         }
     })
 }
 
 #[derive(Deserialize)]
 struct BroadcastForm {
-    query: String,
+    selector: String,
+    command: String,
+    #[serde(default)]
+    args: String,
+    #[serde(default)]
+    queue_ttl: String,
+}
+
+/// A blank TTL sends only to connected clients.
+fn form_queue_ttl(ttl: &str) -> Result<Option<Duration>, AppError> {
+    let ttl = ttl.trim();
+    if ttl.is_empty() { Ok(None) } else { parse_queue_ttl(ttl).map(Some) }
+}
+
+/// Send a command to every client matched by the selector.
+async fn clients_broadcast(
+    Extension(state): Extension<Arc<AppState>>,
+    Form(data): Form<BroadcastForm>,
+) -> Result<Markup, AppError> {
+    let selector = Selector::parse(&data.selector).map_err(AppError::BadRequest)?;
+    let command = ServerWSCommand::operator_command(&data.command)?;
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    let targets = selector.resolve(&mut conn).map_err(AppError::internal)?;
+    let queue_ttl = form_queue_ttl(&data.queue_ttl)?;
+    let deliveries = dispatch_operator_command(&state, targets, &command, data.args.trim(), queue_ttl)
+        .await
+        .map_err(AppError::internal)?;
+
+    Ok(html! {
+        p { "Sent " (command.as_str()) " to " code { (selector) } ", " (deliveries.len()) " client(s) matched." }
+        (delivery_table(&deliveries))
+    })
+}
+
+/// Per-client outcome of a dispatched command.
+fn delivery_table(deliveries: &[Delivery]) -> Markup {
+    let sent = deliveries.iter().filter(|delivery| matches!(delivery.status, DeliveryStatus::Sent)).count();
+
+    html! {
+        p .text-sm { (sent) " delivered, " (deliveries.len() - sent) " not delivered" }
+        table .table.table-sm {
+            thead { tr { th { "Client" } th { "Status" } th { "Queued" } th { "Error" } } }
+            tbody {
+                @for delivery in deliveries {
+                    tr {
+                        td {
+                            a .link hx-get=(format!("/clients/{}", delivery.client_id))
+                                hx-target="#body-contents" hx-push-url="true" { (delivery.name) }
+                        }
+                        td {
+                            @match delivery.status {
+                                DeliveryStatus::Sent => span .badge.badge-success { "Sent" },
+                                DeliveryStatus::Offline => span .badge { "Offline" },
+                                _ => span .badge.badge-error { (delivery.status.as_str()) },
+                            }
+                        }
+                        td { @if let Some(entry) = delivery.queued { "#" (entry) } }
+                        td {
+                            @if let DeliveryStatus::Failed(error) = &delivery.status { (error) }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+const RECENT_LOGS: i64 = 50;
+const RECENT_SESSIONS: i64 = 20;
+const RECENT_QUEUED: i64 = 20;
+const RECENT_EXECUTIONS: i64 = 20;
+const RECENT_SHELLS: i64 = 20;
+const TELEMETRY_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+const POWER_METRICS: [(&str, &str); 2] = [("power:stored", "Stored power"), ("power:draw", "Power draw")];
+
+fn get_client(state: &AppState, id: i64) -> Result<Client, AppError> {
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    Client::get_by_id(&mut conn, id)
+        .map_err(AppError::internal)?
+        .ok_or(AppError::NotFound(format!("No client with id {}", id)))
+}
+
+/// /clients/{id}
+async fn client_page(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+) -> Result<Markup, AppError> {
+    let client = get_client(&state, id)?;
+    let server_url = client_config::server_url(state.config.public_url.as_deref(), &headers);
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    let logs = ClientLog::get_recent_for_client(&mut conn, id, RECENT_LOGS).map_err(AppError::internal)?;
+    let sessions = ClientSession::get_recent_for_client(&mut conn, id, RECENT_SESSIONS)
+        .map_err(AppError::internal)?;
+    let now = OffsetDateTime::now_utc();
+    let (_, power) = telemetry::query(&mut conn, Some(id), "power:%", now - TELEMETRY_WINDOW, now)
+        .map_err(AppError::internal)?;
+    let capabilities = Capabilities::get_for_client(&mut conn, id).map_err(AppError::internal)?;
+    let expected_build = releases::target_release(&mut conn, &client)
+        .map_err(AppError::internal)?
+        .map(|release| release.build)
+        .unwrap_or(state.assets.manifest().build.clone());
+    let version = releases::version_label(
+        &mut conn,
+        &state.assets.manifest(),
+        capabilities.as_ref().and_then(|capabilities| capabilities.client_build.as_deref()),
+    )
+        .map_err(AppError::internal)?;
+
+    Ok(html! {
+        h2 .text-xl.font-bold {
+            (client.name) " "
+            span .badge { (client.status) }
+            @if client.revoked { " " span .badge.badge-error { "Revoked" } }
+        }
+        .grid.grid-cols-2.gap-4 {
+            table .table.table-sm {
+                tr { th { "Id" } td { (id) } }
+                tr { th { "Description" } td { (client.description.as_deref().unwrap_or_default()) } }
+                tr { th { "Location" } td { (client.location.as_deref().unwrap_or_default()) } }
+                tr { th { "API key" } td { @if client.api_key.is_some() { "Issued" } @else { "Not enrolled" } } }
+                tr { th { "Created" } td { (client.created_on) } }
+                tr { th { "Last accessed" } td { (client.accessed_on.map(|time| time.to_string()).unwrap_or_default()) } }
+                @if let Some(capabilities) = &capabilities {
+                    tr { th { "Protocol" } td { (capabilities.protocol_version) } }
+                    tr {
+                        th { "Build" }
+                        td {
+                            @match capabilities.client_build.as_deref() {
+                                Some(build) if build == expected_build => {
+                                    span .badge.badge-success { "Current" } " " (version) " "
+                                    code .text-xs { (build.chars().take(12).collect::<String>()) }
+                                }
+                                Some(build) => {
+                                    span .badge.badge-warning { "Outdated" } " " (version) " "
+                                    code .text-xs { (build.chars().take(12).collect::<String>()) }
+                                }
+                                None => "Unknown",
+                            }
+                        }
+                    }
+                    tr {
+                        th { "Components" }
+                        td {
+                            @for component in capabilities.components.iter().flatten() {
+                                span .badge.badge-outline { (component) } " "
+                            }
+                        }
+                    }
+                    tr { th { "Free memory" } td { (capabilities.free_memory) " / " (capabilities.total_memory) " bytes" } }
+                    tr { th { "Reported" } td { (capabilities.reported_on) } }
+                }
+            }
+            div hx-get=(format!("/clients/{}/live", id)) hx-trigger="load, every 10s" {}
+        }
+
+        h3 .text-lg.font-bold { "Screen" }
+        div hx-get=(format!("/clients/{}/screen", id)) hx-trigger="load, every 2s" {}
+
+        (metadata_section(&mut conn, &client, &[]).map_err(AppError::internal)?)
+        (tags_section(&mut conn, id, None).map_err(AppError::internal)?)
+        (channel_section(&mut conn, &client, None).map_err(AppError::internal)?)
+        (config_section(&client, server_url.as_deref()))
+
+        h3 .text-lg.font-bold { "Telemetry (24h)" }
+        .flex.gap-4 {
+            @for (metric, title) in POWER_METRICS {
+                @let values: Vec<f64> = power.iter()
+                    .filter(|point| point.metric == metric)
+                    .map(|point| point.avg_value)
+                    .collect();
+                div { p .text-sm { (title) } (sparkline(&values)) }
+            }
+        }
+
+        h3 .text-lg.font-bold { "Actions" }
+        .flex.gap-2 {
+            form .flex.gap-2 hx-post=(format!("/clients/{}/command", id)) hx-target="#action-response" {
+                input name="command" type="text" placeholder="Command" .input.input-bordered;
+                input name="args" type="text" placeholder="Arguments" .input.input-bordered;
+                input name="queue_ttl" type="text" placeholder="Queue TTL, e.g. 6h" .input.input-bordered.w-36;
+                button .btn.btn-primary type="submit" { "Send" }
+            }
+            @if client.allow_exec {
+                a .btn href=(format!("/clients/{}/shell", id)) { "Shell" }
+            }
+            button .btn hx-post=(format!("/clients/{}/rotate", id)) hx-target="#action-response"
+                hx-confirm="Issue a new API key to this client?" { "Rotate key" }
+            button .btn.btn-error hx-post=(format!("/clients/{}/revoke", id)) hx-target="#action-response"
+                hx-confirm="Revoke this client's access?" { "Revoke" }
+        }
+        #action-response {}
+
+        div hx-get=(format!("/clients/{}/queue", id)) hx-trigger="load, every 10s" {}
+
+        (exec_section(&client))
+
+        h3 .text-lg.font-bold { "Recent logs" }
+        table .table.table-sm {
+            @for log in &logs {
+                tr { td { (log.log_time) } td { (log.log_level) } td { (log.log_message) } }
+            }
+        }
+
+        h3 .text-lg.font-bold { "Sessions" }
+        table .table.table-sm {
+            thead {
+                tr { th { "Connected" } th { "Disconnected" } th { "Reason" } th { "Address" } th { "Messages in/out" } th { "Bytes in/out" } }
+            }
+            tbody {
+                @for session in &sessions {
+                    tr {
+                        td { (session.connected_on) }
+                        td { (session.disconnected_on.map(|time| time.to_string()).unwrap_or_default()) }
+                        td { (session.close_reason.as_deref().unwrap_or_default()) }
+                        td { (session.remote_addr.as_deref().unwrap_or_default()) }
+                        td { (session.messages_in) " / " (session.messages_out) }
+                        td { (session.bytes_in) " / " (session.bytes_out) }
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// /clients/{id}/live
+async fn client_live(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Markup {
+    let active_clients = state.active_clients.lock().await;
+    let Some(active) = active_clients.get(&id) else {
+        return html! { .stat { .stat-title { "Connection" } .stat-value { "Offline" } } };
+    };
+
+    html! {
+        .stats.stats-vertical {
+            .stat {
+                .stat-title { "Connected since" }
+                .stat-value.text-lg { (active.connected_on) }
+                .stat-desc { "Session " (active.session_id) ", " (active.codec.name()) " frames" }
+            }
+            .stat {
+                .stat-title { "Messages in / out" }
+                .stat-value.text-lg {
+                    (active.traffic.messages_in.load(Ordering::Relaxed)) " / "
+                    (active.traffic.messages_out.load(Ordering::Relaxed))
+                }
+            }
+            .stat {
+                .stat-title { "Bytes in / out" }
+                .stat-value.text-lg {
+                    (active.traffic.bytes_in.load(Ordering::Relaxed)) " / "
+                    (active.traffic.bytes_out.load(Ordering::Relaxed))
+                }
+            }
+        }
+    }
+}
+
+/// /clients/{id}/screen
+async fn client_screen(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Markup {
+    let screens = state.screens.lock().await;
+    let Some(screen) = screens.get(&id) else {
+        return html! { p .text-sm { "No screen received from this client yet." } };
+    };
+
+    html! {
+        p .text-sm { (screen.width) "x" (screen.height) ", updated " (screen.updated_on) }
+        (screen.render())
+    }
+}
+
+/// Edit form for the client's metadata along with its change history.
+fn metadata_section(
+    conn: &mut PgConnection,
+    client: &Client,
+    errors: &[String],
+) -> Result<Markup, anyhow::Error> {
+    let history = ClientMetadataChange::get_for_client(conn, *client.id())?;
+
+    Ok(html! {
+        #metadata {
+            h3 .text-lg.font-bold { "Metadata" }
+            form .flex.gap-2 hx-patch=(format!("/clients/{}/metadata", client.id()))
+                hx-target="#metadata" hx-swap="outerHTML" {
+                input name="description" type="text" placeholder="Description"
+                    maxlength=(MAX_DESCRIPTION_LENGTH) .input.input-bordered
+                    value=(client.description.as_deref().unwrap_or_default());
+                input name="location" type="text" placeholder="Location"
+                    maxlength=(MAX_LOCATION_LENGTH) .input.input-bordered
+                    value=(client.location.as_deref().unwrap_or_default());
+                button .btn.btn-primary type="submit" { "Save" }
+            }
+            @for error in errors {
+                p .text-error { (error) }
+            }
+            @if !history.is_empty() {
+                table .table.table-sm {
+                    thead { tr { th { "Changed" } th { "Field" } th { "From" } th { "To" } th { "By" } } }
+                    tbody {
+                        @for change in &history {
+                            tr {
+                                td { (change.changed_on) }
+                                td { (change.field) }
+                                td { (change.old_value.as_deref().unwrap_or_default()) }
+                                td { (change.new_value.as_deref().unwrap_or_default()) }
+                                td { (change.changed_by) }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+async fn edit_metadata(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Form(changes): Form<ClientMetadata>,
+) -> Result<Markup, AppError> {
+    let client = get_client(&state, id)?;
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    let (client, errors) = match changes.validate() {
+        Ok(()) => {
+            let updated = client
+                .update_metadata(&mut conn, &changes, ChangeSource::Ui)
+                .map_err(AppError::internal)?;
+            (updated, Vec::new())
+        }
+        Err(errors) => (client, errors),
+    };
+
+    metadata_section(&mut conn, &client, &errors).map_err(AppError::internal)
+}
+
+/// The client's tags with controls to add and remove them.
+fn tags_section(
+    conn: &mut PgConnection,
+    client: i64,
+    error: Option<&str>,
+) -> Result<Markup, anyhow::Error> {
+    let tags = Tag::get_for_client(conn, client)?;
+
+    Ok(html! {
+        #tags {
+            h3 .text-lg.font-bold { "Tags" }
+            .flex.gap-2.items-center {
+                @for tag in &tags {
+                    span .badge.gap-1 {
+                        (tag)
+                        button hx-delete=(format!("/clients/{}/tags/{}", client, tag))
+                            hx-target="#tags" hx-swap="outerHTML" { "✕" }
+                    }
+                }
+                form .flex.gap-2 hx-post=(format!("/clients/{}/tags", client))
+                    hx-target="#tags" hx-swap="outerHTML" {
+                    input name="tag" type="text" placeholder="Tag" maxlength=(MAX_TAG_LENGTH)
+                        .input.input-bordered.input-sm;
+                    button .btn.btn-sm type="submit" { "Add" }
+                }
+            }
+            @if let Some(error) = error {
+                p .text-error { (error) }
+            }
+        }
+    })
+}
+
+#[derive(Deserialize)]
+struct TagForm {
+    tag: String,
+}
+
+async fn add_tag(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Form(data): Form<TagForm>,
+) -> Result<Markup, AppError> {
+    get_client(&state, id)?;
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    let error = match Tag::normalize(&data.tag) {
+        Ok(tag) => {
+            Tag::add_to_client(&mut conn, id, &tag).map_err(AppError::internal)?;
+            None
+        }
+        Err(error) => Some(error),
+    };
+
+    tags_section(&mut conn, id, error.as_deref()).map_err(AppError::internal)
+}
+
+async fn remove_tag(
+    Extension(state): Extension<Arc<AppState>>,
+    Path((id, tag)): Path<(i64, String)>,
+) -> Result<Markup, AppError> {
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    Tag::remove_from_client(&mut conn, id, &tag).map_err(AppError::internal)?;
+
+    tags_section(&mut conn, id, None).map_err(AppError::internal)
+}
+
+/// The client's release channel and the release it is meant to run.
+fn channel_section(
+    conn: &mut PgConnection,
+    client: &Client,
+    message: Option<&str>,
+) -> Result<Markup, anyhow::Error> {
+    let channel = releases::channel_for(client, &Tag::get_channels_for_client(conn, *client.id())?);
+    let target = releases::target_release(conn, client)?;
+
+    Ok(html! {
+        #channel {
+            h3 .text-lg.font-bold { "Release channel" }
+            .flex.gap-2.items-center {
+                form hx-post=(format!("/clients/{}/channel", client.id())) hx-trigger="change"
+                    hx-target="#channel" hx-swap="outerHTML" {
+                    (channel_select(client.channel.as_deref(), "Follow tags"))
+                }
+                span { "Following " span .badge { (channel.as_str()) } }
+                span {
+                    "Target release: "
+                    (target.map(|release| release.version).unwrap_or("None".to_string()))
+                }
+            }
+            @if let Some(message) = message {
+                p { (message) }
+            }
+        }
+    })
 }
 
-async fn clients_broadcast(Extension(state): Extension<Arc<AppState>>, Form(data): Form<BroadcastForm>) {
-    let mut all_clients= state.active_clients.lock().await;
-    ws_broadcast(&mut all_clients, Message::Text(data.query)).await.expect("Failed broadcast!");
-}
\ No newline at end of file
+#[derive(Deserialize)]
+struct ChannelForm {
+    channel: String,
+}
+
+async fn set_channel(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Form(data): Form<ChannelForm>,
+) -> Result<Markup, AppError> {
+    let channel = parse_channel(&data.channel)?;
+    get_client(&state, id)?
+        .set_channel(&mut *state.pool.get().map_err(AppError::internal)?, channel)
+        .map_err(AppError::internal)?;
+    let message = roll_out(&state).await;
+
+    let client = get_client(&state, id)?;
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    channel_section(&mut conn, &client, Some(&message)).map_err(AppError::internal)
+}
+
+/// The client's log level and modules, with the configuration generated from them.
+fn config_section(client: &Client, server_url: Option<&str>) -> Markup {
+    let config = server_url.map(|server_url| ClientConfig::for_client(server_url, client));
+    let disabled: Vec<&str> = client.disabled_modules.iter().flatten().map(String::as_str).collect();
+
+    html! {
+        #config {
+            h3 .text-lg.font-bold { "Configuration" }
+            form .flex.gap-4.items-center hx-post=(format!("/clients/{}/config", client.id()))
+                hx-target="#config" hx-swap="outerHTML" {
+                label .flex.gap-2.items-center {
+                    "Log level"
+                    select name="log_level" .select.select-bordered.select-sm {
+                        @for level in LogLevel::ALL {
+                            option value=(level.as_str()) selected[client.log_level == level.as_str()] { (level.as_str()) }
+                        }
+                    }
+                }
+                @for module in client_config::MODULES {
+                    label .flex.gap-1.items-center {
+                        input type="checkbox" name=(module) .checkbox.checkbox-sm checked[!disabled.contains(&module)];
+                        (module)
+                    }
+                }
+                button .btn.btn-sm type="submit" { "Save" }
+            }
+            p .text-xs { "Clients pick up changes when they next start or update." }
+            @match config {
+                Some(Ok(config)) => pre class="text-xs bg-base-200 p-2" { (config.to_lua()) },
+                Some(Err(e)) => p .text-error { (e) },
+                None => p .text-error { "Unknown server address, set PUBLIC_URL." },
+            }
+        }
+    }
+}
+
+/// Log level and checked modules, unchecked modules are left out of the form.
+async fn edit_config(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+    Form(data): Form<HashMap<String, String>>,
+) -> Result<Markup, AppError> {
+    let level = data
+        .get("log_level")
+        .and_then(|level| LogLevel::parse(level))
+        .ok_or(AppError::BadRequest("Unknown log level".to_string()))?;
+    let disabled: Vec<&str> = client_config::MODULES
+        .into_iter()
+        .filter(|module| !data.contains_key(*module))
+        .collect();
+    get_client(&state, id)?
+        .set_config(&mut *state.pool.get().map_err(AppError::internal)?, level, &disabled)
+        .map_err(AppError::internal)?;
+
+    let client = get_client(&state, id)?;
+    Ok(config_section(&client, client_config::server_url(state.config.public_url.as_deref(), &headers).as_deref()))
+}
+
+#[derive(Deserialize)]
+struct CommandForm {
+    command: String,
+    #[serde(default)]
+    args: String,
+    #[serde(default)]
+    queue_ttl: String,
+}
+
+async fn client_command(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Form(data): Form<CommandForm>,
+) -> Result<Markup, AppError> {
+    let client = get_client(&state, id)?;
+    let command = ServerWSCommand::operator_command(&data.command)?;
+    let queue_ttl = form_queue_ttl(&data.queue_ttl)?;
+    let deliveries = dispatch_operator_command(&state, vec![client], &command, data.args.trim(), queue_ttl)
+        .await
+        .map_err(AppError::internal)?;
+
+    Ok(html! {
+        @for delivery in &deliveries {
+            @match (&delivery.status, delivery.queued) {
+                (DeliveryStatus::Sent, None) => p { "Sent " (command.as_str()) " to the client." },
+                (DeliveryStatus::Sent, Some(entry)) => p { "Sent " (command.as_str()) " as queue entry #" (entry) ", waiting for the client to acknowledge it." },
+                (status, Some(entry)) => p { (status.as_str()) ", " (command.as_str()) " was queued as #" (entry) " for when the client reconnects." },
+                (status, None) => p .text-error { (command.as_str()) " was not delivered: " (status.as_str()) },
+            }
+        }
+    })
+}
+
+/// /clients/{id}/queue
+async fn client_queue(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<Markup, AppError> {
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    queue_section(&mut conn, id).map_err(AppError::internal)
+}
+
+/// Recent entries of the client's outbound command queue.
+fn queue_section(conn: &mut PgConnection, client: i64) -> Result<Markup, anyhow::Error> {
+    let entries = QueuedCommand::get_recent_for_client(conn, client, RECENT_QUEUED)?;
+
+    Ok(html! {
+        h3 .text-lg.font-bold { "Command queue" }
+        table .table.table-sm {
+            thead {
+                tr { th { "#" } th { "Command" } th { "Status" } th { "Attempts" } th { "Queued" } th { "Expires" } th { "Acknowledged" } }
+            }
+            tbody {
+                @for entry in &entries {
+                    tr {
+                        td { (entry.id) }
+                        td { code { (entry.command) } }
+                        td { (entry.status) }
+                        td { (entry.attempts) }
+                        td { (entry.queued_on) }
+                        td { (entry.expires_on) }
+                        td { (entry.acked_on.map(|time| time.to_string()).unwrap_or_default()) }
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Lua editor for `Exec`, preselecting this client, with its execution history.
+fn exec_section(client: &Client) -> Markup {
+    let id = client.id();
+
+    html! {
+        #exec {
+            h3 .text-lg.font-bold { "Remote execution" }
+            .flex.gap-2.items-center {
+                @if client.allow_exec {
+                    span .badge.badge-warning { "Exec allowed" }
+                    button .btn.btn-sm hx-post=(format!("/clients/{}/exec/toggle", id))
+                        hx-target="#exec" hx-swap="outerHTML" { "Disallow" }
+                } @else {
+                    span .badge { "Exec not allowed" }
+                    button .btn.btn-sm hx-post=(format!("/clients/{}/exec/toggle", id))
+                        hx-target="#exec" hx-swap="outerHTML"
+                        hx-confirm="Allow operators to run arbitrary Lua on this client?" { "Allow" }
+                }
+            }
+            form .flex.flex-col.gap-2 hx-post="/clients/exec" hx-target="#exec-response" {
+                input name="selector" type="text" value=(format!("id:{}", id))
+                    .input.input-bordered.input-sm.w-full.max-w-xs;
+                textarea name="code" rows="8" maxlength=(exec::MAX_CODE_LENGTH) spellcheck="false"
+                    placeholder="return computer.freeMemory()" .textarea.textarea-bordered.font-mono {}
+                button .btn.btn-primary.w-24 type="submit" { "Run" }
+            }
+            #exec-response {}
+            div hx-get=(format!("/clients/{}/exec", id)) hx-trigger="load, every 5s" {}
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ExecForm {
+    selector: String,
+    code: String,
+}
+
+/// Run a Lua snippet on every selected client.
+async fn run_exec(
+    Extension(state): Extension<Arc<AppState>>,
+    Form(data): Form<ExecForm>,
+) -> Result<Markup, AppError> {
+    let selector = Selector::parse(&data.selector).map_err(AppError::BadRequest)?;
+    if data.code.trim().is_empty() {
+        return Err(AppError::BadRequest("Nothing to run.".to_string()));
+    }
+    if data.code.len() > exec::MAX_CODE_LENGTH {
+        return Err(AppError::BadRequest(format!("Code must be at most {} bytes.", exec::MAX_CODE_LENGTH)));
+    }
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    let targets = selector.resolve(&mut conn).map_err(AppError::internal)?;
+    let deliveries = exec::run(&state, &targets, &data.code).await.map_err(AppError::internal)?;
+
+    Ok(html! {
+        p { "Running on " code { (selector) } ", " (deliveries.len()) " client(s) matched." }
+        (delivery_table(&deliveries))
+    })
+}
+
+/// /clients/{id}/exec
+async fn client_executions(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<Markup, AppError> {
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    let runs = exec::history(&mut conn, id, RECENT_EXECUTIONS).map_err(AppError::internal)?;
+
+    Ok(html! {
+        table .table.table-sm {
+            thead {
+                tr { th { "Requested" } th { "Code" } th { "Status" } th { "Output" } th { "Result" } th { "Duration" } }
+            }
+            tbody {
+                @for run in &runs {
+                    tr {
+                        td { (run.requested_on) }
+                        td { pre .text-xs { (run.code) } }
+                        td { (run.status) }
+                        td { pre .text-xs { (run.output.as_deref().unwrap_or_default()) } }
+                        td {
+                            @if let Some(error) = &run.error {
+                                pre .text-xs.text-error { (error) }
+                            } @else {
+                                pre .text-xs { (run.result.as_deref().unwrap_or_default()) }
+                            }
+                        }
+                        td { @if let Some(duration) = run.duration_ms { (duration) " ms" } }
+                    }
+                }
+            }
+        }
+    })
+}
+
+async fn toggle_exec(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(client_id): Path<i64>,
+) -> Result<Markup, AppError> {
+    use crate::database::schema::clients::dsl::*;
+
+    let client = get_client(&state, client_id)?;
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    let client: Client = diesel::update(&client)
+        .set(allow_exec.eq(!client.allow_exec))
+        .returning(Client::as_returning())
+        .get_result(&mut conn)
+        .map_err(AppError::internal)?;
+
+    Ok(exec_section(&client))
+}
+
+/// /clients/{id}/shell, a terminal bridged to a shell on the client along with past sessions.
+async fn shell_page(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<Markup, AppError> {
+    let client = get_client(&state, id)?;
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    let sessions = ShellSession::get_recent_for_client(&mut conn, id, RECENT_SHELLS)
+        .map_err(AppError::internal)?;
+
+    Ok(html! {
+        h2 .text-xl.font-bold { "Shell on " (client.name) }
+        @if client.allow_exec {
+            div data-shell=(format!("/api/clients/{}/shell", id)) .w-full {}
+        } @else {
+            p .text-error { "Remote shells need Exec to be allowed on this client." }
+        }
+
+        h3 .text-lg.font-bold { "Recorded sessions" }
+        table .table.table-sm {
+            thead { tr { th { "Started" } th { "Ended" } th { "Reason" } th {} } }
+            tbody {
+                @for session in &sessions {
+                    tr {
+                        td { (session.started_on) }
+                        td { (session.ended_on.map(|time| time.to_string()).unwrap_or_default()) }
+                        td { (session.close_reason.as_deref().unwrap_or_default()) }
+                        td {
+                            a .link hx-get=(format!("/clients/{}/shell/{}", id, session.id))
+                                hx-target="#body-contents" hx-push-url="true" { "Replay" }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// /clients/{id}/shell/{session}, plays back a recorded session.
+async fn shell_replay(
+    Extension(state): Extension<Arc<AppState>>,
+    Path((id, session_id)): Path<(i64, i64)>,
+) -> Result<Markup, AppError> {
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    let session = ShellSession::get_by_id(&mut conn, session_id)
+        .map_err(AppError::internal)?
+        .filter(|session| session.client_id == id)
+        .ok_or(AppError::NotFound(format!("No shell session {}", session_id)))?;
+    let events = ShellEvent::get_for_session(&mut conn, session.id).map_err(AppError::internal)?;
+    let recording = serde_json::to_string(&events).map_err(AppError::internal)?;
+
+    Ok(html! {
+        h2 .text-xl.font-bold { "Shell session " (session.id) }
+        p .text-sm { "Started " (session.started_on) ", " (session.close_reason.as_deref().unwrap_or("still open")) }
+        div data-shell-recording=(recording) .w-full {}
+    })
+}
+
+/// Issue a new API key and hand it to the connected client with a `Rekey` command.
+async fn rotate_key(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(client_id): Path<i64>,
+) -> Result<Markup, AppError> {
+    use crate::database::schema::clients::dsl::*;
+
+    let client = get_client(&state, client_id)?;
+    let new_key = create_api_key(state.settings().api_key_length);
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    diesel::update(&client)
+        .set(api_key.eq(&new_key))
+        .execute(&mut conn)
+        .map_err(AppError::internal)?;
+    // released before waiting on the client
+    drop(conn);
+
+    if let Err(e) = send_command(&state, client_id, ServerWSCommand::Rekey.to_message(&new_key)).await {
+        // the client never got the new key, keep the old one working
+        diesel::update(&client)
+            .set(api_key.eq(&client.api_key))
+            .execute(&mut *state.pool.get().map_err(AppError::internal)?)
+            .map_err(AppError::internal)?;
+        return Err(AppError::ServiceUnavailable(e.0.to_string()));
+    }
+
+    Ok(html! { p { "A new API key was sent to the client." } })
+}
+
+/// Revoke the client and drop its connection.
+async fn revoke_client(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(client_id): Path<i64>,
+) -> Result<Markup, AppError> {
+    let client = get_client(&state, client_id)?;
+    client.revoke(&mut *state.pool.get().map_err(AppError::internal)?).map_err(AppError::internal)?;
+
+    // taken out first so the lock is not held while the client is sent Close
+    let active = state.active_clients.lock().await.remove(&client_id);
+    if let Some(active) = active {
+        let frame = CloseFrame { code: close_code::POLICY, reason: "Client revoked".into() };
+        active.send(Message::Close(Some(frame))).await.ok();
+    }
+
+    Ok(html! { p { "Client access revoked." } })
+}
@@ -0,0 +1,210 @@
+use std::sync::Arc;
+use axum::extract::Path;
+use axum::routing::{delete, get, post};
+use axum::{Extension, Form, Router};
+use diesel::prelude::*;
+use maud::{html, Markup};
+use serde::Deserialize;
+use crate::AppState;
+use crate::database::models::{Client, CraftJob, ItemSample, ItemThreshold, NewItemThreshold};
+use crate::error::ServerError;
+
+const RECENT_CRAFT_JOBS: i64 = 25;
+
+pub fn router(extension: Extension<Arc<AppState>>) -> Router {
+    Router::new()
+        .route("/", get(items_page))
+        .route("/thresholds", post(create_threshold))
+        .route("/thresholds/:id", delete(delete_threshold))
+        .route("/thresholds/:id/toggle", post(toggle_threshold))
+        .route("/thresholds/:id/reset", post(reset_threshold))
+        .layer(extension)
+}
+
+/// /items
+async fn items_page(Extension(state): Extension<Arc<AppState>>) -> Result<Markup, ServerError> {
+    let mut conn = state.pool.get()?;
+    let all_clients = Client::get_all(&mut conn)?;
+    let inventory = ItemSample::latest(&mut conn)?;
+
+    Ok(html! {
+        h2 .text-xl.font-bold { "Stock thresholds" }
+        form .flex.gap-2 hx-post="/items/thresholds" hx-target="#thresholds" hx-swap="outerHTML" {
+            select name="client_id" .select.select-bordered {
+                @for client in &all_clients {
+                    option value=(client.id()) { (client.name) }
+                }
+            }
+            input name="item_name" type="text" placeholder="minecraft:iron_ingot"
+                .input.input-bordered;
+            input name="min_amount" type="number" min="1" placeholder="Minimum"
+                .input.input-bordered;
+            button .btn.btn-primary type="submit" { "Add" }
+        }
+        (thresholds_table(&mut conn)?)
+
+        h2 .text-xl.font-bold { "Recent craft jobs" }
+        (craft_jobs_table(&mut conn)?)
+
+        h2 .text-xl.font-bold { "Inventory" }
+        table .table {
+            thead { tr { th { "Client" } th { "Item" } th { "Amount" } th { "Craftable" } } }
+            tbody {
+                @for item in &inventory {
+                    tr {
+                        td { (client_name(&all_clients, item.client_id)) }
+                        td { (item.label) br; span .text-xs { (item.item_name) } }
+                        td { (item.amount) }
+                        td { @if item.craftable { "Yes" } @else { "No" } }
+                    }
+                }
+            }
+        }
+    })
+}
+
+fn client_name(all_clients: &[Client], id: i64) -> &str {
+    all_clients
+        .iter()
+        .find(|client| *client.id() == id)
+        .map(|client| client.name.as_str())
+        .unwrap_or("Unknown")
+}
+
+fn thresholds_table(conn: &mut PgConnection) -> Result<Markup, ServerError> {
+    let all_clients = Client::get_all(conn)?;
+    let thresholds = ItemThreshold::get_all(conn)?;
+
+    Ok(html! {
+        table #thresholds .table {
+            thead {
+                tr { th { "Client" } th { "Item" } th { "Minimum" } th { "Failures" } th { "State" } th {} }
+            }
+            tbody {
+                @for threshold in &thresholds {
+                    tr {
+                        td { (client_name(&all_clients, threshold.client_id)) }
+                        td { (threshold.item_name) }
+                        td { (threshold.min_amount) }
+                        td { (threshold.failure_count) }
+                        td {
+                            @if threshold.flagged {
+                                span .badge.badge-error { "Flagged" }
+                            } @else if threshold.enabled {
+                                span .badge.badge-success { "Active" }
+                            } @else {
+                                span .badge { "Paused" }
+                            }
+                        }
+                        td hx-target="#thresholds" hx-swap="outerHTML" {
+                            button .btn.btn-ghost.btn-sm
+                                hx-post=(format!("/items/thresholds/{}/toggle", threshold.id)) {
+                                @if threshold.enabled { "Pause" } @else { "Resume" }
+                            }
+                            @if threshold.flagged {
+                                button .btn.btn-ghost.btn-sm
+                                    hx-post=(format!("/items/thresholds/{}/reset", threshold.id)) { "Reset" }
+                            }
+                            button .btn.btn-ghost.btn-sm
+                                hx-delete=(format!("/items/thresholds/{}", threshold.id)) { "Delete" }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+fn craft_jobs_table(conn: &mut PgConnection) -> Result<Markup, ServerError> {
+    let jobs = CraftJob::get_recent(conn, RECENT_CRAFT_JOBS)?;
+
+    Ok(html! {
+        table .table {
+            thead {
+                tr { th { "Job" } th { "Item" } th { "Amount" } th { "Status" } th { "Message" } th { "Updated" } }
+            }
+            tbody {
+                @for job in &jobs {
+                    tr {
+                        td { (job.id) }
+                        td { (job.item_name) }
+                        td { (job.amount) }
+                        td { (job.status) }
+                        td { (job.message.as_deref().unwrap_or_default()) }
+                        td { (job.updated_on) }
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[derive(Deserialize)]
+struct ThresholdForm {
+    client_id: i64,
+    item_name: String,
+    min_amount: i64,
+}
+
+async fn create_threshold(
+    Extension(state): Extension<Arc<AppState>>,
+    Form(data): Form<ThresholdForm>,
+) -> Result<Markup, ServerError> {
+    use crate::database::schema::item_thresholds::dsl::*;
+
+    let mut conn = state.pool.get()?;
+    let item = data.item_name.trim();
+    if !item.is_empty() && data.min_amount > 0 {
+        // re-adding an existing threshold updates its minimum and clears any flag
+        diesel::insert_into(item_thresholds)
+            .values(NewItemThreshold {
+                client_id: data.client_id,
+                item_name: item.to_string(),
+                min_amount: data.min_amount,
+            })
+            .on_conflict((client_id, item_name))
+            .do_update()
+            .set((min_amount.eq(data.min_amount), failure_count.eq(0), flagged.eq(false)))
+            .execute(&mut conn)?;
+    }
+
+    thresholds_table(&mut conn)
+}
+
+async fn delete_threshold(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(threshold_id): Path<i64>,
+) -> Result<Markup, ServerError> {
+    use crate::database::schema::item_thresholds::dsl::*;
+
+    let mut conn = state.pool.get()?;
+    diesel::delete(item_thresholds.find(threshold_id)).execute(&mut conn)?;
+    thresholds_table(&mut conn)
+}
+
+async fn toggle_threshold(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(threshold_id): Path<i64>,
+) -> Result<Markup, ServerError> {
+    use crate::database::schema::item_thresholds::dsl::*;
+
+    let mut conn = state.pool.get()?;
+    diesel::update(item_thresholds.find(threshold_id))
+        .set(enabled.eq(diesel::dsl::not(enabled)))
+        .execute(&mut conn)?;
+    thresholds_table(&mut conn)
+}
+
+/// Clear a flagged threshold so it starts requesting crafts again.
+async fn reset_threshold(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(threshold_id): Path<i64>,
+) -> Result<Markup, ServerError> {
+    use crate::database::schema::item_thresholds::dsl::*;
+
+    let mut conn = state.pool.get()?;
+    diesel::update(item_thresholds.find(threshold_id))
+        .set((failure_count.eq(0), flagged.eq(false)))
+        .execute(&mut conn)?;
+    thresholds_table(&mut conn)
+}
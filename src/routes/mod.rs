@@ -1,29 +1,36 @@
 use std::sync::Arc;
-use std::time::Duration;
 use axum::{middleware, Extension, Router};
 use axum::body::Body;
-use axum::extract::Request;
+use axum::extract::{Request, State};
 use axum::middleware::Next;
 use axum::response::Response;
 use maud::{html, Markup, PreEscaped};
 use axum::routing::get;
-use http::header::CONTENT_LENGTH;
+use axum::extract::{Path, Query};
+use serde::Deserialize;
+use axum::response::IntoResponse;
+use http::{HeaderMap, StatusCode};
+use http::header::{CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_NONE_MATCH};
 use tower::ServiceBuilder;
 use include_dir::{Dir, include_dir};
-use tower_serve_static::{include_file, ServeDir, ServeFile};
+use tower_serve_static::{include_file, ServeFile};
 
-use tower_http::timeout::TimeoutLayer;
 use tower_http::trace::TraceLayer;
-use crate::AppState;
-use crate::error::page_not_found;
+use crate::{assets, client_config, install, releases, AppState};
+use crate::database::models::Client;
+use crate::error::{page_not_found, AppError};
 use crate::layout::root;
 
 pub mod api;
 pub mod stats;
 pub mod auth;
 pub mod client_routes;
+pub mod items;
+pub mod release_routes;
+pub mod schedule_routes;
+pub mod settings_routes;
 
-static ASSETS_DIR: Dir<'static> = include_dir!("$CARGO_MANIFEST_DIR/static");
+pub(crate) static ASSETS_DIR: Dir<'static> = include_dir!("$CARGO_MANIFEST_DIR/static");
 
 
 pub fn router(state: Arc<AppState>) -> Router {
@@ -31,27 +38,39 @@ pub fn router(state: Arc<AppState>) -> Router {
     Router::new()
         /*  simple routes  */
         .route("/", get(index))
-        .route("/settings", get(settings))
+        .route("/install", get(install_script))
 
         /*  nested routes  */
         .nest("/api", api::router(Extension(state.clone())))
         .nest("/auth", auth::router(Extension(state.clone())))
         .nest("/clients", client_routes::router(Extension(state.clone())))
         .nest("/stats", stats::router(Extension(state.clone())))
+        .nest("/items", items::router(Extension(state.clone())))
+        .nest("/releases", release_routes::router(Extension(state.clone())))
+        .nest("/schedules", schedule_routes::router(Extension(state.clone())))
+        .nest("/settings", settings_routes::router(Extension(state.clone())))
 
         /*  Nested services  */
-        .nest_service("/static", ServeDir::new(&ASSETS_DIR))
+        .route("/static/*path", get(static_file))
         .nest_service("/favicon.ico", ServeFile::new(include_file!("/favicon.ico")))
         .fallback(page_not_found)
         .layer(ServiceBuilder::new()
             .layer(Extension(state.clone()))
             .layer(middleware::from_fn(hx_response_middleware))
             .layer(TraceLayer::new_for_http())
-            .layer(TimeoutLayer::new(Duration::from_secs(10)))
+            .layer(middleware::from_fn_with_state(state.clone(), timeout_middleware))
         )
 }
 
 
+/// Answer requests that take longer than the request timeout setting with `408 Request Timeout`.
+async fn timeout_middleware(State(state): State<Arc<AppState>>, request: Request<Body>, next: Next) -> Response {
+    match tokio::time::timeout(state.settings().request_timeout, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => StatusCode::REQUEST_TIMEOUT.into_response(),
+    }
+}
+
 /// Proper handling of htmx and non-htmx requests
 pub async fn hx_response_middleware(request: Request<Body>, next: Next) -> Response {
     let request_uri = request.uri();
@@ -64,10 +83,11 @@ pub async fn hx_response_middleware(request: Request<Body>, next: Next) -> Respo
         || request_uri.to_string().starts_with("/static");
 
     let is_api = request_uri.to_string().starts_with("/api");
+    let is_script = request_uri.path() == "/install";
     let response = next.run(request).await;
 
     /* Render standard html on non-hx requests & static/api routes */
-    if !( !is_htmx && !is_static && !is_api ) { response } else {
+    if !( !is_htmx && !is_static && !is_api && !is_script ) { response } else {
         let (response_parts, response_body) = response.into_parts();
         let body_bytes = axum::body::to_bytes(response_body, usize::MAX)
             .await
@@ -85,6 +105,70 @@ pub async fn hx_response_middleware(request: Request<Body>, next: Next) -> Respo
     }
 }
 
+/// /static/{path}, from the static directory if one is configured, see `crate::assets`.
+async fn static_file(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(path): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(contents) = state.assets.read(&path) else {
+        return page_not_found().await.into_response();
+    };
+    let etag = assets::etag(&contents);
+    // browsers revalidate every time so edited files show up on the next load
+    let response = Response::builder()
+        .header(ETAG, &etag)
+        .header(CACHE_CONTROL, "no-cache");
+
+    let not_modified = headers
+        .get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| assets::matches_etag(value, &etag));
+    if not_modified {
+        return response.status(StatusCode::NOT_MODIFIED).body(Body::empty()).unwrap();
+    }
+    let mime = mime_guess::from_path(&path).first_or_octet_stream();
+    response
+        .header(CONTENT_TYPE, mime.as_ref())
+        .body(Body::from(contents.into_owned()))
+        .unwrap()
+}
+
+#[derive(Deserialize)]
+struct InstallQuery {
+    code: Option<String>,
+}
+
+/// /install, the bootstrap script for new computers, see `crate::install`. With an authorization
+/// code it enrolls as that client and installs the release meant for it.
+async fn install_script(
+    Extension(state): Extension<Arc<AppState>>,
+    Query(query): Query<InstallQuery>,
+    headers: HeaderMap,
+) -> Result<String, AppError> {
+    let server_url = client_config::server_url(state.config.public_url.as_deref(), &headers)
+        .ok_or(AppError::BadRequest("Unknown server address, set PUBLIC_URL.".to_string()))?;
+    let code = query.code.filter(|code| !code.is_empty());
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+
+    let release = match code.as_deref() {
+        Some(code) => {
+            let client = Client::get_by_auth(&mut conn, code)
+                .ok()
+                .flatten()
+                .filter(|client| !client.revoked)
+                .ok_or(AppError::NotFound("Unknown authorization code".to_string()))?;
+            releases::target_release(&mut conn, &client).map_err(AppError::internal)?
+        }
+        None => None,
+    };
+    let manifest = match release {
+        Some(release) => releases::manifest(&mut conn, &release).map_err(AppError::internal)?,
+        None => state.assets.manifest().as_ref().clone(),
+    };
+    install::script(&server_url, code.as_deref(), &manifest).map_err(AppError::internal)
+}
+
 /// /
 async fn index() -> Markup {
     html! {
@@ -111,9 +195,3 @@ async fn index() -> Markup {
     }
 }
 
-/// /settings
-async fn settings() -> Markup {
-    html! {
-        "Hello settings!!"
-    }
-}
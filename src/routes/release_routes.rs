@@ -0,0 +1,432 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use axum::extract::{DefaultBodyLimit, Multipart, Path};
+use axum::routing::{get, patch, post};
+use axum::{Extension, Form, Router};
+use diesel::prelude::*;
+use maud::{html, Markup};
+use serde::Deserialize;
+use crate::{releases, AppState};
+use crate::database::models::{Capabilities, Channel, Client, ClientUpdate, NewRelease, Release, Tag, UpdateStatus};
+use crate::error::AppError;
+use crate::assets::Assets;
+use crate::manifest::Manifest;
+use tracing::error;
+
+const RECENT_UPDATES: i64 = 25;
+/// Uploaded bundles are held in memory and stored in the database, keep them reasonably small.
+const MAX_UPLOAD_SIZE: usize = 16 * 1024 * 1024;
+
+pub fn router(extension: Extension<Arc<AppState>>) -> Router {
+    Router::new()
+        .route("/", get(releases_page).post(create_release))
+        .route("/:id/rollout", patch(set_rollout))
+        .route("/:id/resume", post(resume_release))
+        .route("/tags", post(set_tag_channel))
+        .layer(DefaultBodyLimit::max(MAX_UPLOAD_SIZE))
+        .layer(extension)
+}
+
+/// /releases
+async fn releases_page(Extension(state): Extension<Arc<AppState>>) -> Result<Markup, AppError> {
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    let all_clients = Client::get_all(&mut conn).map_err(AppError::internal)?;
+    let updates = ClientUpdate::get_recent(&mut conn, RECENT_UPDATES).map_err(AppError::internal)?;
+    let all_releases = Release::get_all(&mut conn).map_err(AppError::internal)?;
+
+    Ok(html! {
+        h2 .text-xl.font-bold { "New release" }
+        form .flex.flex-col.gap-2.max-w-xl hx-post="/releases" hx-encoding="multipart/form-data"
+            hx-target="#releases" hx-swap="outerHTML" {
+            .flex.gap-2 {
+                input name="version" type="text" placeholder="Version" required .input.input-bordered;
+                select name="channel" .select.select-bordered {
+                    @for channel in Channel::ALL {
+                        option value=(channel.as_str()) { (channel.as_str()) }
+                    }
+                }
+                label .flex.gap-1.items-center {
+                    "Rollout %"
+                    input name="rollout_percent" type="number" min="0" max="100" value="100"
+                        .input.input-bordered.w-24;
+                }
+                label .flex.gap-1.items-center {
+                    "Max failures %"
+                    input name="max_failure_percent" type="number" min="0" max="100"
+                        value=(releases::DEFAULT_MAX_FAILURE_PERCENT) .input.input-bordered.w-24;
+                }
+            }
+            textarea name="notes" placeholder="Release notes" .textarea.textarea-bordered {}
+            .flex.gap-2.items-center {
+                select name="source" .select.select-bordered {
+                    option value="bundle" { "Bundle served by the server" }
+                    option value="upload" { "Uploaded directory" }
+                }
+                input name="files" type="file" multiple webkitdirectory .file-input.file-input-bordered;
+            }
+            button .btn.btn-primary type="submit" { "Release" }
+        }
+
+        (releases_section(&mut conn, None).map_err(AppError::internal)?)
+        (tag_channels_section(&mut conn).map_err(AppError::internal)?)
+
+        h2 .text-xl.font-bold { "Recent updates" }
+        table .table.table-sm {
+            thead { tr { th { "Client" } th { "Release" } th { "Status" } th { "Sent" } th { "Installed" } } }
+            tbody {
+                @for update in &updates {
+                    tr {
+                        td { (client_name(&all_clients, update.client_id)) }
+                        td {
+                            (all_releases.iter()
+                                .find(|release| release.id == update.release_id)
+                                .map(|release| release.version.as_str())
+                                .unwrap_or("Unknown"))
+                        }
+                        td {
+                            (update.status)
+                            @if let Some(failure) = &update.failure {
+                                br; span .text-xs { (failure) }
+                            }
+                        }
+                        td { (update.sent_on) }
+                        td { (update.completed_on.map(|time| time.to_string()).unwrap_or_default()) }
+                    }
+                }
+            }
+        }
+    })
+}
+
+fn client_name(all_clients: &[Client], id: i64) -> &str {
+    all_clients
+        .iter()
+        .find(|client| *client.id() == id)
+        .map(|client| client.name.as_str())
+        .unwrap_or("Unknown")
+}
+
+/// Every release with the number of clients running it and a control for its rollout.
+fn releases_section(conn: &mut PgConnection, message: Option<&str>) -> Result<Markup, anyhow::Error> {
+    let all_releases = Release::get_all(conn)?;
+    let ids: Vec<i64> = Client::get_all(conn)?.iter().map(|client| *client.id()).collect();
+    let mut running: HashMap<String, usize> = HashMap::new();
+    for capabilities in Capabilities::get_for_clients(conn, &ids)?.into_values() {
+        if let Some(build) = capabilities.client_build {
+            *running.entry(build).or_default() += 1;
+        }
+    }
+    // (failed, total) updates per release
+    let mut outcomes: HashMap<i64, (i64, i64)> = HashMap::new();
+    for (release, status, count) in ClientUpdate::count_by_release(conn)? {
+        let outcome = outcomes.entry(release).or_default();
+        if status == UpdateStatus::Failed.as_str() {
+            outcome.0 += count;
+        }
+        outcome.1 += count;
+    }
+
+    Ok(html! {
+        #releases {
+            h2 .text-xl.font-bold { "Releases" }
+            @if let Some(message) = message {
+                p { (message) }
+            }
+            table .table.table-sm {
+                thead {
+                    tr { th { "Version" } th { "Channel" } th { "Build" } th { "Running" } th { "Rollout" } th { "Failed updates" } th { "Created" } }
+                }
+                tbody {
+                    @for release in &all_releases {
+                        tr {
+                            td {
+                                (release.version)
+                                @if let Some(notes) = &release.notes {
+                                    br; span .text-xs { (notes) }
+                                }
+                            }
+                            td {
+                                span .badge { (release.channel) }
+                                @if release.halted_on.is_some() {
+                                    " " span .badge.badge-error { "Halted" }
+                                    br; span .text-xs { (release.halt_reason.as_deref().unwrap_or_default()) }
+                                    button .btn.btn-xs hx-post=(format!("/releases/{}/resume", release.id))
+                                        hx-target="#releases" hx-swap="outerHTML"
+                                        hx-confirm="Offer this release to clients again?" { "Resume" }
+                                }
+                            }
+                            td { code .text-xs { (release.build.chars().take(12).collect::<String>()) } }
+                            td { (running.get(&release.build).copied().unwrap_or_default()) }
+                            td {
+                                form .flex.gap-1 hx-patch=(format!("/releases/{}/rollout", release.id))
+                                    hx-target="#releases" hx-swap="outerHTML" {
+                                    input name="rollout_percent" type="number" min="0" max="100"
+                                        value=(release.rollout_percent) .input.input-bordered.input-sm.w-20;
+                                    button .btn.btn-sm type="submit" { "%" }
+                                }
+                            }
+                            td {
+                                @let (failed, total) = outcomes.get(&release.id).copied().unwrap_or_default();
+                                (failed) " / " (total)
+                                span .text-xs { " (max " (release.max_failure_percent) "%)" }
+                            }
+                            td { (release.created_on) }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Channel of every tag, for sending groups of clients to beta or dev.
+fn tag_channels_section(conn: &mut PgConnection) -> Result<Markup, anyhow::Error> {
+    let tags = Tag::get_all(conn)?;
+
+    Ok(html! {
+        #tag-channels {
+            h2 .text-xl.font-bold { "Tag channels" }
+            table .table.table-sm {
+                thead { tr { th { "Tag" } th { "Channel" } } }
+                tbody {
+                    @for tag in &tags {
+                        tr {
+                            td { (tag.name) }
+                            td {
+                                form hx-post="/releases/tags" hx-trigger="change" hx-target="#tag-channels"
+                                    hx-swap="outerHTML" {
+                                    input type="hidden" name="tag" value=(tag.name);
+                                    (channel_select(tag.channel.as_deref(), "None"))
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Select for an optional channel, the empty value standing for none.
+pub fn channel_select(selected: Option<&str>, none_label: &str) -> Markup {
+    html! {
+        select name="channel" .select.select-bordered.select-sm {
+            option value="" selected[selected.is_none()] { (none_label) }
+            @for channel in Channel::ALL {
+                option value=(channel.as_str()) selected[selected == Some(channel.as_str())] { (channel.as_str()) }
+            }
+        }
+    }
+}
+
+/// Send the new targets out, reporting how many clients were updated.
+pub async fn roll_out(state: &AppState) -> String {
+    match releases::roll_out(state).await {
+        Ok(updated) => format!("Sent Update to {} connected clients.", updated),
+        Err(e) => {
+            error!("Failed to roll out releases: {}", e);
+            "Failed to send updates, see the server log.".to_string()
+        }
+    }
+}
+
+/// Install path of an uploaded file. Browsers name the files of an uploaded directory after their
+/// path inside it, starting with the directory itself.
+fn install_path(file_name: &str) -> Result<String, String> {
+    let path = file_name.replace('\\', "/");
+    let path = path.split_once('/').map(|(_, rest)| rest).unwrap_or(&path);
+    if path.is_empty() || path.split('/').any(|part| part.is_empty() || part == "." || part == "..") {
+        return Err(format!("Invalid file path {}", file_name));
+    }
+    Ok(format!("/{}", path))
+}
+
+/// Files of a release as `(install path, contents)`.
+type ReleaseFiles = Vec<(String, Vec<u8>)>;
+
+/// Release being created, as read from its multipart form.
+#[derive(Default)]
+struct ReleaseForm {
+    version: String,
+    channel: String,
+    notes: String,
+    rollout_percent: String,
+    max_failure_percent: String,
+    source: String,
+    files: ReleaseFiles,
+}
+
+impl ReleaseForm {
+    async fn read(mut multipart: Multipart) -> Result<ReleaseForm, AppError> {
+        let mut form = ReleaseForm::default();
+        while let Some(field) = multipart.next_field().await.map_err(|e| AppError::BadRequest(e.to_string()))? {
+            let name = field.name().unwrap_or_default().to_string();
+            if name == "files" {
+                let file_name = field.file_name().unwrap_or_default().to_string();
+                let contents = field.bytes().await.map_err(|e| AppError::BadRequest(e.to_string()))?;
+                // an empty file input still submits one nameless part
+                if !file_name.is_empty() {
+                    form.files.push((file_name, contents.to_vec()));
+                }
+                continue;
+            }
+            let value = field.text().await.map_err(|e| AppError::BadRequest(e.to_string()))?;
+            match name.as_str() {
+                "version" => form.version = value,
+                "channel" => form.channel = value,
+                "notes" => form.notes = value,
+                "rollout_percent" => form.rollout_percent = value,
+                "max_failure_percent" => form.max_failure_percent = value,
+                "source" => form.source = value,
+                _ => {}
+            }
+        }
+        Ok(form)
+    }
+
+    fn validate(self, assets: &Assets) -> Result<(NewRelease, ReleaseFiles), String> {
+        let version = self.version.trim().to_string();
+        if version.is_empty() {
+            return Err("Version must not be empty.".to_string());
+        }
+        let channel = Channel::parse(&self.channel).ok_or(format!("Unknown channel {}", self.channel))?;
+        let rollout_percent = parse_percent("Rollout", &self.rollout_percent)?;
+        let max_failure_percent = parse_percent("Max failures", &self.max_failure_percent)?;
+
+        let files: ReleaseFiles = match self.source.as_str() {
+            "upload" => self
+                .files
+                .into_iter()
+                .map(|(file_name, contents)| Ok((install_path(&file_name)?, contents)))
+                .collect::<Result<_, String>>()?,
+            _ => assets
+                .bundle_files()
+                .into_iter()
+                .map(|(path, contents)| (path, contents.to_vec()))
+                .collect(),
+        };
+        if files.is_empty() {
+            return Err("A release needs at least one file.".to_string());
+        }
+
+        let manifest = Manifest::from_files(
+            &version,
+            "",
+            files.iter().map(|(path, contents)| (path.clone(), contents.as_slice())),
+        );
+        let notes = Some(self.notes.trim().to_string()).filter(|notes| !notes.is_empty());
+        let release = NewRelease {
+            version,
+            channel: channel.as_str().to_string(),
+            build: manifest.build,
+            notes,
+            rollout_percent,
+            max_failure_percent,
+        };
+        Ok((release, files))
+    }
+}
+
+fn parse_percent(field: &str, value: &str) -> Result<i32, String> {
+    value
+        .trim()
+        .parse()
+        .ok()
+        .filter(|percent| (0..=100).contains(percent))
+        .ok_or(format!("{} must be a percentage from 0 to 100.", field))
+}
+
+async fn create_release(
+    Extension(state): Extension<Arc<AppState>>,
+    multipart: Multipart,
+) -> Result<Markup, AppError> {
+    let form = ReleaseForm::read(multipart).await?;
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    let message = match form.validate(&state.assets) {
+        Ok((release, files)) => match Release::create(&mut conn, &release, &files).map_err(AppError::internal)? {
+            Some(_) => {
+                drop(conn);
+                let message = format!("Released {}. {}", release.version, roll_out(&state).await);
+                conn = state.pool.get().map_err(AppError::internal)?;
+                message
+            }
+            None => format!("Version {} was already released.", release.version),
+        },
+        Err(error) => error,
+    };
+
+    releases_section(&mut conn, Some(&message)).map_err(AppError::internal)
+}
+
+#[derive(Deserialize)]
+struct RolloutForm {
+    rollout_percent: String,
+}
+
+async fn set_rollout(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(id): Path<i64>,
+    Form(data): Form<RolloutForm>,
+) -> Result<Markup, AppError> {
+    let percent = parse_percent("Rollout", &data.rollout_percent).map_err(AppError::BadRequest)?;
+    let updated = Release::set_rollout(&mut *state.pool.get().map_err(AppError::internal)?, id, percent)
+        .map_err(AppError::internal)?;
+    if !updated {
+        return Err(AppError::NotFound(format!("No release with id {}", id)));
+    }
+    let message = roll_out(&state).await;
+
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    releases_section(&mut conn, Some(&message)).map_err(AppError::internal)
+}
+
+async fn resume_release(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<Markup, AppError> {
+    let resumed = Release::resume(&mut *state.pool.get().map_err(AppError::internal)?, id)
+        .map_err(AppError::internal)?;
+    if !resumed {
+        return Err(AppError::NotFound(format!("No release with id {}", id)));
+    }
+    let message = roll_out(&state).await;
+
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    releases_section(&mut conn, Some(&message)).map_err(AppError::internal)
+}
+
+#[derive(Deserialize)]
+struct TagChannelForm {
+    tag: String,
+    channel: String,
+}
+
+async fn set_tag_channel(
+    Extension(state): Extension<Arc<AppState>>,
+    Form(data): Form<TagChannelForm>,
+) -> Result<Markup, AppError> {
+    let channel = parse_channel(&data.channel)?;
+    let updated = Tag::set_channel(
+        &mut *state.pool.get().map_err(AppError::internal)?,
+        &data.tag,
+        channel.map(|channel| channel.as_str()),
+    )
+        .map_err(AppError::internal)?;
+    if !updated {
+        return Err(AppError::NotFound(format!("No tag named {}", data.tag)));
+    }
+    roll_out(&state).await;
+
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    tag_channels_section(&mut conn).map_err(AppError::internal)
+}
+
+/// Channel chosen in a `channel_select`.
+pub fn parse_channel(value: &str) -> Result<Option<Channel>, AppError> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    Channel::parse(value)
+        .map(Some)
+        .ok_or(AppError::BadRequest(format!("Unknown channel {}", value)))
+}
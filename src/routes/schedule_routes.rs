@@ -0,0 +1,267 @@
+use std::sync::Arc;
+use axum::extract::Path;
+use axum::routing::{delete, get, post};
+use axum::{Extension, Form, Router};
+use diesel::PgConnection;
+use maud::{html, Markup};
+use serde::Deserialize;
+use time::OffsetDateTime;
+use crate::{parse_duration, scheduler, AppState};
+use crate::database::models::{NewSchedule, RunStatus, Schedule, ScheduleRun};
+use crate::error::AppError;
+use crate::routes::api::ServerWSCommand;
+use crate::selector::Selector;
+
+const RECENT_RUNS: i64 = 50;
+
+pub fn router(extension: Extension<Arc<AppState>>) -> Router {
+    Router::new()
+        .route("/", get(schedules_page).post(create_schedule))
+        .route("/:id", delete(delete_schedule))
+        .route("/:id/pause", post(pause_schedule))
+        .route("/:id/resume", post(resume_schedule))
+        .route("/:id/run", post(run_schedule))
+        .layer(extension)
+}
+
+/// /schedules
+async fn schedules_page(Extension(state): Extension<Arc<AppState>>) -> Result<Markup, AppError> {
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    let commands = ServerWSCommand::ALL
+        .iter()
+        .filter(|command| ServerWSCommand::operator_command(command.as_str()).is_ok());
+
+    Ok(html! {
+        h2 .text-xl.font-bold { "New schedule" }
+        form .flex.flex-col.gap-2.max-w-xl hx-post="/schedules" hx-target="#schedules" hx-swap="outerHTML" {
+            input name="name" type="text" placeholder="Name" required .input.input-bordered;
+            .flex.gap-2 {
+                input name="cron" type="text" placeholder="Cron, e.g. 0 3 * * *" required
+                    title="minute hour day month weekday in UTC, or @hourly, @daily, @weekly"
+                    .input.input-bordered;
+                input name="selector" type="text" placeholder="Selector, e.g. tag:factory" value="all" required
+                    .input.input-bordered;
+            }
+            .flex.gap-2 {
+                select name="command" .select.select-bordered {
+                    @for command in commands {
+                        option value=(command.as_str()) { (command.as_str()) }
+                    }
+                }
+                input name="args" type="text" placeholder="Arguments" .input.input-bordered;
+                input name="queue_ttl" type="text" placeholder="Queue TTL, e.g. 6h"
+                    title="Keep the command for offline clients this long, blank sends only to connected ones"
+                    .input.input-bordered.w-36;
+            }
+            button .btn.btn-primary type="submit" { "Schedule" }
+        }
+
+        (schedules_section(&mut conn, None).map_err(AppError::internal)?)
+    })
+}
+
+/// Every schedule with its controls, followed by the recent runs of all of them.
+fn schedules_section(conn: &mut PgConnection, message: Option<&str>) -> Result<Markup, anyhow::Error> {
+    let schedules = Schedule::get_all(conn)?;
+    let runs = ScheduleRun::get_recent(conn, RECENT_RUNS)?;
+    let schedule_name = |id: i64| {
+        schedules
+            .iter()
+            .find(|schedule| schedule.id == id)
+            .map(|schedule| schedule.name.as_str())
+            .unwrap_or("Unknown")
+    };
+
+    Ok(html! {
+        #schedules {
+            h2 .text-xl.font-bold { "Schedules" }
+            @if let Some(message) = message {
+                p { (message) }
+            }
+            table .table.table-sm {
+                thead {
+                    tr { th { "Name" } th { "Cron (UTC)" } th { "Selector" } th { "Command" } th { "Queue TTL" } th { "Next run" } th {} }
+                }
+                tbody {
+                    @for schedule in &schedules {
+                        tr {
+                            td {
+                                (schedule.name)
+                                @if schedule.paused {
+                                    " " span .badge { "Paused" }
+                                }
+                            }
+                            td { code { (schedule.cron) } }
+                            td { code { (schedule.selector) } }
+                            td { (schedule.command) " " (schedule.args) }
+                            td { (schedule.queue_ttl.as_deref().unwrap_or_default()) }
+                            td { @if !schedule.paused { (schedule.next_run_on) } }
+                            td .flex.gap-1 {
+                                @if schedule.paused {
+                                    button .btn.btn-xs hx-post=(format!("/schedules/{}/resume", schedule.id))
+                                        hx-target="#schedules" hx-swap="outerHTML" { "Resume" }
+                                } @else {
+                                    button .btn.btn-xs hx-post=(format!("/schedules/{}/pause", schedule.id))
+                                        hx-target="#schedules" hx-swap="outerHTML" { "Pause" }
+                                }
+                                button .btn.btn-xs hx-post=(format!("/schedules/{}/run", schedule.id))
+                                    hx-target="#schedules" hx-swap="outerHTML" { "Run now" }
+                                button .btn.btn-xs.btn-error hx-delete=(format!("/schedules/{}", schedule.id))
+                                    hx-target="#schedules" hx-swap="outerHTML"
+                                    hx-confirm=(format!("Delete the schedule {} and its history?", schedule.name)) { "Delete" }
+                            }
+                        }
+                    }
+                }
+            }
+
+            h2 .text-xl.font-bold { "Recent runs" }
+            table .table.table-sm {
+                thead {
+                    tr { th { "Schedule" } th { "Status" } th { "Matched" } th { "Sent" } th { "Queued" } th { "Failed" } th { "Started" } }
+                }
+                tbody {
+                    @for run in &runs {
+                        tr {
+                            td { (schedule_name(run.schedule_id)) }
+                            td {
+                                @if run.status == RunStatus::Sent.as_str() {
+                                    span .badge.badge-success { (run.status) }
+                                } @else if run.status == RunStatus::NoTargets.as_str() {
+                                    span .badge { (run.status) }
+                                } @else {
+                                    span .badge.badge-error { (run.status) }
+                                }
+                                @if let Some(error) = &run.error {
+                                    br; span .text-xs { (error) }
+                                }
+                            }
+                            td { (run.matched) }
+                            td { (run.sent) }
+                            td { (run.queued) }
+                            td { (run.failed) }
+                            td { (run.started_on) }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[derive(Deserialize)]
+struct ScheduleForm {
+    name: String,
+    cron: String,
+    selector: String,
+    command: String,
+    #[serde(default)]
+    args: String,
+    #[serde(default)]
+    queue_ttl: String,
+}
+
+impl ScheduleForm {
+    fn validate(self, now: OffsetDateTime) -> Result<NewSchedule, String> {
+        let name = self.name.trim().to_string();
+        if name.is_empty() {
+            return Err("Name must not be empty.".to_string());
+        }
+        let next_run_on = scheduler::next_run(&self.cron, now)?;
+        Selector::parse(&self.selector)?;
+        let command = ServerWSCommand::operator_command(&self.command)
+            .map_err(|_| format!("Unknown command \"{}\".", self.command))?;
+        let queue_ttl = Some(self.queue_ttl.trim().to_string()).filter(|ttl| !ttl.is_empty());
+        if let Some(ttl) = &queue_ttl {
+            parse_duration(ttl).ok_or(format!("Invalid queue TTL \"{}\", e.g. 6h, at most 365d.", ttl))?;
+        }
+
+        Ok(NewSchedule {
+            name,
+            cron: self.cron.split_whitespace().collect::<Vec<_>>().join(" "),
+            selector: self.selector.trim().to_string(),
+            command: command.as_str().to_string(),
+            args: self.args.trim().to_string(),
+            queue_ttl,
+            next_run_on,
+        })
+    }
+}
+
+async fn create_schedule(
+    Extension(state): Extension<Arc<AppState>>,
+    Form(data): Form<ScheduleForm>,
+) -> Result<Markup, AppError> {
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    let message = match data.validate(OffsetDateTime::now_utc()) {
+        Ok(schedule) => {
+            Schedule::create(&mut conn, &schedule).map_err(AppError::internal)?;
+            format!("Scheduled {}, next run at {}.", schedule.name, schedule.next_run_on)
+        }
+        Err(error) => error,
+    };
+
+    schedules_section(&mut conn, Some(&message)).map_err(AppError::internal)
+}
+
+fn get_schedule(conn: &mut PgConnection, id: i64) -> Result<Schedule, AppError> {
+    Schedule::get_by_id(conn, id)
+        .map_err(AppError::internal)?
+        .ok_or(AppError::NotFound(format!("No schedule with id {}", id)))
+}
+
+async fn pause_schedule(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<Markup, AppError> {
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    let schedule = get_schedule(&mut conn, id)?;
+    Schedule::set_paused(&mut conn, id, true, schedule.next_run_on).map_err(AppError::internal)?;
+
+    schedules_section(&mut conn, Some(&format!("Paused {}.", schedule.name))).map_err(AppError::internal)
+}
+
+/// Resume a schedule from now on, the runs it missed while paused are skipped.
+async fn resume_schedule(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<Markup, AppError> {
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    let schedule = get_schedule(&mut conn, id)?;
+    let message = match scheduler::next_run(&schedule.cron, OffsetDateTime::now_utc()) {
+        Ok(next_run) => {
+            Schedule::set_paused(&mut conn, id, false, next_run).map_err(AppError::internal)?;
+            format!("Resumed {}, next run at {}.", schedule.name, next_run)
+        }
+        Err(error) => error,
+    };
+
+    schedules_section(&mut conn, Some(&message)).map_err(AppError::internal)
+}
+
+/// Fire a schedule right away, its regular runs are not affected.
+async fn run_schedule(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<Markup, AppError> {
+    let schedule = get_schedule(&mut *state.pool.get().map_err(AppError::internal)?, id)?;
+    let run = scheduler::run(&state, &schedule).await.map_err(AppError::internal)?;
+    let message = format!(
+        "Ran {}: {} of {} matched clients sent, {} queued.",
+        schedule.name, run.sent, run.matched, run.queued,
+    );
+
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    schedules_section(&mut conn, Some(&message)).map_err(AppError::internal)
+}
+
+async fn delete_schedule(
+    Extension(state): Extension<Arc<AppState>>,
+    Path(id): Path<i64>,
+) -> Result<Markup, AppError> {
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    let schedule = get_schedule(&mut conn, id)?;
+    Schedule::delete(&mut conn, id).map_err(AppError::internal)?;
+
+    schedules_section(&mut conn, Some(&format!("Deleted {}.", schedule.name))).map_err(AppError::internal)
+}
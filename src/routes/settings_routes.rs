@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use axum::routing::get;
+use axum::{Extension, Form, Router};
+use maud::{html, Markup};
+use crate::AppState;
+use crate::error::AppError;
+use crate::settings::{Settings, API_KEY_LENGTH, AUTH_CODE_LENGTH, BIND_ADDRESS, REQUEST_TIMEOUT};
+
+pub fn router(extension: Extension<Arc<AppState>>) -> Router {
+    Router::new()
+        .route("/", get(settings_page).post(save_settings))
+        .layer(extension)
+}
+
+/// /settings
+async fn settings_page(Extension(state): Extension<Arc<AppState>>) -> Markup {
+    settings_section(&state, &stored_values(&state.settings()), &[])
+}
+
+fn stored_values(settings: &Settings) -> HashMap<String, String> {
+    settings.to_values().into_iter().map(|(key, value)| (key.to_string(), value)).collect()
+}
+
+/// The settings form filled with `values`, showing `messages` above it. Settings the server
+/// configuration overrides cannot be edited.
+fn settings_section(state: &AppState, values: &HashMap<String, String>, messages: &[String]) -> Markup {
+    let overridden = Settings::overridden_keys(&state.config);
+    let fields = [
+        (BIND_ADDRESS, "Bind address", "Address the server listens on, applies after a restart."),
+        (API_KEY_LENGTH, "API key length", "Length of newly issued API keys, existing keys keep working."),
+        (AUTH_CODE_LENGTH, "Authorization code length", "Length of the codes new computers enroll with."),
+        (REQUEST_TIMEOUT, "Request timeout", "How long a request may take, e.g. 10s."),
+    ];
+
+    html! {
+        #settings {
+            h2 .text-xl.font-bold { "Settings" }
+            @for message in messages {
+                p { (message) }
+            }
+            form .flex.flex-col.gap-2.max-w-xl hx-post="/settings" hx-target="#settings" hx-swap="outerHTML" {
+                @for (key, label, help) in fields {
+                    label .form-control {
+                        span .label-text { (label) }
+                        input name=(key) type="text" required disabled[overridden.contains(&key)]
+                            .input.input-bordered value=(values.get(key).map(String::as_str).unwrap_or_default());
+                        span .label-text-alt {
+                            (help)
+                            @if overridden.contains(&key) { " Set by the server configuration." }
+                        }
+                    }
+                }
+                button .btn.btn-primary type="submit" { "Save" }
+            }
+        }
+    }
+}
+
+/// Validate and store the settings, they apply right away except for the bind address.
+async fn save_settings(
+    Extension(state): Extension<Arc<AppState>>,
+    Form(values): Form<HashMap<String, String>>,
+) -> Result<Markup, AppError> {
+    let stored = state.settings.read().unwrap().clone();
+    let settings = match stored.update(&values) {
+        Ok(settings) => settings,
+        // keep what was entered so it can be corrected
+        Err(errors) => {
+            let mut entered = stored_values(&state.settings());
+            entered.extend(values);
+            return Ok(settings_section(&state, &entered, &errors));
+        }
+    };
+    settings.save(&mut *state.pool.get().map_err(AppError::internal)?).map_err(AppError::internal)?;
+    *state.settings.write().unwrap() = settings.clone();
+
+    Ok(settings_section(&state, &stored_values(&state.settings()), &["Settings saved.".to_string()]))
+}
@@ -1,17 +1,341 @@
+use std::collections::BTreeMap;
 use std::sync::Arc;
+use std::time::Duration;
 use axum::{Extension, Router};
+use axum::extract::Query;
 use axum::routing::get;
+use diesel::Identifiable;
 use maud::{html, Markup};
-use crate::AppState;
+use serde::Deserialize;
+use time::{Date, OffsetDateTime};
+use crate::{fleet, items, parse_duration, releases, AppState};
+use crate::database::models::{Capabilities, Client, ClientLog, ClientSession, LogLevel};
+use crate::error::{AppError, ServerError};
+use crate::items::ItemRate;
+use crate::layout::sparkline;
+
+/// Windows offered on the statistics page, any `parse_duration` string works through the API.
+const RATE_WINDOWS: [&str; 4] = ["1h", "6h", "24h", "7d"];
+pub const DEFAULT_RATE_WINDOW: &str = "24h";
+pub const DEFAULT_RATE_LIMIT: usize = 10;
+
+/// How often the fleet cards refresh themselves.
+const CARD_REFRESH: &str = "load, every 30s";
+/// Minutes of history shown in the per-client message charts.
+const MESSAGE_MINUTES: usize = 60;
+const LOG_VOLUME_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+const ENROLLMENT_DAYS: i64 = 30;
+const TOP_TALKERS: usize = 5;
+const UPTIME_WINDOW: Duration = Duration::from_secs(7 * 24 * 60 * 60);
 
 pub fn router(extension: Extension<Arc<AppState>>) -> Router {
     Router::new()
         .route("/", get(stats))
+        .route("/items", get(item_rates))
+        .route("/status", get(status_card))
+        .route("/connections", get(connections_card))
+        .route("/messages", get(messages_card))
+        .route("/logs", get(logs_card))
+        .route("/enrollments", get(enrollments_card))
+        .route("/talkers", get(talkers_card))
+        .route("/uptime", get(uptime_card))
+        .route("/versions", get(versions_card))
         .layer(extension)
 }
 
+/// /stats
 async fn stats() -> Markup {
     html! {
-        "Hello stats world!!"
+        h2 .text-xl.font-bold { "Fleet" }
+        .grid.grid-cols-3.gap-4 {
+            (card("Clients", "/stats/status"))
+            (card("Connected", "/stats/connections"))
+            (card("Top talkers", "/stats/talkers"))
+            (card("Log volume (24h)", "/stats/logs"))
+            (card("Enrollments", "/stats/enrollments"))
+            (card("Messages per minute", "/stats/messages"))
+            (card("Uptime (7d)", "/stats/uptime"))
+            (card("Versions", "/stats/versions"))
+        }
+
+        h2 .text-xl.font-bold { "Item throughput" }
+        select name="window" .select.select-bordered
+            hx-get="/stats/items" hx-target="#item-rates" hx-trigger="change" {
+            @for window in RATE_WINDOWS {
+                option value=(window) selected[window == DEFAULT_RATE_WINDOW] { (window) }
+            }
+        }
+        #item-rates hx-get="/stats/items" hx-trigger="load" {}
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RateQuery {
+    pub window: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl RateQuery {
+    pub fn window(&self) -> Result<std::time::Duration, AppError> {
+        let window = self.window.as_deref().unwrap_or(DEFAULT_RATE_WINDOW);
+        parse_duration(window)
+            .ok_or(AppError::BadRequest(format!("Invalid window \"{}\", e.g. 6h, at most 365d", window)))
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_RATE_LIMIT)
     }
 }
+
+/// /stats/items
+async fn item_rates(
+    Extension(state): Extension<Arc<AppState>>,
+    Query(query): Query<RateQuery>,
+) -> Result<Markup, AppError> {
+    let mut conn = state.pool.get().map_err(AppError::internal)?;
+    let rates = items::item_rates(&mut conn, query.window()?).map_err(AppError::internal)?;
+    let running_out = items::running_out(rates.clone(), query.limit());
+    let movers = items::top_movers(rates, query.limit());
+
+    Ok(html! {
+        .flex.gap-4 {
+            .flex-1 {
+                h3 .font-bold { "Top movers" }
+                (rates_table(&movers))
+            }
+            .flex-1 {
+                h3 .font-bold { "Running out" }
+                (rates_table(&running_out))
+            }
+        }
+    })
+}
+
+fn rates_table(rates: &[ItemRate]) -> Markup {
+    html! {
+        table .table {
+            thead { tr { th { "Item" } th { "Amount" } th { "Items/hour" } th { "Runs out in" } } }
+            tbody {
+                @for rate in rates {
+                    tr {
+                        td { (rate.label) }
+                        td { (rate.amount) }
+                        td { (format!("{:+.1}", rate.per_hour)) }
+                        td {
+                            @if let Some(hours) = rate.hours_remaining {
+                                (format!("{:.1}h", hours))
+                            } @else { "-" }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Card whose body is loaded from `url` and refreshed every `CARD_REFRESH`.
+fn card(title: &str, url: &str) -> Markup {
+    html! {
+        .card.bg-base-200 {
+            .card-body {
+                h3 .card-title { (title) }
+                div hx-get=(url) hx-trigger=(CARD_REFRESH) {}
+            }
+        }
+    }
+}
+
+fn client_name(all_clients: &[Client], id: i64) -> String {
+    all_clients
+        .iter()
+        .find(|client| *client.id() == id)
+        .map(|client| client.name.clone())
+        .unwrap_or_else(|| format!("#{}", id))
+}
+
+/// /stats/status
+async fn status_card(Extension(state): Extension<Arc<AppState>>) -> Result<Markup, ServerError> {
+    let mut conn = state.pool.get()?;
+    let counts = Client::count_by_status(&mut conn)?;
+    let connected = state.active_clients.lock().await.len();
+
+    Ok(html! {
+        .stats.stats-vertical {
+            .stat {
+                .stat-title { "Connected now" }
+                .stat-value { (connected) }
+            }
+            @for (status, count) in &counts {
+                .stat {
+                    .stat-title { (status) }
+                    .stat-value { (count) }
+                }
+            }
+        }
+    })
+}
+
+/// /stats/connections
+async fn connections_card(Extension(state): Extension<Arc<AppState>>) -> Markup {
+    let fleet_stats = state.fleet_stats.lock().await;
+    let history: Vec<f64> = fleet_stats
+        .connected_history()
+        .map(|(_, count)| *count as f64)
+        .collect();
+    let peak = history.iter().copied().fold(0.0, f64::max);
+
+    html! {
+        (sparkline(&history))
+        p .text-xs { "Peak over the last 24h: " (peak) }
+    }
+}
+
+/// /stats/messages
+async fn messages_card(Extension(state): Extension<Arc<AppState>>) -> Result<Markup, ServerError> {
+    let mut conn = state.pool.get()?;
+    let all_clients = Client::get_all(&mut conn)?;
+    let connected: Vec<i64> = state.active_clients.lock().await.keys().copied().collect();
+    let fleet_stats = state.fleet_stats.lock().await;
+
+    Ok(html! {
+        table .table.table-sm {
+            @for id in &connected {
+                @let counts = fleet_stats.messages_per_minute(*id, MESSAGE_MINUTES);
+                tr {
+                    td { (client_name(&all_clients, *id)) }
+                    td { (counts.last().copied().unwrap_or_default()) "/min" }
+                    td { (sparkline(&counts.iter().map(|count| *count as f64).collect::<Vec<_>>())) }
+                }
+            }
+        }
+    })
+}
+
+/// /stats/logs
+async fn logs_card(Extension(state): Extension<Arc<AppState>>) -> Result<Markup, ServerError> {
+    let mut conn = state.pool.get()?;
+    let counts = ClientLog::count_by_level(&mut conn, OffsetDateTime::now_utc() - LOG_VOLUME_WINDOW)?;
+
+    Ok(html! {
+        table .table.table-sm {
+            @for level in LogLevel::ALL {
+                tr {
+                    td { (level.as_str()) }
+                    td {
+                        (counts.iter()
+                            .find(|(name, _)| name == level.as_str())
+                            .map(|(_, count)| *count)
+                            .unwrap_or_default())
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// /stats/enrollments
+async fn enrollments_card(Extension(state): Extension<Arc<AppState>>) -> Result<Markup, ServerError> {
+    let mut conn = state.pool.get()?;
+    let cutoff = OffsetDateTime::now_utc().date() - time::Duration::days(ENROLLMENT_DAYS);
+
+    // (authorized, enrolled) per day
+    let mut days: BTreeMap<Date, (u32, u32)> = BTreeMap::new();
+    for client in Client::get_all(&mut conn)? {
+        if client.created_on.date() >= cutoff {
+            days.entry(client.created_on.date()).or_default().0 += 1;
+        }
+        if let Some(enrolled) = client.accessed_on.filter(|_| client.api_key.is_some()) {
+            if enrolled.date() >= cutoff {
+                days.entry(enrolled.date()).or_default().1 += 1;
+            }
+        }
+    }
+
+    Ok(html! {
+        table .table.table-sm {
+            thead { tr { th { "Day" } th { "Authorized" } th { "Enrolled" } } }
+            tbody {
+                @for (day, (authorized, enrolled)) in days.iter().rev() {
+                    tr { td { (day) } td { (authorized) } td { (enrolled) } }
+                }
+            }
+        }
+    })
+}
+
+/// /stats/talkers
+async fn talkers_card(Extension(state): Extension<Arc<AppState>>) -> Result<Markup, ServerError> {
+    let mut conn = state.pool.get()?;
+    let all_clients = Client::get_all(&mut conn)?;
+    let talkers = state
+        .fleet_stats
+        .lock()
+        .await
+        .top_talkers(Duration::from_secs(60 * 60), TOP_TALKERS);
+
+    Ok(html! {
+        table .table.table-sm {
+            thead { tr { th { "Client" } th { "Messages (1h)" } } }
+            tbody {
+                @for (id, count) in &talkers {
+                    tr { td { (client_name(&all_clients, *id)) } td { (count) } }
+                }
+            }
+        }
+    })
+}
+
+/// /stats/uptime
+async fn uptime_card(Extension(state): Extension<Arc<AppState>>) -> Result<Markup, ServerError> {
+    let mut conn = state.pool.get()?;
+    let all_clients = Client::get_all(&mut conn)?;
+    let start = OffsetDateTime::now_utc() - UPTIME_WINDOW;
+    let sessions = ClientSession::since(&mut conn, start)?;
+    let mut availability: Vec<_> = fleet::availability(&sessions, start).into_iter().collect();
+    availability.sort_by(|a, b| a.1.uptime.total_cmp(&b.1.uptime));
+
+    Ok(html! {
+        table .table.table-sm {
+            thead { tr { th { "Client" } th { "Uptime" } th { "Reconnects" } th { "Last online" } } }
+            tbody {
+                @for (id, client) in &availability {
+                    tr {
+                        td { (client_name(&all_clients, *id)) }
+                        td { (format!("{:.1}%", client.uptime * 100.0)) }
+                        td { (client.reconnects) }
+                        td {
+                            @if let Some(last_online) = client.last_online {
+                                (last_online)
+                            } @else { "Now" }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// /stats/versions
+async fn versions_card(Extension(state): Extension<Arc<AppState>>) -> Result<Markup, ServerError> {
+    let mut conn = state.pool.get()?;
+    let ids: Vec<i64> = Client::get_all(&mut conn)?.iter().map(|client| *client.id()).collect();
+    let capabilities = Capabilities::get_for_clients(&mut conn, &ids)?;
+    let bundle = state.assets.manifest();
+
+    let mut versions: BTreeMap<String, usize> = BTreeMap::new();
+    for id in &ids {
+        let build = capabilities.get(id).and_then(|capabilities| capabilities.client_build.as_deref());
+        *versions.entry(releases::version_label(&mut conn, &bundle, build)?).or_default() += 1;
+    }
+
+    Ok(html! {
+        table .table.table-sm {
+            thead { tr { th { "Version" } th { "Clients" } } }
+            tbody {
+                @for (version, count) in &versions {
+                    tr { td { (version) } td { (count) } }
+                }
+            }
+        }
+    })
+}
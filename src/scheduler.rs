@@ -0,0 +1,112 @@
+//! Commands sent on a schedule, e.g. `Update` to `tag:factory` every night at 03:00.
+//!
+//! Schedules are stored in `schedules` with a cron expression, see `crate::cron`, and fired by
+//! `schedule_task` through the same dispatch as commands sent from the clients page. Every run is
+//! recorded in `schedule_runs` with how many of the matched clients got the command. A run missed
+//! while the server was down fires once when it starts again, not once for every missed time.
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::anyhow;
+use time::OffsetDateTime;
+use crate::{parse_duration, AppState};
+use crate::cron::Cron;
+use crate::database::models::{NewScheduleRun, RunStatus, Schedule, ScheduleRun};
+use crate::routes::api::{dispatch_operator_command, DeliveryStatus, ServerWSCommand};
+use crate::selector::Selector;
+use tracing::{error, warn};
+
+/// How often due schedules are checked, cron expressions have a resolution of a minute.
+pub const SCHEDULE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Next time a schedule fires after `now`.
+pub fn next_run(cron: &str, now: OffsetDateTime) -> Result<OffsetDateTime, String> {
+    Cron::parse(cron)?
+        .next_after(now)
+        .ok_or(format!("\"{}\" never runs.", cron))
+}
+
+pub async fn schedule_task(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(SCHEDULE_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = run_due(&state).await {
+            error!("Running scheduled commands failed: {}", e);
+        }
+    }
+}
+
+/// Fire every due schedule, moving each to its next run first so it fires only once.
+async fn run_due(state: &AppState) -> Result<(), anyhow::Error> {
+    let now = OffsetDateTime::now_utc();
+    let due = Schedule::get_due(&mut *state.pool.get()?, now)?;
+    for schedule in due {
+        let mut conn = state.pool.get()?;
+        match next_run(&schedule.cron, now) {
+            Ok(next) => Schedule::set_next_run(&mut conn, schedule.id, next)?,
+            Err(e) => {
+                warn!("Pausing schedule {}: {}", schedule.id, e);
+                Schedule::set_paused(&mut conn, schedule.id, true, schedule.next_run_on)?;
+            }
+        }
+        drop(conn);
+        // one failing schedule must not hold up the rest of the batch
+        if let Err(e) = run(state, &schedule).await {
+            error!("Running schedule {} failed: {}", schedule.id, e);
+        }
+    }
+    Ok(())
+}
+
+/// Send a schedule's command now and record the run.
+pub async fn run(state: &AppState, schedule: &Schedule) -> Result<ScheduleRun, anyhow::Error> {
+    let started_on = OffsetDateTime::now_utc();
+    let mut run = NewScheduleRun {
+        schedule_id: schedule.id,
+        status: RunStatus::Failed.as_str().to_string(),
+        matched: 0,
+        sent: 0,
+        queued: 0,
+        failed: 0,
+        error: None,
+        started_on,
+    };
+    if let Err(e) = dispatch(state, schedule, &mut run).await {
+        run.status = RunStatus::Failed.as_str().to_string();
+        run.error = Some(e.to_string());
+    }
+
+    ScheduleRun::record(&mut *state.pool.get()?, &run)
+}
+
+async fn dispatch(state: &AppState, schedule: &Schedule, run: &mut NewScheduleRun) -> Result<(), anyhow::Error> {
+    let selector = Selector::parse(&schedule.selector).map_err(|e| anyhow!(e))?;
+    let command = ServerWSCommand::operator_command(&schedule.command)
+        .map_err(|_| anyhow!("Unknown command \"{}\"", schedule.command))?;
+    let queue_ttl = schedule
+        .queue_ttl
+        .as_deref()
+        .map(|ttl| parse_duration(ttl).ok_or(anyhow!("Invalid queue TTL \"{}\"", ttl)))
+        .transpose()?;
+    let targets = selector.resolve(&mut *state.pool.get()?)?;
+    let deliveries = dispatch_operator_command(state, targets, &command, &schedule.args, queue_ttl).await?;
+
+    for delivery in &deliveries {
+        match (&delivery.status, delivery.queued) {
+            (DeliveryStatus::Sent, _) => run.sent += 1,
+            (_, Some(_)) => run.queued += 1,
+            _ => run.failed += 1,
+        }
+    }
+    run.matched = deliveries.len() as i32;
+    let status = if run.matched == 0 {
+        RunStatus::NoTargets
+    } else if run.failed == 0 {
+        RunStatus::Sent
+    } else if run.failed < run.matched {
+        RunStatus::Partial
+    } else {
+        RunStatus::Failed
+    };
+    run.status = status.as_str().to_string();
+    Ok(())
+}
@@ -0,0 +1,182 @@
+//! Live mirrors of client screens.
+//!
+//! Clients send their GPU buffer as binary frames, a full snapshot first and then diffs of the
+//! cells that changed. All numbers are big endian:
+//!
+//! ```text
+//! u8  kind         1 = snapshot, 2 = diff
+//! u16 width
+//! u16 height
+//! snapshot: width * height cells, row by row
+//! diff:     u16 run count, each run being u16 x, u16 y (from 0), u16 length and `length` cells
+//!           continuing along the row
+//! cell:     u16 character (BMP code point), u24 foreground RGB, u24 background RGB
+//! ```
+//!
+//! A diff the server has no matching snapshot for, e.g. after a restart, is answered with a
+//! `Screen` command asking the client for a new snapshot.
+use anyhow::anyhow;
+use maud::{html, Markup};
+use time::OffsetDateTime;
+use crate::AppState;
+use crate::routes::api::{send_command, ServerWSCommand};
+
+pub const SNAPSHOT: u8 = 1;
+pub const DIFF: u8 = 2;
+/// Largest resolution of a tier 3 screen.
+pub const MAX_WIDTH: u16 = 160;
+pub const MAX_HEIGHT: u16 = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub character: char,
+    pub foreground: u32,
+    pub background: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Run {
+    pub x: u16,
+    pub y: u16,
+    pub cells: Vec<Cell>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScreenFrame {
+    Snapshot { width: u16, height: u16, cells: Vec<Cell> },
+    Diff { width: u16, height: u16, runs: Vec<Run> },
+}
+
+/// Reads big endian fields off a frame, failing on truncated input.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take(&mut self, length: usize) -> Result<&[u8], anyhow::Error> {
+        if self.data.len() < length {
+            return Err(anyhow!("Screen frame is truncated."));
+        }
+        let (taken, rest) = self.data.split_at(length);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn uint(&mut self, length: usize) -> Result<u32, anyhow::Error> {
+        Ok(self.take(length)?.iter().fold(0, |value, &byte| value << 8 | byte as u32))
+    }
+
+    fn u16(&mut self) -> Result<u16, anyhow::Error> {
+        Ok(self.uint(2)? as u16)
+    }
+
+    fn cell(&mut self) -> Result<Cell, anyhow::Error> {
+        let character = char::from_u32(self.uint(2)?).unwrap_or(char::REPLACEMENT_CHARACTER);
+        Ok(Cell { character, foreground: self.uint(3)?, background: self.uint(3)? })
+    }
+
+    fn cells(&mut self, count: usize) -> Result<Vec<Cell>, anyhow::Error> {
+        (0..count).map(|_| self.cell()).collect()
+    }
+}
+
+impl ScreenFrame {
+    pub fn decode(data: &[u8]) -> Result<ScreenFrame, anyhow::Error> {
+        let mut reader = Reader { data };
+        let kind = reader.uint(1)? as u8;
+        let (width, height) = (reader.u16()?, reader.u16()?);
+        if width == 0 || height == 0 || width > MAX_WIDTH || height > MAX_HEIGHT {
+            return Err(anyhow!("Invalid screen resolution {}x{}", width, height));
+        }
+
+        let frame = match kind {
+            SNAPSHOT => {
+                let cells = reader.cells(width as usize * height as usize)?;
+                ScreenFrame::Snapshot { width, height, cells }
+            }
+            DIFF => {
+                let count = reader.u16()?;
+                let mut runs = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    let (x, y, length) = (reader.u16()?, reader.u16()?, reader.u16()?);
+                    if y >= height || x as u32 + length as u32 > width as u32 {
+                        return Err(anyhow!("Screen diff run is out of bounds."));
+                    }
+                    runs.push(Run { x, y, cells: reader.cells(length as usize)? });
+                }
+                ScreenFrame::Diff { width, height, runs }
+            }
+            _ => return Err(anyhow!("Unknown screen frame kind {}", kind)),
+        };
+        if !reader.data.is_empty() {
+            return Err(anyhow!("Screen frame has trailing data."));
+        }
+        Ok(frame)
+    }
+}
+
+/// Last known contents of a client's screen.
+#[derive(Clone)]
+pub struct Screen {
+    pub width: u16,
+    pub height: u16,
+    pub cells: Vec<Cell>,
+    pub updated_on: OffsetDateTime,
+}
+
+impl Screen {
+    /// Apply a frame on top of the current screen, `None` when a diff has nothing to apply to.
+    pub fn apply(current: Option<&Screen>, frame: ScreenFrame) -> Option<Screen> {
+        let updated_on = OffsetDateTime::now_utc();
+        match frame {
+            ScreenFrame::Snapshot { width, height, cells } => Some(Screen { width, height, cells, updated_on }),
+            ScreenFrame::Diff { width, height, runs } => {
+                let mut screen = current.filter(|screen| screen.width == width && screen.height == height)?.clone();
+                for run in runs {
+                    let start = run.y as usize * width as usize + run.x as usize;
+                    screen.cells[start..start + run.cells.len()].copy_from_slice(&run.cells);
+                }
+                screen.updated_on = updated_on;
+                Some(screen)
+            }
+        }
+    }
+
+    /// The screen as a grid of colored text, consecutive cells sharing colors are merged.
+    pub fn render(&self) -> Markup {
+        html! {
+            pre .font-mono.leading-none.inline-block.text-sm {
+                @for row in self.cells.chunks(self.width as usize) {
+                    @for span in row.chunk_by(|a, b| (a.foreground, a.background) == (b.foreground, b.background)) {
+                        span style=(format!("color:#{:06x};background:#{:06x}", span[0].foreground, span[0].background)) {
+                            (span.iter().map(|cell| cell.character).collect::<String>())
+                        }
+                    }
+                    "\n"
+                }
+            }
+        }
+    }
+}
+
+/// Handle a raw binary screen frame from a client that did not negotiate a codec.
+pub async fn handle_frame(client_id: i64, data: &[u8], state: &AppState) -> Result<(), anyhow::Error> {
+    apply_frame(client_id, ScreenFrame::decode(data)?, state).await
+}
+
+/// Update the mirror of a client's screen, asking for a snapshot when a diff does not apply.
+pub async fn apply_frame(client_id: i64, frame: ScreenFrame, state: &AppState) -> Result<(), anyhow::Error> {
+    let mut screens = state.screens.lock().await;
+    match Screen::apply(screens.get(&client_id), frame) {
+        Some(screen) => {
+            screens.insert(client_id, screen);
+        }
+        None => {
+            drop(screens);
+            send_command(state, client_id, ServerWSCommand::Screen.to_message(""))
+                .await
+                .map_err(|e| e.0)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,233 @@
+//! Selectors pick the clients a command is sent to.
+//!
+//! A selector is a comma separated list of alternatives and a client is selected when it matches
+//! any of them. An alternative is a space separated list of terms that must all match:
+//!
+//! - `*` or `all`: every client
+//! - `12` or `id:12`: the client with that id
+//! - `tag:factory`: clients tagged `factory`
+//! - `status:connected`: clients in that status
+//! - `name:reactor-*` or just `reactor-*`: clients whose name matches the glob (`*` and `?`)
+//!
+//! `tag:factory status:connected, 4` selects the connected factory clients and client 4.
+//! Revoked clients are never selected.
+
+use std::collections::HashMap;
+use std::fmt;
+use diesel::{Identifiable, PgConnection};
+use crate::database::models::{Client, Status, Tag};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    All,
+    Id(i64),
+    Tag(String),
+    Status(&'static str),
+    Name(String),
+}
+
+impl Term {
+    fn parse(string: &str) -> Result<Term, String> {
+        if string == "*" || string.eq_ignore_ascii_case("all") {
+            return Ok(Term::All);
+        }
+        if let Ok(id) = string.parse() {
+            return Ok(Term::Id(id));
+        }
+        let Some((key, value)) = string.split_once(':') else {
+            return Ok(Term::Name(string.to_string()));
+        };
+        match key {
+            "id" => value.parse()
+                .map(Term::Id)
+                .map_err(|_| format!("\"{}\" is not a client id.", value)),
+            "tag" => Tag::normalize(value).map(Term::Tag),
+            "status" => Status::ALL.iter()
+                .find(|status| status.as_str().eq_ignore_ascii_case(value))
+                .map(|status| Term::Status(status.as_str()))
+                .ok_or(format!("Unknown status \"{}\".", value)),
+            "name" if !value.is_empty() => Ok(Term::Name(value.to_string())),
+            _ => Err(format!("Unknown selector term \"{}\".", string)),
+        }
+    }
+
+    fn matches(&self, client: &Client, tags: &[String]) -> bool {
+        match self {
+            Term::All => true,
+            Term::Id(id) => client.id() == id,
+            Term::Tag(tag) => tags.contains(tag),
+            Term::Status(status) => client.status == *status,
+            Term::Name(pattern) => glob_match(pattern, &client.name),
+        }
+    }
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Term::All => write!(f, "all"),
+            Term::Id(id) => write!(f, "id:{}", id),
+            Term::Tag(tag) => write!(f, "tag:{}", tag),
+            Term::Status(status) => write!(f, "status:{}", status.to_lowercase()),
+            Term::Name(pattern) => write!(f, "name:{}", pattern),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Selector {
+    alternatives: Vec<Vec<Term>>,
+}
+
+impl Selector {
+    pub fn parse(string: &str) -> Result<Selector, String> {
+        let alternatives = string
+            .split(',')
+            .map(|alternative| {
+                alternative.split_whitespace().map(Term::parse).collect::<Result<Vec<_>, _>>()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if alternatives.iter().any(Vec::is_empty) {
+            return Err("Selector has an empty alternative.".to_string());
+        }
+        Ok(Selector { alternatives })
+    }
+
+    /// Select a single client.
+    pub fn id(client: i64) -> Selector {
+        Selector { alternatives: vec![vec![Term::Id(client)]] }
+    }
+
+    pub fn matches(&self, client: &Client, tags: &[String]) -> bool {
+        !client.revoked
+            && self.alternatives.iter().any(|terms| terms.iter().all(|term| term.matches(client, tags)))
+    }
+
+    /// Every client the selector matches.
+    pub fn resolve(&self, connection: &mut PgConnection) -> Result<Vec<Client>, anyhow::Error> {
+        let mut tags: HashMap<i64, Vec<String>> = HashMap::new();
+        for (client, tag) in Tag::get_assignments(connection)? {
+            tags.entry(client).or_default().push(tag);
+        }
+        let selected = Client::get_all(connection)?
+            .into_iter()
+            .filter(|client| {
+                let client_tags = tags.get(client.id()).map(Vec::as_slice).unwrap_or_default();
+                self.matches(client, client_tags)
+            })
+            .collect();
+        Ok(selected)
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, terms) in self.alternatives.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            for (j, term) in terms.iter().enumerate() {
+                if j > 0 {
+                    write!(f, " ")?;
+                }
+                write!(f, "{}", term)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Case insensitive glob match, `*` matches any run of characters and `?` exactly one.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // position of the last `*` and the text position it is currently matched up to
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_matches_any_run_and_single_characters() {
+        assert!(glob_match("reactor-*", "reactor-1"));
+        assert!(glob_match("reactor-*", "reactor-"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*-?", "reactor-1"));
+        assert!(glob_match("r*t*r-?", "reactor-1"));
+        assert!(glob_match("*a*b", "aXbab"));
+        assert!(!glob_match("reactor-?", "reactor-"));
+        assert!(!glob_match("reactor-?", "reactor-12"));
+        assert!(!glob_match("reactor", "reactor-1"));
+        assert!(!glob_match("*-1", "reactor-12"));
+    }
+
+    #[test]
+    fn glob_ignores_case() {
+        assert!(glob_match("REACTOR-*", "Reactor-1"));
+        assert!(glob_match("reactor-?", "REACTOR-A"));
+    }
+
+    #[test]
+    fn terms_parse() {
+        assert_eq!(Term::parse("*"), Ok(Term::All));
+        assert_eq!(Term::parse("ALL"), Ok(Term::All));
+        assert_eq!(Term::parse("12"), Ok(Term::Id(12)));
+        assert_eq!(Term::parse("id:12"), Ok(Term::Id(12)));
+        assert_eq!(Term::parse("tag:factory"), Ok(Term::Tag("factory".to_string())));
+        assert_eq!(Term::parse("status:CONNECTED"), Ok(Term::Status(Status::Connected.as_str())));
+        assert_eq!(Term::parse("name:reactor-*"), Ok(Term::Name("reactor-*".to_string())));
+        assert_eq!(Term::parse("reactor-*"), Ok(Term::Name("reactor-*".to_string())));
+    }
+
+    #[test]
+    fn invalid_terms_are_rejected() {
+        assert!(Term::parse("id:twelve").is_err());
+        assert!(Term::parse("status:sleeping").is_err());
+        assert!(Term::parse("name:").is_err());
+        assert!(Term::parse("owner:me").is_err());
+    }
+
+    #[test]
+    fn spaces_join_terms_and_commas_split_alternatives() {
+        let selector = Selector::parse("tag:factory status:connected, 4").unwrap();
+        assert_eq!(selector.alternatives, vec![
+            vec![Term::Tag("factory".to_string()), Term::Status(Status::Connected.as_str())],
+            vec![Term::Id(4)],
+        ]);
+        assert_eq!(selector.to_string(), "tag:factory status:connected, id:4");
+        assert_eq!(Selector::parse(&selector.to_string()), Ok(selector));
+    }
+
+    #[test]
+    fn empty_alternatives_are_rejected() {
+        assert!(Selector::parse("").is_err());
+        assert!(Selector::parse("  ").is_err());
+        assert!(Selector::parse("4,").is_err());
+        assert!(Selector::parse("4, ,5").is_err());
+    }
+}
@@ -0,0 +1,145 @@
+//! Server settings edited on the settings page and stored in `settings`.
+//!
+//! Every setting has a default, used until it is saved and whenever a stored value is no longer
+//! valid. Saved settings apply right away, except for the bind address which is only read when
+//! the server starts. Settings given in the server configuration override the stored ones, see
+//! `crate::config`.
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+use diesel::PgConnection;
+use crate::config::Config;
+use crate::database::models::Setting;
+use crate::parse_duration;
+use tracing::warn;
+
+pub const BIND_ADDRESS: &str = "bind_address";
+pub const API_KEY_LENGTH: &str = "api_key_length";
+pub const AUTH_CODE_LENGTH: &str = "auth_code_length";
+pub const REQUEST_TIMEOUT: &str = "request_timeout";
+
+/// Keys shorter than this are too easy to guess.
+const MIN_API_KEY_LENGTH: usize = 16;
+const MAX_API_KEY_LENGTH: usize = 128;
+/// Authorization codes are typed in by hand on the new computer.
+const MIN_AUTH_CODE_LENGTH: usize = 6;
+const MAX_AUTH_CODE_LENGTH: usize = 32;
+const MAX_REQUEST_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    /// Address the server listens on, applies on restart.
+    pub bind_address: SocketAddr,
+    /// Length of newly issued API keys.
+    pub api_key_length: usize,
+    /// Length of newly issued authorization codes.
+    pub auth_code_length: usize,
+    /// How long a request may take before it is answered with `408 Request Timeout`.
+    pub request_timeout: Duration,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            bind_address: SocketAddr::from(([0, 0, 0, 0], 3000)),
+            api_key_length: 48,
+            auth_code_length: 8,
+            request_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl Settings {
+    /// The stored settings, stored values that are invalid are reported and replaced by defaults.
+    pub fn load(conn: &mut PgConnection) -> Result<Settings, anyhow::Error> {
+        let values: HashMap<String, String> = Setting::get_all(conn)?
+            .into_iter()
+            .map(|setting| (setting.key, setting.value))
+            .collect();
+        let (settings, errors) = Settings::default().merge(&values);
+        for error in errors {
+            warn!("Ignoring stored setting: {}", error);
+        }
+        Ok(settings)
+    }
+
+    /// These settings updated with the ones submitted on the settings page, with every problem if
+    /// they are invalid.
+    pub fn update(&self, values: &HashMap<String, String>) -> Result<Settings, Vec<String>> {
+        let (settings, errors) = self.clone().merge(values);
+        if errors.is_empty() { Ok(settings) } else { Err(errors) }
+    }
+
+    /// These settings with the ones the configuration sets instead.
+    pub fn overridden(mut self, config: &Config) -> Settings {
+        if let Some(address) = config.bind_address {
+            self.bind_address = address;
+        }
+        if let Some(timeout) = config.request_timeout {
+            self.request_timeout = timeout;
+        }
+        self
+    }
+
+    /// Keys of the settings the configuration overrides.
+    pub fn overridden_keys(config: &Config) -> Vec<&'static str> {
+        let mut keys = Vec::new();
+        if config.bind_address.is_some() {
+            keys.push(BIND_ADDRESS);
+        }
+        if config.request_timeout.is_some() {
+            keys.push(REQUEST_TIMEOUT);
+        }
+        keys
+    }
+
+    /// These settings with the given values applied, along with the ones that were invalid.
+    fn merge(mut self, values: &HashMap<String, String>) -> (Settings, Vec<String>) {
+        let mut errors = Vec::new();
+        for (key, value) in values {
+            let value = value.trim();
+            let result = match key.as_str() {
+                BIND_ADDRESS => value
+                    .parse()
+                    .map(|address| self.bind_address = address)
+                    .map_err(|_| format!("Bind address \"{}\" is not an address like 0.0.0.0:3000.", value)),
+                API_KEY_LENGTH => parse_length("API key length", value, MIN_API_KEY_LENGTH, MAX_API_KEY_LENGTH)
+                    .map(|length| self.api_key_length = length),
+                AUTH_CODE_LENGTH => parse_length("Authorization code length", value, MIN_AUTH_CODE_LENGTH, MAX_AUTH_CODE_LENGTH)
+                    .map(|length| self.auth_code_length = length),
+                REQUEST_TIMEOUT => parse_duration(value)
+                    .filter(|timeout| *timeout <= MAX_REQUEST_TIMEOUT)
+                    .map(|timeout| self.request_timeout = timeout)
+                    .ok_or(format!("Request timeout \"{}\" must be a duration such as 10s, at most 10m.", value)),
+                _ => Err(format!("Unknown setting \"{}\".", key)),
+            };
+            if let Err(error) = result {
+                errors.push(error);
+            }
+        }
+        errors.sort();
+        (self, errors)
+    }
+
+    /// The settings as stored, by key.
+    pub fn to_values(&self) -> Vec<(&'static str, String)> {
+        vec![
+            (BIND_ADDRESS, self.bind_address.to_string()),
+            (API_KEY_LENGTH, self.api_key_length.to_string()),
+            (AUTH_CODE_LENGTH, self.auth_code_length.to_string()),
+            (REQUEST_TIMEOUT, format!("{}s", self.request_timeout.as_secs())),
+        ]
+    }
+
+    pub fn save(&self, conn: &mut PgConnection) -> Result<(), anyhow::Error> {
+        Setting::set_all(conn, &self.to_values())
+    }
+}
+
+fn parse_length(name: &str, value: &str, min: usize, max: usize) -> Result<usize, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|length| (min..=max).contains(length))
+        .ok_or(format!("{} must be a number from {} to {}.", name, min, max))
+}
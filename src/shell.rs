@@ -0,0 +1,157 @@
+//! Remote shell sessions between a browser terminal and a client.
+//!
+//! `Shell <session> open` starts a shell on the client, keystrokes from the browser follow as
+//! `Shell <session> input <data>` and the client streams back `Shell <session> output <data>`.
+//! Either side ends the session with `Shell <session> close`, answered by the client as
+//! `Shell <session> closed`. Everything passing through is recorded in `shell_events` for replay.
+use std::sync::Arc;
+use std::time::Instant;
+use anyhow::anyhow;
+use axum::extract::ws::{Message, WebSocket};
+use diesel::prelude::*;
+use futures_util::{SinkExt, StreamExt};
+use tokio::sync::mpsc;
+use crate::AppState;
+use crate::database::models::{NewShellEvent, ShellSession};
+use crate::database::schema::shell_events;
+use crate::routes::api::{send_command, ServerWSCommand};
+use tracing::error;
+
+/// Client side of a session, forwarded to the browser bridging it.
+pub enum ShellMessage {
+    Output(String),
+    Closed(String),
+}
+
+/// A running session, keyed by its `shell_sessions` id in `AppState::shells`.
+pub struct ShellBridge {
+    pub client_id: i64,
+    pub sender: mpsc::UnboundedSender<ShellMessage>,
+}
+
+fn command(session: i64, operation: &str, data: &str) -> Message {
+    if data.is_empty() {
+        ServerWSCommand::Shell.to_message(&format!("{} {}", session, operation))
+    } else {
+        ServerWSCommand::Shell.to_message(&format!("{} {} {}", session, operation, data))
+    }
+}
+
+/// Carries one recorded session's timeline.
+struct Recorder {
+    session: i64,
+    started: Instant,
+}
+
+impl Recorder {
+    fn record(&self, state: &AppState, direction: &str, data: &str) {
+        let event = NewShellEvent {
+            session_id: self.session,
+            offset_ms: self.started.elapsed().as_millis() as i64,
+            direction,
+            data,
+        };
+        let result = state
+            .pool
+            .get()
+            .map_err(anyhow::Error::from)
+            .and_then(|mut conn| {
+                diesel::insert_into(shell_events::table).values(&event).execute(&mut conn)?;
+                Ok(())
+            });
+        if let Err(e) = result {
+            error!("Failed to record shell session {}: {}", self.session, e);
+        }
+    }
+}
+
+/// Bridge a browser terminal to a shell on the client until either side closes it.
+pub async fn bridge(socket: WebSocket, client_id: i64, state: Arc<AppState>) {
+    let session = match state.pool.get().map_err(anyhow::Error::from)
+        .and_then(|mut conn| ShellSession::start(&mut conn, client_id)) {
+        Ok(session) => session,
+        Err(e) => {
+            error!("Failed to start shell session for client {}: {}", client_id, e);
+            return;
+        }
+    };
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    state.shells.lock().await.insert(session, ShellBridge { client_id, sender });
+
+    let recorder = Recorder { session, started: Instant::now() };
+    let (mut browser_tx, mut browser_rx) = socket.split();
+    let reason = match send_command(&state, client_id, command(session, "open", "")).await {
+        Err(_) => {
+            browser_tx.send(Message::Text("Client is not connected.\r\n".to_string())).await.ok();
+            "Client is not connected".to_string()
+        }
+        Ok(()) => loop {
+            tokio::select! {
+                message = browser_rx.next() => match message {
+                    Some(Ok(Message::Text(keys))) => {
+                        recorder.record(&state, "input", &keys);
+                        if send_command(&state, client_id, command(session, "input", &keys)).await.is_err() {
+                            browser_tx.send(Message::Text("\r\nClient disconnected.\r\n".to_string())).await.ok();
+                            break "Client disconnected".to_string();
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => {
+                        send_command(&state, client_id, command(session, "close", "")).await.ok();
+                        break "Closed by operator".to_string();
+                    }
+                    Some(Ok(_)) => {}
+                },
+                event = receiver.recv() => match event {
+                    Some(ShellMessage::Output(output)) => {
+                        recorder.record(&state, "output", &output);
+                        if browser_tx.send(Message::Text(output)).await.is_err() {
+                            send_command(&state, client_id, command(session, "close", "")).await.ok();
+                            break "Browser disconnected".to_string();
+                        }
+                    }
+                    Some(ShellMessage::Closed(reason)) => {
+                        browser_tx.send(Message::Text(format!("\r\n{}.\r\n", reason))).await.ok();
+                        break reason;
+                    }
+                    None => break "Session dropped".to_string(),
+                },
+            }
+        },
+    };
+
+    browser_tx.send(Message::Close(None)).await.ok();
+    state.shells.lock().await.remove(&session);
+    let result = state.pool.get().map_err(anyhow::Error::from)
+        .and_then(|mut conn| ShellSession::finish(&mut conn, session, &reason));
+    if let Err(e) = result {
+        error!("Failed to end shell session {}: {}", session, e);
+    }
+}
+
+/// Handle `Shell <session> output <data>` and `Shell <session> closed` from a client.
+pub async fn handle_shell(client_id: i64, body: &str, state: &AppState) -> Result<(), anyhow::Error> {
+    let mut parts = body.splitn(3, ' ');
+    let session: i64 = parts.next().unwrap_or_default().parse()?;
+    let message = match parts.next() {
+        Some("output") => ShellMessage::Output(parts.next().unwrap_or_default().to_string()),
+        Some("closed") => ShellMessage::Closed("Shell exited".to_string()),
+        _ => return Err(anyhow!("Invalid shell message.")),
+    };
+
+    let shells = state.shells.lock().await;
+    let bridge = shells
+        .get(&session)
+        .filter(|bridge| bridge.client_id == client_id)
+        .ok_or(anyhow!("No shell session {}", session))?;
+    bridge.sender.send(message).ok();
+    Ok(())
+}
+
+/// End every session of a client whose connection went away.
+pub async fn client_disconnected(client_id: i64, state: &AppState) {
+    for bridge in state.shells.lock().await.values() {
+        if bridge.client_id == client_id {
+            bridge.sender.send(ShellMessage::Closed("Client disconnected".to_string())).ok();
+        }
+    }
+}
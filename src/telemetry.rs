@@ -0,0 +1,286 @@
+//! Rollups of raw telemetry samples into fixed resolution min/max/avg buckets.
+//!
+//! Raw item and power samples are aggregated into `telemetry_rollups` by a background task, each
+//! resolution being computed from the next finer one. Every metric is keyed by name, items as
+//! `item:<item name>` and power as `power:stored`, `power:capacity` and `power:draw`.
+use std::sync::Arc;
+use std::time::Duration;
+use diesel::prelude::*;
+use diesel::sql_types::{Int4, Nullable, Timestamptz};
+use serde::Deserialize;
+use time::OffsetDateTime;
+use crate::AppState;
+use crate::database::models::{NewPowerSample, TelemetryPoint};
+use crate::database::schema::power_samples;
+use crate::error::ServerError;
+use tracing::error;
+
+/// How often the rollup task runs.
+pub const ROLLUP_INTERVAL: Duration = Duration::from_secs(60);
+/// Raw samples older than this are dropped once they have been rolled up.
+pub const RAW_RETENTION: Duration = Duration::from_secs(6 * 60 * 60);
+/// Fewest buckets a query should return when picking a resolution.
+pub const MIN_QUERY_POINTS: u64 = 30;
+
+pub const ITEM_METRIC_PREFIX: &str = "item:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Minute,
+    FifteenMinutes,
+    Hour,
+    Day,
+}
+
+impl Resolution {
+    /// Finest first, each resolution is rolled up from the one before it.
+    pub const ALL: [Resolution; 4] = [
+        Resolution::Minute,
+        Resolution::FifteenMinutes,
+        Resolution::Hour,
+        Resolution::Day,
+    ];
+
+    pub fn seconds(&self) -> i32 {
+        match self {
+            Resolution::Minute => 60,
+            Resolution::FifteenMinutes => 15 * 60,
+            Resolution::Hour => 60 * 60,
+            Resolution::Day => 24 * 60 * 60,
+        }
+    }
+
+    /// How long buckets are kept, `None` keeps them forever.
+    pub fn retention(&self) -> Option<Duration> {
+        match self {
+            Resolution::Minute => Some(Duration::from_secs(2 * 24 * 60 * 60)),
+            Resolution::FifteenMinutes => Some(Duration::from_secs(14 * 24 * 60 * 60)),
+            Resolution::Hour => Some(Duration::from_secs(90 * 24 * 60 * 60)),
+            Resolution::Day => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Resolution::Minute => "1m",
+            Resolution::FifteenMinutes => "15m",
+            Resolution::Hour => "1h",
+            Resolution::Day => "1d",
+        }
+    }
+
+    /// Start of the bucket `time` falls into.
+    pub fn bucket_start(&self, time: OffsetDateTime) -> OffsetDateTime {
+        let seconds = self.seconds() as i64;
+        let start = time.unix_timestamp().div_euclid(seconds) * seconds;
+        OffsetDateTime::from_unix_timestamp(start).unwrap_or(time)
+    }
+
+    /// The coarsest resolution that still has `MIN_QUERY_POINTS` buckets across the range and
+    /// has not yet dropped data from its start. Falls back to the finest resolution.
+    pub fn pick(start: OffsetDateTime, end: OffsetDateTime) -> Resolution {
+        let range = (end - start).whole_seconds().max(0) as u64;
+        let age = (OffsetDateTime::now_utc() - start).whole_seconds().max(0) as u64;
+        Resolution::ALL
+            .into_iter()
+            .rev()
+            .find(|resolution| {
+                let enough_points = range / resolution.seconds() as u64 >= MIN_QUERY_POINTS;
+                let retained = resolution.retention().is_none_or(|keep| age <= keep.as_secs());
+                enough_points && retained
+            })
+            .unwrap_or(Resolution::Minute)
+    }
+}
+
+#[derive(Deserialize)]
+struct ReportedPower {
+    stored: f64,
+    capacity: f64,
+    #[serde(default)]
+    draw: f64,
+}
+
+/// Store a `Power <json>` report.
+pub async fn handle_power(client_id: i64, body: &str, state: &AppState) -> Result<(), ServerError> {
+    let reported: ReportedPower = serde_json::from_str(body)?;
+    let mut conn = state.pool.get()?;
+    diesel::insert_into(power_samples::table)
+        .values(NewPowerSample {
+            client_id,
+            stored: reported.stored,
+            capacity: reported.capacity,
+            draw: reported.draw,
+        })
+        .execute(&mut conn)?;
+    Ok(())
+}
+
+/// Background task rolling up telemetry and applying retention every `ROLLUP_INTERVAL`.
+pub async fn rollup_task(state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(ROLLUP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let result = state
+            .pool
+            .get()
+            .map_err(anyhow::Error::from)
+            .and_then(|mut conn| run_rollups(&mut conn));
+        if let Err(e) = result {
+            error!("Telemetry rollup failed: {}", e);
+        }
+    }
+}
+
+const ROLLUP_UPSERT: &str = "
+    ON CONFLICT (client_id, metric, resolution, bucket) DO UPDATE SET
+        min_value = EXCLUDED.min_value,
+        max_value = EXCLUDED.max_value,
+        avg_value = EXCLUDED.avg_value,
+        sample_count = EXCLUDED.sample_count";
+
+/// Roll up every completed bucket since the last run at each resolution, then drop expired data.
+pub fn run_rollups(conn: &mut PgConnection) -> Result<(), anyhow::Error> {
+    let now = OffsetDateTime::now_utc();
+    let mut finer: Option<Resolution> = None;
+
+    for resolution in Resolution::ALL {
+        // the last bucket is recomputed, it may have been rolled up before all its samples arrived
+        let watermark = latest_bucket(conn, resolution)?;
+        let until = resolution.bucket_start(now);
+        let seconds = resolution.seconds();
+
+        match finer {
+            None => {
+                diesel::sql_query(format!("
+                    INSERT INTO telemetry_rollups
+                    SELECT client_id, 'item:' || item_name, $1,
+                           to_timestamp(floor(extract(epoch FROM sampled_on) / $1) * $1),
+                           min(amount), max(amount), avg(amount), count(*)
+                    FROM (
+                        SELECT client_id, item_name, sampled_on, sum(amount)::float8 AS amount
+                        FROM item_samples
+                        WHERE ($2 IS NULL OR sampled_on >= $2) AND sampled_on < $3
+                        GROUP BY client_id, item_name, sampled_on
+                    ) snapshot_totals
+                    GROUP BY 1, 2, 4
+                    {ROLLUP_UPSERT}"))
+                    .bind::<Int4, _>(seconds)
+                    .bind::<Nullable<Timestamptz>, _>(watermark)
+                    .bind::<Timestamptz, _>(until)
+                    .execute(conn)?;
+
+                diesel::sql_query(format!("
+                    INSERT INTO telemetry_rollups
+                    SELECT client_id, metrics.metric, $1,
+                           to_timestamp(floor(extract(epoch FROM sampled_on) / $1) * $1),
+                           min(metrics.value), max(metrics.value), avg(metrics.value), count(*)
+                    FROM power_samples
+                    CROSS JOIN LATERAL (VALUES
+                        ('power:stored', stored),
+                        ('power:capacity', capacity),
+                        ('power:draw', draw)
+                    ) AS metrics (metric, value)
+                    WHERE ($2 IS NULL OR sampled_on >= $2) AND sampled_on < $3
+                    GROUP BY 1, 2, 4
+                    {ROLLUP_UPSERT}"))
+                    .bind::<Int4, _>(seconds)
+                    .bind::<Nullable<Timestamptz>, _>(watermark)
+                    .bind::<Timestamptz, _>(until)
+                    .execute(conn)?;
+            }
+            Some(finer) => {
+                diesel::sql_query(format!("
+                    INSERT INTO telemetry_rollups
+                    SELECT client_id, metric, $1,
+                           to_timestamp(floor(extract(epoch FROM bucket) / $1) * $1),
+                           min(min_value), max(max_value),
+                           sum(avg_value * sample_count) / sum(sample_count), sum(sample_count)
+                    FROM telemetry_rollups
+                    WHERE resolution = $4 AND ($2 IS NULL OR bucket >= $2) AND bucket < $3
+                    GROUP BY 1, 2, 4
+                    {ROLLUP_UPSERT}"))
+                    .bind::<Int4, _>(seconds)
+                    .bind::<Nullable<Timestamptz>, _>(watermark)
+                    .bind::<Timestamptz, _>(until)
+                    .bind::<Int4, _>(finer.seconds())
+                    .execute(conn)?;
+            }
+        }
+        finer = Some(resolution);
+    }
+
+    apply_retention(conn, now)
+}
+
+fn latest_bucket(
+    conn: &mut PgConnection,
+    resolution_query: Resolution,
+) -> Result<Option<OffsetDateTime>, anyhow::Error> {
+    use crate::database::schema::telemetry_rollups::dsl::*;
+    let latest = telemetry_rollups
+        .filter(resolution.eq(resolution_query.seconds()))
+        .select(diesel::dsl::max(bucket))
+        .first(conn)?;
+    Ok(latest)
+}
+
+/// Drop raw samples that are both rolled up and older than `RAW_RETENTION`, keeping the latest
+/// snapshot of every client, then drop rollups past their resolution's retention.
+fn apply_retention(conn: &mut PgConnection, now: OffsetDateTime) -> Result<(), anyhow::Error> {
+    use crate::database::schema::telemetry_rollups::dsl::*;
+
+    let rolled_up_until = Resolution::Minute.bucket_start(now);
+    let cutoff = (now - RAW_RETENTION).min(rolled_up_until);
+
+    diesel::sql_query("
+        DELETE FROM item_samples AS old
+        WHERE sampled_on < $1 AND sampled_on < (
+            SELECT max(sampled_on) FROM item_samples latest WHERE latest.client_id = old.client_id
+        )")
+        .bind::<Timestamptz, _>(cutoff)
+        .execute(conn)?;
+
+    diesel::delete(power_samples::table)
+        .filter(power_samples::sampled_on.lt(cutoff))
+        .execute(conn)?;
+
+    for level in Resolution::ALL {
+        if let Some(keep) = level.retention() {
+            diesel::delete(telemetry_rollups)
+                .filter(resolution.eq(level.seconds()))
+                .filter(bucket.lt(now - keep))
+                .execute(conn)?;
+        }
+    }
+    Ok(())
+}
+
+/// Rolled up points for metrics matching `metric` (SQL `LIKE`, so `item:%` selects every item)
+/// between `start` and `end`, at the resolution picked by `Resolution::pick`.
+pub fn query(
+    conn: &mut PgConnection,
+    client: Option<i64>,
+    metric_pattern: &str,
+    start: OffsetDateTime,
+    end: OffsetDateTime,
+) -> Result<(Resolution, Vec<TelemetryPoint>), anyhow::Error> {
+    use crate::database::schema::telemetry_rollups::dsl::*;
+
+    let picked = Resolution::pick(start, end);
+    let mut points = telemetry_rollups
+        .filter(resolution.eq(picked.seconds()))
+        .filter(metric.like(metric_pattern))
+        .filter(bucket.ge(picked.bucket_start(start)))
+        .filter(bucket.lt(end))
+        .into_boxed();
+    if let Some(client) = client {
+        points = points.filter(client_id.eq(client));
+    }
+
+    let points = points
+        .order((client_id, metric, bucket))
+        .select(TelemetryPoint::as_select())
+        .load(conn)?;
+    Ok((picked, points))
+}
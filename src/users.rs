@@ -0,0 +1,54 @@
+//! Accounts of the people running the server, managed from the command line with `site user`.
+//!
+//! Passwords are stored as argon2 hashes with a random salt each. Without a password of their own
+//! the commands generate one and print it once, like authorization codes for clients.
+//!
+//! The web UI has no login yet, so nothing reads the accounts back: they can be created and their
+//! passwords reset, but they do not grant or restrict access to anything.
+use anyhow::anyhow;
+use argon2::{Argon2, PasswordHasher};
+use argon2::password_hash::SaltString;
+use argon2::password_hash::rand_core::OsRng;
+use crate::routes::api::create_api_key;
+
+pub const MAX_USERNAME_LENGTH: usize = 32;
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+/// Length of generated passwords, alphanumeric like API keys.
+pub const GENERATED_PASSWORD_LENGTH: usize = 20;
+
+/// Usernames are lowercase so accounts cannot differ only in case.
+pub fn normalize_username(name: &str) -> Result<String, String> {
+    let name = name.trim().to_lowercase();
+    if name.is_empty() {
+        return Err("Username must not be empty.".to_string());
+    }
+    if name.chars().count() > MAX_USERNAME_LENGTH {
+        return Err(format!("Username must be at most {} characters.", MAX_USERNAME_LENGTH));
+    }
+    if !name.chars().all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.')) {
+        return Err("Username may only contain letters, digits, '-', '_' and '.'.".to_string());
+    }
+    Ok(name)
+}
+
+pub fn check_password(password: &str) -> Result<(), String> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(format!("Password must be at least {} characters.", MIN_PASSWORD_LENGTH));
+    }
+    if password.chars().any(char::is_control) {
+        return Err("Password must not contain control characters.".to_string());
+    }
+    Ok(())
+}
+
+pub fn generate_password() -> String {
+    create_api_key(GENERATED_PASSWORD_LENGTH)
+}
+
+pub fn hash_password(password: &str) -> Result<String, anyhow::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow!("Failed to hash the password: {}", e))?;
+    Ok(hash.to_string())
+}
@@ -1,6 +1,7 @@
 local term = require("term");
 local string = require("string");
 local component = require("component");
+local computer = require("computer");
 local WebSocket = require("ws");
 local event = require("event");
 local internet = require("internet");
@@ -10,18 +11,29 @@ local os = require("os");
 local colors = require("colors");
 local RUi = require("rui");
 local commands = require("commands");
+local util = require("util");
+local Config = require("config");
 
 local gpu = component.gpu;
 
-local RECONNECT_TIMEOUT = 30 -- 30 seconds cooldown between attempts
+local TELEMETRY_INTERVAL = 60 -- seconds between AE inventory and power reports
+local SCREEN_INTERVAL = 2 -- seconds between screen mirror updates
+local SCREEN_SNAPSHOT = 1 -- binary screen frame kinds, see the server's screen module
+local SCREEN_DIFF = 2
+-- compact binary frames, see the server's codec module
+local COMPACT_ITEMS = 4
+local COMPACT_SCREEN = 0x80
+local COMPRESS_THRESHOLD = 512 -- bytes, smaller frames are not worth deflating
 
 local API_KEY_LOCATION = "/home/.APIKEY"
-local AUTH_KEY_LEN = 8
-local API_KEY_LEN = 48
-local SERVER_ADDRESS = "http://192.168.1.124:3000"
-local WS_ADDRESS = "ws://192.168.1.124"
-local WS_PORT = 3000
-local WS_PATH = "/api/ws"
+local AUTH_KEY_LEN = 8 -- the default, the server's settings may issue longer codes
+
+-- server addresses, log level and modules, refreshed from the server on every start
+local config = Config.load();
+
+-- reported to the server on every connect when set, nil leaves the server's value alone
+local CLIENT_DESCRIPTION = nil
+local CLIENT_LOCATION = nil
 
 -- ws client
 local ws = nil;
@@ -44,9 +56,12 @@ function main()
         client_initialization()
     end
     
-    local api_keyfile = filesystem.open(API_KEY_LOCATION);
-    api_key = api_keyfile:read(API_KEY_LEN);
+    -- the key length is a server setting, the whole file is the key
+    local api_keyfile = io.open(API_KEY_LOCATION, "rb");
+    api_key = api_keyfile:read("*a"):match("%w+");
+    api_keyfile:close();
     print("Keyfile was found and read successfully!");
+    config = Config.refresh(api_key);
     -- catch interrupted and disconnect websocket cleanly before exiting threads and self
     event.listen("interrupted", function()
         cleanup()
@@ -54,8 +69,28 @@ function main()
     end)
     
     display_layout(rui);
-    local ws_handle = thread.create(websocket_thread);
-    thread.waitForAll({ws_handle})
+    local handles = {thread.create(reporting_errors("Websocket thread", websocket_thread))};
+    if Config.enabled(config, "telemetry") or Config.enabled(config, "items") then
+        table.insert(handles, thread.create(reporting_errors("Telemetry thread", telemetry_thread)));
+    end
+    if Config.enabled(config, "screen") then
+        table.insert(handles, thread.create(reporting_errors("Screen thread", screen_thread)));
+    end
+    thread.waitForAll(handles)
+end
+
+-- log an error at Error level, which the server counts against a release we just updated to
+function report_error(message)
+    print(message)
+    if ws and ws:isOpen() then ws:log("Error", message) end
+end
+
+-- fn wrapped to report an error it raises before giving up
+function reporting_errors(name, fn)
+    return function(...)
+        local ok, err = xpcall(fn, debug.traceback, ...)
+        if not ok then report_error(name .. " failed: " .. tostring(err)) end
+    end
 end
 
 function display_layout(rui)
@@ -79,10 +114,13 @@ function websocket_thread()
 
     ::reconnect::
     ws = WebSocket.new({
-        address = WS_ADDRESS,
-        port = WS_PORT,
-        path = WS_PATH,
-        headers = "X-API-Key: " .. api_key
+        address = config.ws_address,
+        port = config.ws_port,
+        path = config.ws_path,
+        headers = "X-API-Key: " .. api_key,
+        log_level = config.log_level,
+        -- compressed frames need a data card to deflate them
+        protocols = component.isAvailable("data") and {"oc-compact+deflate", "oc-compact"} or {"oc-compact"}
     })
     
     while true do
@@ -90,15 +128,19 @@ function websocket_thread()
         if connected then break end
         if err then 
             print('Failed to connect: ' .. err);
-            print('Attempting reconnect after', RECONNECT_TIMEOUT, "seconds");
-            os.sleep(RECONNECT_TIMEOUT);
+            print('Attempting reconnect after', config.reconnect_timeout, "seconds");
+            os.sleep(config.reconnect_timeout);
             ws:close();
             goto reconnect;
         end
         os.sleep(1);
     end
 
+    ws:hello()
     ws:print("Websocket connected!");
+    if CLIENT_DESCRIPTION or CLIENT_LOCATION then
+        ws:send("Metadata " .. util.to_json({description = CLIENT_DESCRIPTION, location = CLIENT_LOCATION}))
+    end
 
     -- Read incoming messages
     while true do
@@ -111,8 +153,12 @@ function websocket_thread()
         end
         if messageType == WebSocket.MESSAGE_TYPES.TEXT then
             ws:print("Command received: "..message)
-            local command_fn = commands[message];
-            if command_fn then command_fn() end
+            local command, args = util.split_on_space(message);
+            local command_fn = commands[command];
+            if command_fn then
+                local ok, err = pcall(command_fn, ws, args)
+                if not ok then report_error("Command " .. command .. " failed: " .. tostring(err)) end
+            end
 
         elseif messageType == WebSocket.MESSAGE_TYPES.PING then
             ws:pong(message)
@@ -124,11 +170,139 @@ function websocket_thread()
     end
 end
 
+-- wrap {type, body} messages in a compact binary frame, deflated when negotiated and worth it
+function compact_frame(messages)
+    local parts = {}
+    for _, message in ipairs(messages) do
+        table.insert(parts, string.pack(">s4", string.char(message[1]) .. message[2]))
+    end
+    local body = table.concat(parts)
+    if ws.protocol == "oc-compact+deflate" and #body >= COMPRESS_THRESHOLD then
+        return string.char(1) .. component.data.deflate(body)
+    end
+    return string.char(0) .. body
+end
+
+-- "Power <json>" from the first attached energy device, or the computer itself
+local last_stored = nil
+function report_power()
+    local stored, capacity
+    if component.isAvailable("energy_device") then
+        stored = component.energy_device.getEnergyStored()
+        capacity = component.energy_device.getMaxEnergyStored()
+    else
+        stored, capacity = computer.energy(), computer.maxEnergy()
+    end
+    local draw = last_stored and (last_stored - stored) / TELEMETRY_INTERVAL or 0
+    last_stored = stored
+    ws:send("Power " .. util.to_json({stored = stored, capacity = capacity, draw = draw}))
+end
+
+-- "Items <json>" with the contents of an attached ME network, as a compact frame when negotiated
+function report_items()
+    if not component.isAvailable("me_interface") then return end
+    local items = {}
+    for _, stack in ipairs(component.me_interface.getItemsInNetwork()) do
+        table.insert(items, {
+            name = stack.name,
+            label = stack.label,
+            size = stack.size,
+            isCraftable = stack.isCraftable
+        })
+    end
+    if #items == 0 then return end
+    if ws.protocol then
+        ws:sendBinary(compact_frame({{COMPACT_ITEMS, "\1" .. util.to_json(items)}}))
+    else
+        ws:send("Items " .. util.to_json(items))
+    end
+end
+
+function telemetry_thread()
+    while true do
+        if ws and ws:isOpen() then
+            if Config.enabled(config, "telemetry") then report_power() end
+            if Config.enabled(config, "items") then report_items() end
+        end
+        os.sleep(TELEMETRY_INTERVAL);
+    end
+end
+
+-- cells of the last mirrored screen, nil sends a full snapshot next
+local last_screen = nil
+
+-- the server asks for a snapshot when it has nothing to apply our diffs to
+commands["Screen"] = function(ws, args) last_screen = nil end
+
+local function send_screen(frame)
+    if ws.protocol then frame = compact_frame({{COMPACT_SCREEN, frame}}) end
+    return ws:sendBinary(frame)
+end
+
+local function pack_cell(cell)
+    return string.pack(">I2I3I3", utf8.codepoint(cell[1]), cell[2], cell[3])
+end
+
+-- send the screen as a binary snapshot, or only the runs of cells that changed since the last one
+function report_screen()
+    local width, height = gpu.getResolution()
+    local cells = {}
+    for y = 1, height do
+        for x = 1, width do
+            local char, fg, bg = gpu.get(x, y)
+            cells[(y - 1) * width + x] = {char, fg, bg}
+        end
+    end
+
+    local previous = last_screen
+    if previous and (previous.width ~= width or previous.height ~= height) then previous = nil end
+    last_screen = {width = width, height = height, cells = cells}
+
+    local header = string.pack(">BI2I2", previous and SCREEN_DIFF or SCREEN_SNAPSHOT, width, height)
+    if not previous then
+        local frame = {header}
+        for i = 1, width * height do table.insert(frame, pack_cell(cells[i])) end
+        return send_screen(table.concat(frame))
+    end
+
+    local function same(a, b) return a[1] == b[1] and a[2] == b[2] and a[3] == b[3] end
+    local runs = {}
+    for y = 1, height do
+        local x = 1
+        while x <= width do
+            local i = (y - 1) * width + x
+            if same(cells[i], previous.cells[i]) then
+                x = x + 1
+            else
+                local run = {}
+                local start = x
+                while x <= width and not same(cells[(y - 1) * width + x], previous.cells[(y - 1) * width + x]) do
+                    table.insert(run, pack_cell(cells[(y - 1) * width + x]))
+                    x = x + 1
+                end
+                table.insert(runs, string.pack(">I2I2I2", start - 1, y - 1, #run) .. table.concat(run))
+            end
+        end
+    end
+    if #runs > 0 then
+        send_screen(header .. string.pack(">I2", #runs) .. table.concat(runs))
+    end
+end
+
+function screen_thread()
+    while true do
+        if ws and ws:isOpen() then
+            report_screen()
+        end
+        os.sleep(SCREEN_INTERVAL);
+    end
+end
+
 function client_initialization()
     print("Create an authorization key and enter it here: ")
     result = term.read()
     
-    if #result ~= AUTH_KEY_LEN+1 then
+    if #result < AUTH_KEY_LEN+1 then
         print("Authorization key shorter than expected")
         -- goto loop
     end
@@ -137,7 +311,7 @@ function client_initialization()
     print("Sending request")
     local req_body = string.format([[{"authcode": "%s"}]], auth_code)
 
-    local response = internet.request(SERVER_ADDRESS.."/api/authenticate", 
+    local response = internet.request(config.server_url.."/api/authenticate", 
         req_body, {["Content-Type"] = "application/json"}, "POST");
     
     local result = ""
@@ -149,5 +323,5 @@ function client_initialization()
     return "Successfully connected to server and obtained an API key!"
 end
 
-main()
+reporting_errors("Client", main)()
 
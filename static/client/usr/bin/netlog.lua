@@ -3,19 +3,19 @@
 local WebSocket = require("ws");
 local filesystem = require("filesystem");
 local event = require("event");
+local Config = require("config");
 
-local API_KEY_LEN = 48
 local API_KEY_LOCATION = "/home/.APIKEY"
-local WS_ADDRESS = "ws://192.168.1.124"
-local WS_PORT = 3000
-local WS_PATH = "/api/ws"
+
+local config = Config.load();
 
 if not filesystem.exists(API_KEY_LOCATION) then
     client_initialization()
 end
 
-local api_keyfile = filesystem.open(API_KEY_LOCATION);
-local api_key = api_keyfile:read(API_KEY_LEN);
+local api_keyfile = io.open(API_KEY_LOCATION, "rb");
+local api_key = api_keyfile:read("*a"):match("%w+");
+api_keyfile:close();
 
 event.listen("interrupted", function()
     ws:close();
@@ -28,9 +28,9 @@ end)
 
 ::reconnect::
 ws = WebSocket.new({
-    address = WS_ADDRESS,
-    port = WS_PORT,
-    path = WS_PATH,
+    address = config.ws_address,
+    port = config.ws_port,
+    path = config.ws_path,
     headers = "X-API-Key: " .. api_key
 })
 
@@ -38,13 +38,14 @@ while true do
     local connected, err = ws:finishConnect()
     if connected then break end
     if err then
-        print("Could not connect! Reconnecting in 30 seconds!");
+        print("Could not connect! Reconnecting in " .. config.reconnect_timeout .. " seconds!");
         ws:close();
-        os.sleep(30);
+        os.sleep(config.reconnect_timeout);
         goto reconnect;
     end
     os.sleep(1);
 end
+ws:hello();
 
 while true do
     if not ws:isOpen() then
@@ -1,37 +1,102 @@
 local internet = require("internet");
 local filesystem = require("filesystem");
+local component = require("component");
 local util = require("util");
+local Config = require("config");
 
-local CLIENT_UPDATE_URL = "http://192.168.1.124:3000/static/client"
-local UPDATE_MANIFEST_URL = CLIENT_UPDATE_URL .. "/MANIFEST"
+local args = {...}
 
-local function get_update_urls()
-    local req_handle = internet.request(UPDATE_MANIFEST_URL)
+local SERVER_ADDRESS = Config.load().server_url
+-- the server names the release to install in `Update`, otherwise take its embedded bundle
+local MANIFEST_PATH = (args[1] ~= nil and args[1] ~= "") and args[1] or "/api/manifest"
+local MANIFEST_URL = SERVER_ADDRESS .. MANIFEST_PATH
+local MANIFEST_LOCATION = "/etc/client.manifest" -- last installed manifest, the app reports its build
+
+local function fetch(url)
+    local req_handle = internet.request(url)
     local result = ""
     for chunk in req_handle do result = result..chunk end
-    return util.split_on_newl(result);
+    return result
 end
 
-local function updateFile(url, filename)
-    local req_handle = internet.request(url);
-    local result = "";
-    for chunk in req_handle do result = result..chunk end;
+local function read_file(path)
+    local file = io.open(path, "rb")
+    if file == nil then return nil end
+    local contents = file:read("*a")
+    file:close()
+    return contents
+end
 
-    local new_file = filesystem.open(filename, "w");
-    if new_file == nil then
-        error("Error creating output file " .. filename);
+local function write_file(path, contents)
+    local file = filesystem.open(path, "w")
+    if file == nil then
+        error("Error creating output file " .. path)
     end
+    if not file:write(contents) then
+        file:close()
+        error("Failed to write file " .. path .. "!")
+    end
+    file:close()
+end
 
-    if not new_file:write(result) then
-        error("Failed to update file"..filename.."!")
+-- hex sha256 of some data, nil without a data card to compute it
+local function sha256(data)
+    if not component.isAvailable("data") then return nil end
+    return (component.data.sha256(data):gsub(".", function(c) return string.format("%02x", c:byte()) end))
+end
+
+-- download a file of the manifest, checking it before replacing the installed one
+local function install(file)
+    local contents = fetch(SERVER_ADDRESS .. file.url)
+    if #contents ~= file.size then
+        error("Size mismatch for " .. file.path .. ", expected " .. file.size .. " got " .. #contents)
+    end
+    local hash = sha256(contents)
+    if hash and hash ~= file.sha256 then
+        error("Checksum mismatch for " .. file.path)
     end
+
+    filesystem.makeDirectory(filesystem.path(file.path))
+    local staged = file.path .. ".new"
+    write_file(staged, contents)
+    filesystem.remove(file.path)
+    filesystem.rename(staged, file.path)
+end
+
+-- sha256 of every file as of the last update, keyed by path
+local function installed_files()
+    local installed = {}
+    local previous = read_file(MANIFEST_LOCATION)
+    if previous == nil then return installed end
+    local ok, manifest = pcall(util.from_json, previous)
+    if not ok then return installed end
+    for _, file in ipairs(manifest.files) do installed[file.path] = file.sha256 end
+    return installed
 end
 
 local function main()
-    local updates = get_update_urls()
-    for idx, file in ipairs(updates) do
-        updateFile(CLIENT_UPDATE_URL.."/"..file, file)
+    local raw = fetch(MANIFEST_URL)
+    local manifest = util.from_json(raw)
+    local installed = installed_files()
+
+    local updated = 0
+    for _, file in ipairs(manifest.files) do
+        if installed[file.path] ~= file.sha256 or not filesystem.exists(file.path) then
+            print("Updating " .. file.path)
+            install(file)
+            updated = updated + 1
+        end
+        installed[file.path] = nil
+    end
+    -- whatever is left was dropped from the bundle
+    for path in pairs(installed) do
+        print("Removing " .. path)
+        filesystem.remove(path)
     end
+
+    write_file(MANIFEST_LOCATION, raw)
+    print(string.format("Updated %d of %d files to %s (build %s)", updated, #manifest.files,
+        manifest.version, manifest.build:sub(1, 12)))
 end
 
 main()
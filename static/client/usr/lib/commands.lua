@@ -1,34 +1,212 @@
 local event = require("event");
+local component = require("component");
+local thread = require("thread");
+local filesystem = require("filesystem");
+local computer = require("computer");
+local util = require("util");
+
+local API_KEY_LOCATION = "/home/.APIKEY"
+
+-- seconds between polls of a running craft
+local CRAFT_POLL_INTERVAL = 5
 
 
 -- run some info functions and send them back to the server
-local function cmd_info()
+local function cmd_info(ws, args)
     
 end
 
--- execute update script and download new files from server
-local function cmd_update()
+-- execute update script and download new files from server, args being the path of the
+-- release manifest to install, the server's own bundle when empty
+local function cmd_update(ws, args)
     print("Launching updater");
-    os.execute("updater");
+    local ok, reason = os.execute("updater " .. (args or ""));
+    if not ok then
+        -- keep running the installed files, the server sends the update again later
+        ws:log("Error", "Update failed: " .. tostring(reason));
+        return
+    end
     print("Throwing an interruption before rebooting for update.");
     event.push("interrupted");
     os.execute("reboot");
 end
 
 -- response to a previous command issued. Second token contains the name of original command
-local function cmd_response()
+local function cmd_response(ws, args)
+
+end
+
+-- request a craft from the attached ME network, args are "<job id> <item name> <amount>".
+-- progress is reported back as "Response Craft <job id> <status> [message]"
+local function cmd_craft(ws, args)
+    local job_id, item_name, amount = string.match(args, "^(%d+) (%S+) (%d+)$")
+    if not job_id then return end
+    local function respond(status, message)
+        ws:send("Response Craft " .. job_id .. " " .. status .. (message and (" " .. message) or ""))
+    end
+
+    if not component.isAvailable("me_interface") then
+        return respond("Failed", "No ME interface attached")
+    end
+    local craftables = component.me_interface.getCraftables({name = item_name})
+    if not craftables or #craftables == 0 then
+        return respond("Failed", "No crafting pattern for " .. item_name)
+    end
+
+    local status = craftables[1].request(tonumber(amount))
+    respond("Accepted")
+    thread.create(function()
+        while not status.isDone() and not status.isCanceled() do
+            os.sleep(CRAFT_POLL_INTERVAL)
+        end
+        if status.isCanceled() then respond("Failed", "Craft was canceled") else respond("Done") end
+    end):detach()
+end
 
+-- replace the stored api key with the one issued by the server, then reboot to reconnect with it
+local function cmd_rekey(ws, args)
+    if #args == 0 then return end
+    local keyfile = filesystem.open(API_KEY_LOCATION, "w")
+    keyfile:write(args)
+    keyfile:close()
+    print("API key rotated, rebooting to reconnect.");
+    event.push("interrupted");
+    os.execute("reboot");
+end
+
+-- run a Lua snippet from the server, args are "<execution id> <code>". print output is captured
+-- and the result reported as "Response Exec <execution id> <json>"
+local function cmd_exec(ws, args)
+    local execution_id, code = string.match(args, "^(%d+) (.*)$")
+    if not execution_id then return end
+
+    local output = {}
+    local env = setmetatable({
+        print = function(...)
+            local parts = table.pack(...)
+            for i = 1, parts.n do parts[i] = tostring(parts[i]) end
+            table.insert(output, table.concat(parts, "\t", 1, parts.n) .. "\n")
+        end,
+    }, {__index = _G})
+
+    local started = computer.uptime()
+    local chunk, err = load(code, "=exec", "t", env)
+    local response = {ok = false}
+    if chunk then
+        local results = table.pack(pcall(chunk))
+        if results[1] then
+            local values = {}
+            for i = 2, results.n do table.insert(values, tostring(results[i])) end
+            response.ok = true
+            response.result = table.concat(values, "\t")
+        else
+            response.error = tostring(results[2])
+        end
+    else
+        response.error = err
+    end
+    response.output = table.concat(output)
+    response.duration_ms = math.floor((computer.uptime() - started) * 1000)
+
+    ws:send("Response Exec " .. execution_id .. " " .. util.to_json(response))
+end
+
+-- open remote shell sessions by id, each holding the line being typed
+local shell_sessions = {}
+
+local function shell_output(ws, session_id, text)
+    ws:send("Shell " .. session_id .. " output " .. text)
+end
+
+local function shell_prompt(ws, session_id)
+    shell_output(ws, session_id, require("shell").getWorkingDirectory() .. " # ")
+end
+
+-- run a line typed in the browser, anything it writes to stdout is streamed back
+local function shell_run(ws, session_id, line)
+    local stream = {}
+    function stream:write(...)
+        local parts = table.pack(...)
+        for i = 1, parts.n do shell_output(ws, session_id, tostring(parts[i])) end
+        return self
+    end
+    function stream:close() return true end
+
+    local previous = io.output()
+    io.output(stream)
+    local ok, err = pcall(require("shell").execute, line)
+    io.output(previous)
+    if not ok then shell_output(ws, session_id, tostring(err) .. "\n") end
+end
+
+-- remote shell, args are "<session id> open", "<session id> input <keys>" or "<session id> close".
+-- input is echoed back and run a line at a time
+local function cmd_shell(ws, args)
+    local session_id, operation, data = string.match(args, "^(%d+) (%a+) ?(.*)$")
+    if not session_id then return end
+
+    if operation == "open" then
+        shell_sessions[session_id] = ""
+        shell_output(ws, session_id, "Connected to " .. computer.address() .. "\n")
+        shell_prompt(ws, session_id)
+    elseif operation == "close" then
+        shell_sessions[session_id] = nil
+        ws:send("Shell " .. session_id .. " closed")
+    elseif operation == "input" and shell_sessions[session_id] then
+        for key in string.gmatch(data, ".") do
+            local line = shell_sessions[session_id]
+            if key == "\r" or key == "\n" then
+                shell_output(ws, session_id, "\n")
+                shell_sessions[session_id] = ""
+                if line == "exit" then
+                    shell_sessions[session_id] = nil
+                    ws:send("Shell " .. session_id .. " closed")
+                    return
+                end
+                if #line > 0 then shell_run(ws, session_id, line) end
+                shell_prompt(ws, session_id)
+            elseif key == "\127" or key == "\b" then
+                if #line > 0 then
+                    shell_sessions[session_id] = string.sub(line, 1, -2)
+                    shell_output(ws, session_id, "\b \b")
+                end
+            elseif string.byte(key) >= 32 then
+                shell_sessions[session_id] = line .. key
+                shell_output(ws, session_id, key)
+            end
+        end
+    end
 end
 
 local Commands = {
     ["Info"]     = cmd_info,
     ["Update"]   = cmd_update,
     ["Response"] = cmd_response,
+    ["Craft"]    = cmd_craft,
+    ["Rekey"]    = cmd_rekey,
+    ["Exec"]     = cmd_exec,
+    ["Shell"]    = cmd_shell,
 }
 
+-- ids of queued commands already run, the server resends any whose ack it did not get
+local handled_queue_ids = {}
 
+-- a command from the server's outbound queue, args are "<queue id> <command> [args]".
+-- acked before running since some commands reboot the computer
+local function cmd_queued(ws, args)
+    local queue_id, command, command_args = string.match(args, "^(%d+) (%S+) ?(.*)$")
+    if not queue_id then return end
+    ws:send("Ack " .. queue_id)
+    if handled_queue_ids[queue_id] then return end
+    handled_queue_ids[queue_id] = true
 
+    local command_fn = Commands[command]
+    if command_fn then command_fn(ws, command_args) end
+end
 
-return Commands;
+Commands["Queued"] = cmd_queued
 
 
+
+
+return Commands;
@@ -0,0 +1,76 @@
+-- client configuration generated by the server, see its client_config module
+local filesystem = require("filesystem");
+local internet = require("internet");
+
+local CONFIG_LOCATION = "/etc/client.cfg"
+
+-- only used until the server wrote a configuration, by clients installed before it did
+local DEFAULTS = {
+    server_url = "http://192.168.1.124:3000",
+    ws_address = "ws://192.168.1.124",
+    ws_port = 3000,
+    ws_path = "/api/ws",
+    reconnect_timeout = 30,
+    log_level = "Info",
+    modules = {"telemetry", "items", "screen"}
+}
+
+local Config = {}
+
+-- a configuration chunk as a table, nil if it is not one
+local function parse(contents)
+    local chunk = load(contents, "=config", "t", {})
+    if chunk == nil then return nil end
+    local ok, config = pcall(chunk)
+    if not ok or type(config) ~= "table" then return nil end
+    return config
+end
+
+-- the saved configuration, with the defaults for anything it lacks
+function Config.load()
+    local saved = nil
+    local file = io.open(CONFIG_LOCATION, "rb")
+    if file ~= nil then
+        saved = parse(file:read("*a"))
+        file:close()
+    end
+    saved = saved or {}
+
+    local config = {}
+    for key, value in pairs(DEFAULTS) do
+        if saved[key] == nil then config[key] = value else config[key] = saved[key] end
+    end
+    return config
+end
+
+-- download and save the configuration of the client with this api key, then load it. The saved
+-- configuration stays when the server cannot be reached.
+function Config.refresh(api_key)
+    local server_url = Config.load().server_url
+    local ok, contents = pcall(function()
+        local handle = internet.request(server_url .. "/api/config", nil, {["X-API-Key"] = api_key})
+        local result = ""
+        for chunk in handle do result = result .. chunk end
+        return result
+    end)
+    if ok and parse(contents) then
+        local file = filesystem.open(CONFIG_LOCATION, "w")
+        if file then
+            file:write(contents)
+            file:close()
+        end
+    else
+        print("Could not refresh the configuration, using the saved one")
+    end
+    return Config.load()
+end
+
+-- whether a module is enabled, see MODULES in the server's client_config
+function Config.enabled(config, module)
+    for _, name in ipairs(config.modules) do
+        if name == module then return true end
+    end
+    return false
+end
+
+return Config
@@ -22,4 +22,130 @@ function Util.split_on_space(str)
     end
 end
 
+local ARRAY = {}
+
+-- mark a table as a json array, so it is encoded as [] rather than {} while empty
+function Util.array(value)
+    return setmetatable(value or {}, ARRAY)
+end
+
+-- minimal json encoder for the tables we report (strings, numbers, booleans and nested tables).
+-- tables with a [1] entry or marked with Util.array are encoded as arrays, all others as objects
+function Util.to_json(value)
+    local value_type = type(value)
+    if value_type == "string" then
+        return '"' .. value:gsub('[%c"\\]', function(c)
+            return string.format("\\u%04x", string.byte(c))
+        end) .. '"'
+    elseif value_type == "number" or value_type == "boolean" then
+        return tostring(value)
+    elseif value_type == "table" then
+        local parts = {}
+        if value[1] ~= nil or getmetatable(value) == ARRAY then
+            for _, item in ipairs(value) do table.insert(parts, Util.to_json(item)) end
+            return "[" .. table.concat(parts, ",") .. "]"
+        end
+        for key, item in pairs(value) do
+            table.insert(parts, Util.to_json(tostring(key)) .. ":" .. Util.to_json(item))
+        end
+        return "{" .. table.concat(parts, ",") .. "}"
+    end
+    return "null"
+end
+
+local JSON_ESCAPES = {b = "\b", f = "\f", n = "\n", r = "\r", t = "\t"}
+
+-- minimal json decoder for what the server sends us, objects and arrays become tables and null nil
+function Util.from_json(text)
+    local pos = 1
+    local function skip()
+        pos = text:find("[^ \t\r\n]", pos) or #text + 1
+    end
+    local function fail(expected)
+        error(string.format("Invalid json at %d, expected %s", pos, expected))
+    end
+    local function expect(char)
+        skip()
+        if text:sub(pos, pos) ~= char then fail(char) end
+        pos = pos + 1
+    end
+
+    local function read_string()
+        expect('"')
+        local parts = {}
+        while true do
+            local stop = text:find('["\\]', pos)
+            if not stop then fail('"') end
+            table.insert(parts, text:sub(pos, stop - 1))
+            pos = stop + 1
+            if text:sub(stop, stop) == '"' then return table.concat(parts) end
+            local escape = text:sub(pos, pos)
+            if escape == "u" then
+                table.insert(parts, utf8.char(tonumber(text:sub(pos + 1, pos + 4), 16)))
+                pos = pos + 5
+            else
+                table.insert(parts, JSON_ESCAPES[escape] or escape)
+                pos = pos + 1
+            end
+        end
+    end
+
+    local read_value
+    local function read_list(close, read_item)
+        pos = pos + 1
+        skip()
+        if text:sub(pos, pos) == close then
+            pos = pos + 1
+            return
+        end
+        while true do
+            read_item()
+            skip()
+            local separator = text:sub(pos, pos)
+            pos = pos + 1
+            if separator == close then return end
+            if separator ~= "," then fail("," .. close) end
+        end
+    end
+
+    function read_value()
+        skip()
+        local char = text:sub(pos, pos)
+        if char == "{" then
+            local object = {}
+            read_list("}", function()
+                local key = read_string()
+                expect(":")
+                object[key] = read_value()
+            end)
+            return object
+        elseif char == "[" then
+            local array, count = {}, 0
+            read_list("]", function()
+                count = count + 1
+                array[count] = read_value()
+            end)
+            return array
+        elseif char == '"' then
+            return read_string()
+        end
+        for word, value in pairs({["true"] = true, ["false"] = false}) do
+            if text:sub(pos, pos + #word - 1) == word then
+                pos = pos + #word
+                return value
+            end
+        end
+        if text:sub(pos, pos + 3) == "null" then
+            pos = pos + 4
+            return nil
+        end
+        local number = text:match("^-?%d+%.?%d*[eE]?[+-]?%d*", pos)
+        if not number then fail("a value") end
+        pos = pos + #number
+        return tonumber(number)
+    end
+
+    return read_value()
+end
+
 return Util;
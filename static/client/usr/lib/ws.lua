@@ -1,5 +1,8 @@
 local io = require("io");
 local bit32 = require('bit32')
+local component = require("component");
+local computer = require("computer");
+local util = require("util");
 -- local bdebug = require('bdebug')
 
 ---@class WebSocket
@@ -46,6 +49,9 @@ local MESSAGE_TYPES = {
 }
 WebSocket.MESSAGE_TYPES = MESSAGE_TYPES
 
+-- log levels the server understands, lowest first
+local LOG_LEVELS = { Debug = 1, Info = 2, Warn = 3, Error = 4 }
+
 ---@return WebSocket
 function WebSocket.new(socketOptions)
 	local socket = {}
@@ -57,6 +63,9 @@ function WebSocket.new(socketOptions)
 	socket.port = socketOptions.port or 80
 	socket.path = socketOptions.path or '/'
     socket.headers = socketOptions.headers or {}
+	socket.protocols = socketOptions.protocols -- subprotocols to offer, most preferred first
+	socket.protocol = nil -- the one the server picked
+	socket.log_level = socketOptions.log_level or 'Debug' -- logs below it are not sent
 	socket.key = socket.generateWebSocketKey()
 	socket.connection = socket.internet.connect(socket.address, socket.port)
 	socket.readyState = READY_STATES.CONNECTING
@@ -90,6 +99,12 @@ function WebSocket:send(message)
 	return self.connection.write(self:createWebSocketFrame({ opcode = OPCODES.TEXT, payload = message}))
 end
 
+function WebSocket:sendBinary(data)
+	if not self:isOpen() then return end
+
+	return self.connection.write(self:createWebSocketFrame({ opcode = OPCODES.BINARY, payload = data}))
+end
+
 function WebSocket:ping(data)
 	return self.connection.write(self:createWebSocketFrame({ opcode = OPCODES.PING, payload = data }))
 end
@@ -182,6 +197,9 @@ function WebSocket:performHandshake()
 	request = request .. "Connection: Upgrade\r\n"
 	request = request .. "Sec-WebSocket-Key: " .. self.key .. "\r\n"
     request = request .. self.headers .. "\r\n"       -- my addition
+	if self.protocols then
+		request = request .. "Sec-WebSocket-Protocol: " .. table.concat(self.protocols, ", ") .. "\r\n"
+	end
 	request = request .. "Sec-WebSocket-Version: 13\r\n\r\n"
 
 	local written, err = self.connection.write(request)
@@ -218,9 +236,17 @@ function WebSocket:performHandshake()
 		self:close()
 		return false, 'Server doesn\'t support Websocket'
 	end
-	if (handshakeHeaders['sec-websocket-protocol'] ~= 'chat' and handshakeHeaders['sec-websocket-protocol'] ~= nil) then
-		self:close()
-		return false, 'Server doesn\'t support "chat" protocol'
+	local protocol = handshakeHeaders['sec-websocket-protocol']
+	if protocol ~= nil and protocol ~= 'chat' then
+		local offered = false
+		for _, candidate in ipairs(self.protocols or {}) do
+			if candidate == protocol then offered = true end
+		end
+		if not offered then
+			self:close()
+			return false, 'Server picked unoffered protocol "' .. protocol .. '"'
+		end
+		self.protocol = protocol
 	end
 
 	return true
@@ -300,9 +326,39 @@ function WebSocket:readWebSocketFrame()
 end
 
 function WebSocket:print(...)
+	self:log("Info", ...);
+end
+
+-- log with a level tag the server understands: "Debug", "Info", "Warn" or "Error"
+function WebSocket:log(level, ...)
+	if (LOG_LEVELS[level] or LOG_LEVELS.Info) < (LOG_LEVELS[self.log_level] or LOG_LEVELS.Debug) then return end
 	local args = {...};
-	local combined = table.concat(args, " ")
-	self:send("Log " .. combined);
+	self:send("Log [" .. level .. "] " .. table.concat(args, " "));
+end
+
+local PROTOCOL_VERSION = 1 -- has to be one the server supports, see its handshake module
+local MANIFEST_LOCATION = "/etc/client.manifest" -- saved by the updater, its build is reported
+
+-- build of the manifest the updater last installed, nil before the first update
+local function client_build()
+	local file = io.open(MANIFEST_LOCATION, "rb")
+	if file == nil then return nil end
+	local ok, manifest = pcall(util.from_json, file:read("*a"))
+	file:close()
+	return ok and manifest.build or nil
+end
+
+-- "Hello <json>" has to be the first message of every connection
+function WebSocket:hello()
+	local components = util.array()
+	for _, name in component.list() do table.insert(components, name) end
+	self:send("Hello " .. util.to_json({
+		protocol = PROTOCOL_VERSION,
+		build = client_build(),
+		components = components,
+		free_memory = computer.freeMemory(),
+		total_memory = computer.totalMemory()
+	}))
 end
 
 ---@return string
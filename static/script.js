@@ -4,4 +4,37 @@ document.body.addEventListener('htmx:responseError', function(event) {
     if (event.detail.xhr.status === 404) {
         alert('This page does not exist. The feature may not be implemented yet.');
     }
-});
\ No newline at end of file
+});
+
+// remote shell terminals, [data-shell] holds the websocket path of a live session and
+// [data-shell-recording] the recorded events of one to replay
+htmx.onLoad(function(content) {
+    content.querySelectorAll('[data-shell]').forEach(function(element) {
+        const terminal = new Terminal({convertEol: true});
+        terminal.open(element);
+        const scheme = location.protocol === 'https:' ? 'wss://' : 'ws://';
+        const socket = new WebSocket(scheme + location.host + element.dataset.shell);
+        socket.onmessage = function(event) { terminal.write(event.data); };
+        socket.onclose = function() { terminal.write('\r\n[session closed]\r\n'); };
+        terminal.onData(function(keys) {
+            if (socket.readyState === WebSocket.OPEN) socket.send(keys);
+        });
+        // close the session when navigating away from the terminal
+        document.body.addEventListener('htmx:beforeSwap', function closeShell(event) {
+            if (event.detail.target.contains(element)) {
+                socket.close();
+                document.body.removeEventListener('htmx:beforeSwap', closeShell);
+            }
+        });
+    });
+
+    content.querySelectorAll('[data-shell-recording]').forEach(function(element) {
+        const terminal = new Terminal({convertEol: true, disableStdin: true});
+        terminal.open(element);
+        JSON.parse(element.dataset.shellRecording)
+            .filter(function(event) { return event.direction === 'output'; })
+            .forEach(function(event) {
+                setTimeout(function() { terminal.write(event.data); }, event.offset_ms);
+            });
+    });
+});
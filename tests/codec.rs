@@ -0,0 +1,184 @@
+use std::io::Write;
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use proptest::prelude::*;
+use site::codec::{Codec, WireMessage, COMPRESSED, SCREEN_FRAME};
+use site::routes::api::ClientWSCommand;
+use site::screen::{Cell, Run, ScreenFrame, DIFF, MAX_HEIGHT, MAX_WIDTH, SNAPSHOT};
+
+/// Bodies app.lua leaves uncompressed.
+const COMPRESS_THRESHOLD: usize = 512;
+
+/// Frames as the client builds them in app.lua, see `compact_frame` and `send_screen`.
+fn client_frame(compression: bool, messages: &[WireMessage]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for message in messages {
+        let encoded = encode_message(message);
+        body.extend_from_slice(&(encoded.len() as u32).to_be_bytes());
+        body.extend_from_slice(&encoded);
+    }
+    if compression && body.len() >= COMPRESS_THRESHOLD {
+        let mut encoder = ZlibEncoder::new(vec![COMPRESSED], Compression::default());
+        encoder.write_all(&body).unwrap();
+        return encoder.finish().unwrap();
+    }
+    let mut frame = vec![0];
+    frame.extend_from_slice(&body);
+    frame
+}
+
+fn encode_message(message: &WireMessage) -> Vec<u8> {
+    let mut out = Vec::new();
+    match message {
+        WireMessage::Client(command) => {
+            let (tag, payload) = match command {
+                ClientWSCommand::Log(payload) => (1, payload),
+                ClientWSCommand::Response(payload) => (2, payload),
+                ClientWSCommand::Discord(payload) => (3, payload),
+                ClientWSCommand::Items(payload) => (4, payload),
+                ClientWSCommand::Power(payload) => (5, payload),
+                ClientWSCommand::Metadata(payload) => (6, payload),
+                ClientWSCommand::Ack(payload) => (7, payload),
+                ClientWSCommand::Shell(payload) => (8, payload),
+            };
+            out.push(tag);
+            match payload {
+                Some(payload) => {
+                    out.push(1);
+                    out.extend_from_slice(payload.as_bytes());
+                }
+                None => out.push(0),
+            }
+        }
+        WireMessage::Screen(frame) => {
+            out.push(SCREEN_FRAME);
+            out.extend_from_slice(&encode_screen(frame));
+        }
+    }
+    out
+}
+
+fn put_cell(out: &mut Vec<u8>, cell: &Cell) {
+    out.extend_from_slice(&(cell.character as u32 as u16).to_be_bytes());
+    out.extend_from_slice(&cell.foreground.to_be_bytes()[1..]);
+    out.extend_from_slice(&cell.background.to_be_bytes()[1..]);
+}
+
+fn encode_screen(frame: &ScreenFrame) -> Vec<u8> {
+    let mut out = Vec::new();
+    match frame {
+        ScreenFrame::Snapshot { width, height, cells } => {
+            out.push(SNAPSHOT);
+            out.extend_from_slice(&width.to_be_bytes());
+            out.extend_from_slice(&height.to_be_bytes());
+            cells.iter().for_each(|cell| put_cell(&mut out, cell));
+        }
+        ScreenFrame::Diff { width, height, runs } => {
+            out.push(DIFF);
+            out.extend_from_slice(&width.to_be_bytes());
+            out.extend_from_slice(&height.to_be_bytes());
+            out.extend_from_slice(&(runs.len() as u16).to_be_bytes());
+            for run in runs {
+                out.extend_from_slice(&run.x.to_be_bytes());
+                out.extend_from_slice(&run.y.to_be_bytes());
+                out.extend_from_slice(&(run.cells.len() as u16).to_be_bytes());
+                run.cells.iter().for_each(|cell| put_cell(&mut out, cell));
+            }
+        }
+    }
+    out
+}
+
+fn payload() -> impl Strategy<Value = Option<String>> {
+    prop::option::of(".*")
+}
+
+fn client_command() -> impl Strategy<Value = ClientWSCommand> {
+    prop_oneof![
+        payload().prop_map(ClientWSCommand::Log),
+        payload().prop_map(ClientWSCommand::Response),
+        payload().prop_map(ClientWSCommand::Discord),
+        payload().prop_map(ClientWSCommand::Items),
+        payload().prop_map(ClientWSCommand::Power),
+        payload().prop_map(ClientWSCommand::Metadata),
+        payload().prop_map(ClientWSCommand::Ack),
+        payload().prop_map(ClientWSCommand::Shell),
+    ]
+}
+
+fn cell() -> impl Strategy<Value = Cell> {
+    // screen cells carry BMP code points and 24 bit colors
+    (prop::char::range('\0', '\u{FFFF}'), 0..0x100_0000u32, 0..0x100_0000u32)
+        .prop_map(|(character, foreground, background)| Cell { character, foreground, background })
+}
+
+fn screen_frame() -> impl Strategy<Value = ScreenFrame> {
+    (1..=MAX_WIDTH, 1..=MAX_HEIGHT).prop_flat_map(|(width, height)| {
+        let snapshot = prop::collection::vec(cell(), width as usize * height as usize)
+            .prop_map(move |cells| ScreenFrame::Snapshot { width, height, cells });
+        let run = (0..width, 0..height)
+            .prop_flat_map(move |(x, y)| {
+                prop::collection::vec(cell(), 0..=(width - x) as usize).prop_map(move |cells| Run { x, y, cells })
+            });
+        let diff = prop::collection::vec(run, 0..8)
+            .prop_map(move |runs| ScreenFrame::Diff { width, height, runs });
+        prop_oneof![snapshot, diff]
+    })
+}
+
+fn wire_message() -> impl Strategy<Value = WireMessage> {
+    prop_oneof![
+        client_command().prop_map(WireMessage::Client),
+        screen_frame().prop_map(WireMessage::Screen),
+    ]
+}
+
+fn codec() -> impl Strategy<Value = Codec> {
+    any::<bool>().prop_map(|compression| Codec { compact: true, compression })
+}
+
+proptest! {
+    #[test]
+    fn client_frames_decode(codec in codec(), messages in prop::collection::vec(wire_message(), 1..4)) {
+        let frame = client_frame(codec.compression, &messages);
+        prop_assert_eq!(codec.decode(&frame).unwrap(), messages);
+    }
+
+    #[test]
+    fn large_payloads_decode(codec in codec(), snapshot in "[a-z\"{}:,0-9]{512,8192}") {
+        let messages = vec![WireMessage::Client(ClientWSCommand::Items(Some(snapshot)))];
+        let frame = client_frame(codec.compression, &messages);
+        prop_assert_eq!(codec.decode(&frame).unwrap(), messages);
+    }
+
+    #[test]
+    fn garbage_is_rejected_without_panicking(codec in codec(), frame in prop::collection::vec(any::<u8>(), 0..256)) {
+        let _ = codec.decode(&frame);
+    }
+}
+
+#[test]
+fn compressed_frames_need_negotiation() {
+    let messages = vec![WireMessage::Client(ClientWSCommand::Items(Some("x".repeat(4096))))];
+    let frame = client_frame(true, &messages);
+    assert!(frame.len() < 4096);
+    assert!(Codec { compact: true, compression: false }.decode(&frame).is_err());
+    assert!(Codec::default().decode(&frame).is_err());
+}
+
+#[test]
+fn unknown_message_types_are_rejected() {
+    let codec = Codec { compact: true, compression: false };
+    // only client commands and screen frames travel in compact frames
+    for tag in [0, 9, 0x40, 0x7f] {
+        assert!(codec.decode(&[0, 0, 0, 0, 2, tag, 0]).is_err(), "type {} was accepted", tag);
+    }
+}
+
+#[test]
+fn protocols_negotiate_codecs() {
+    assert_eq!(Codec::negotiate(Some("oc-compact+deflate")), Codec { compact: true, compression: true });
+    assert_eq!(Codec::negotiate(Some("oc-compact")), Codec { compact: true, compression: false });
+    assert_eq!(Codec::negotiate(Some("chat")), Codec::default());
+    assert_eq!(Codec::negotiate(None), Codec::default());
+}